@@ -0,0 +1,267 @@
+//! `--append`. Parquet files can not be extended in place, since their footer is at the end. So the
+//! row groups of the existing output are copied into a temporary file next to it, followed by the
+//! row groups of the current export. The temporary file replaces the output once it is complete,
+//! so readers never see a partially written file.
+
+use std::{
+    ffi::OsString,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Error};
+use parquet::{
+    file::{
+        metadata::KeyValue,
+        reader::{FileReader, SerializedFileReader},
+        writer::FileWriter,
+    },
+    schema::{printer::print_schema, types::Type},
+};
+
+use crate::filter::{read_chunks, write_chunks};
+
+/// Output file of a previous run, the current export is appended to.
+pub struct ExistingFile {
+    path: PathBuf,
+    reader: SerializedFileReader<File>,
+}
+
+impl ExistingFile {
+    /// `None` if there is no file at `path` yet, so the export starts a new one.
+    pub fn open(path: &Path) -> Result<Option<Self>, Error> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let reader = SerializedFileReader::new(file).map_err(|error| {
+            Error::from(error).context(format!(
+                "Can not append to '{}', since it is not a readable parquet file.",
+                path.display()
+            ))
+        })?;
+        Ok(Some(ExistingFile {
+            path: path.to_owned(),
+            reader,
+        }))
+    }
+
+    /// Fails, unless the file has exactly the columns of `schema`. Names the first column which
+    /// differs.
+    pub fn check_schema(&self, schema: &Type) -> Result<(), Error> {
+        let existing = self.reader.metadata().file_metadata().schema().get_fields();
+        let new = schema.get_fields();
+        for (existing, new) in existing.iter().zip(new) {
+            if existing == new {
+                continue;
+            }
+            let (existing_text, new_text) = (describe(existing), describe(new));
+            if existing_text == new_text {
+                bail!(
+                    "Can not append to '{}'. Column '{}' has a different field id than in the \
+                    query.",
+                    self.path.display(),
+                    existing.name()
+                )
+            }
+            bail!(
+                "Can not append to '{}'. Column '{}' is '{}' in the file, but '{}' in the query.",
+                self.path.display(),
+                existing.name(),
+                existing_text,
+                new_text
+            )
+        }
+        if existing.len() != new.len() {
+            bail!(
+                "Can not append to '{}'. It has {} columns, but the query yields {}.",
+                self.path.display(),
+                existing.len(),
+                new.len()
+            )
+        }
+        Ok(())
+    }
+
+    pub fn num_rows(&self) -> u64 {
+        self.reader.metadata().file_metadata().num_rows() as u64
+    }
+
+    /// Key value metadata of the existing file, updated with the entries of the current export.
+    /// Entries with the same key are replaced, so they describe the latest run.
+    pub fn merge_key_value_metadata(&self, new: Option<Vec<KeyValue>>) -> Option<Vec<KeyValue>> {
+        let existing = self
+            .reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .clone();
+        let (mut merged, new) = match (existing, new) {
+            (existing, None) => return existing,
+            (None, new) => return new,
+            (Some(existing), Some(new)) => (existing, new),
+        };
+        for entry in new {
+            match merged.iter_mut().find(|existing| existing.key == entry.key) {
+                Some(existing) => *existing = entry,
+                None => merged.push(entry),
+            }
+        }
+        Some(merged)
+    }
+
+    /// Writes all rows of the existing file into `writer`, keeping its row groups.
+    pub fn copy_row_groups(&self, writer: &mut dyn FileWriter) -> Result<(), Error> {
+        for index in 0..self.reader.num_row_groups() {
+            let chunks = read_chunks(self.reader.get_row_group(index)?.as_ref())?;
+            let num_rows = self.reader.metadata().row_group(index).num_rows() as usize;
+            write_chunks(writer, &chunks, &vec![true; num_rows])?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of a file while it is written, e.g. `out.par.tmp` for `out.par`. In the same directory, so
+/// renaming it once complete is atomic.
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    name.into()
+}
+
+/// Schema line of a single column, e.g. `OPTIONAL INT64 id;`.
+fn describe(field: &Type) -> String {
+    let mut text = Vec::new();
+    print_schema(&mut text, field);
+    String::from_utf8_lossy(&text).trim().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, sync::Arc};
+
+    use parquet::{
+        column::writer::ColumnWriter,
+        file::{
+            metadata::KeyValue,
+            properties::WriterProperties,
+            reader::{FileReader, SerializedFileReader},
+            writer::{FileWriter, SerializedFileWriter},
+        },
+        schema::{parser::parse_message_type, types::Type},
+    };
+    use tempfile::tempdir;
+
+    use super::ExistingFile;
+
+    fn schema(message: &str) -> Arc<Type> {
+        Arc::new(parse_message_type(message).unwrap())
+    }
+
+    fn entry(key: &str, value: &str) -> KeyValue {
+        KeyValue::new(key.to_owned(), value.to_owned())
+    }
+
+    /// Writes one row group per element of `row_groups` into a file with a single required
+    /// `INT64` column named `a`.
+    fn write(writer: &mut dyn FileWriter, row_groups: &[&[i64]]) {
+        for values in row_groups {
+            let mut row_group_writer = writer.next_row_group().unwrap();
+            let mut column_writer = row_group_writer.next_column().unwrap().unwrap();
+            if let ColumnWriter::Int64ColumnWriter(cw) = &mut column_writer {
+                cw.write_batch(values, None, None).unwrap();
+            }
+            row_group_writer.close_column(column_writer).unwrap();
+            writer.close_row_group(row_group_writer).unwrap();
+        }
+    }
+
+    #[test]
+    fn append_to_existing_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.par");
+        let schema = schema("message schema { REQUIRED INT64 a; }");
+        let metadata = vec![entry("origin", "first"), entry("kept", "yes")];
+        let properties = WriterProperties::builder()
+            .set_key_value_metadata(Some(metadata))
+            .build();
+        let mut writer = SerializedFileWriter::new(
+            File::create(&path).unwrap(),
+            schema.clone(),
+            Arc::new(properties),
+        )
+        .unwrap();
+        write(&mut writer, &[&[1, 2], &[3]]);
+        writer.close().unwrap();
+
+        let existing = ExistingFile::open(&path).unwrap().unwrap();
+        assert_eq!(3, existing.num_rows());
+        existing.check_schema(&schema).unwrap();
+        let merged = existing
+            .merge_key_value_metadata(Some(vec![entry("origin", "second"), entry("added", "yes")]));
+        assert_eq!(
+            Some(vec![
+                entry("origin", "second"),
+                entry("kept", "yes"),
+                entry("added", "yes")
+            ]),
+            merged
+        );
+
+        let copy_path = dir.path().join("copy.par");
+        let properties = Arc::new(WriterProperties::builder().build());
+        let mut writer =
+            SerializedFileWriter::new(File::create(&copy_path).unwrap(), schema, properties)
+                .unwrap();
+        existing.copy_row_groups(&mut writer).unwrap();
+        write(&mut writer, &[&[4]]);
+        writer.close().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&copy_path).unwrap()).unwrap();
+        let num_rows: Vec<_> = reader
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|row_group| row_group.num_rows())
+            .collect();
+        assert_eq!(vec![2, 1, 1], num_rows);
+    }
+
+    #[test]
+    fn mismatching_schema() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.par");
+        let properties = Arc::new(WriterProperties::builder().build());
+        let existing_schema = schema("message schema { REQUIRED INT64 a; OPTIONAL INT32 b; }");
+        let mut writer =
+            SerializedFileWriter::new(File::create(&path).unwrap(), existing_schema, properties)
+                .unwrap();
+        writer.close().unwrap();
+        let existing = ExistingFile::open(&path).unwrap().unwrap();
+
+        let error = existing
+            .check_schema(&schema(
+                "message schema { REQUIRED INT64 a; OPTIONAL INT64 b; }",
+            ))
+            .unwrap_err();
+        assert!(
+            error.to_string().ends_with(
+                "Column 'b' is 'OPTIONAL INT32 b;' in the file, but 'OPTIONAL INT64 b;' in the \
+                query."
+            ),
+            "{}",
+            error
+        );
+        let error = existing
+            .check_schema(&schema("message schema { REQUIRED INT64 a; }"))
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("It has 2 columns, but the query yields 1."));
+        assert!(ExistingFile::open(&dir.path().join("missing.par"))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,196 @@
+//! Memory needed by the ODBC buffers bound for fetching. These are allocated up front for a whole
+//! batch, so a single wide text column may make an export fail before the first row is fetched.
+
+use std::cmp::Reverse;
+
+use anyhow::{bail, Error};
+use log::{debug, info};
+
+use crate::{column_mapping::ColumnMapping, estimate::format_bytes};
+
+/// Rows per batch, if neither `--batch-size` nor `--batch-memory` is specified.
+const DEFAULT_BATCH_SIZE: u32 = 100_000;
+
+/// Number of rows fetched at once.
+///
+/// # Parameters
+///
+/// * `batch_size`: Set by `--batch-size`. Upper bound for the number of rows.
+/// * `batch_memory`: Set by `--batch-memory`. Upper bound for the bytes of all buffers.
+/// * `mappings`: Mapping of every bound column. Ignored columns must not be passed.
+pub fn batch_size(
+    batch_size: Option<u32>,
+    batch_memory: Option<u64>,
+    mappings: &[ColumnMapping],
+) -> Result<u32, Error> {
+    let batch_memory = match batch_memory {
+        Some(batch_memory) => batch_memory,
+        None => return Ok(batch_size.unwrap_or(DEFAULT_BATCH_SIZE)),
+    };
+    let bytes_per_row: u64 = mappings
+        .iter()
+        .map(|mapping| mapping.bytes_per_row() as u64)
+        .sum();
+    let num_rows = batch_memory / bytes_per_row.max(1);
+    if num_rows == 0 {
+        bail!(
+            "--batch-memory of {} does not fit a single row, which takes {}.",
+            format_bytes(batch_memory),
+            format_bytes(bytes_per_row)
+        )
+    }
+    let num_rows = num_rows.min(u32::MAX as u64) as u32;
+    info!(
+        "Rows take {} each, so {} rows fit into --batch-memory of {}.",
+        format_bytes(bytes_per_row),
+        num_rows,
+        format_bytes(batch_memory)
+    );
+    Ok(batch_size.map_or(num_rows, |batch_size| batch_size.min(num_rows)))
+}
+
+/// Number of columns named in the error message, if the buffers do not fit.
+const NUM_WORST_OFFENDERS: usize = 3;
+
+/// Bytes of buffer memory needed for each bound column.
+pub struct BufferBudget {
+    batch_size: u32,
+    /// Name and bytes of each column, largest first.
+    columns: Vec<(String, u64)>,
+    total: u64,
+}
+
+impl BufferBudget {
+    /// # Parameters
+    ///
+    /// * `mappings`: Mapping of every bound column. Ignored columns must not be passed.
+    pub fn new(mappings: &[ColumnMapping], batch_size: u32) -> Self {
+        let mut columns: Vec<_> = mappings
+            .iter()
+            .map(|mapping| {
+                let bytes = mapping.bytes_per_row() as u64 * batch_size as u64;
+                (mapping.name.clone(), bytes)
+            })
+            .collect();
+        columns.sort_by_key(|(_, bytes)| Reverse(*bytes));
+        let total = columns.iter().map(|(_, bytes)| bytes).sum();
+        BufferBudget {
+            batch_size,
+            columns,
+            total,
+        }
+    }
+
+    /// Logs the size of the buffers, and fails if their total exceeds `limit`.
+    pub fn check(&self, limit: Option<u64>) -> Result<(), Error> {
+        for (name, bytes) in &self.columns {
+            debug!(
+                "Buffer of column '{}' takes {} for {} rows.",
+                name,
+                format_bytes(*bytes),
+                self.batch_size
+            );
+        }
+        info!("Buffers take {} in total.", format_bytes(self.total));
+        let limit = match limit {
+            Some(limit) if self.total > limit => limit,
+            _ => return Ok(()),
+        };
+        let worst_offenders: Vec<_> = self
+            .columns
+            .iter()
+            .take(NUM_WORST_OFFENDERS)
+            .map(|(name, bytes)| format!("'{}' ({})", name, format_bytes(*bytes)))
+            .collect();
+        bail!(
+            "Buffers for {} rows would take {}, but only {} are available. Largest columns: {}. \
+            Use a smaller --batch-size, or shorten wide text columns in the query, e.g. with \
+            CAST or LEFT.",
+            self.batch_size,
+            format_bytes(self.total),
+            format_bytes(limit),
+            worst_offenders.join(", ")
+        )
+    }
+}
+
+/// Memory available to new processes according to the operating system, if known.
+#[cfg(target_os = "linux")]
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    meminfo_available(&meminfo)
+}
+
+/// Memory available to new processes according to the operating system, if known.
+#[cfg(not(target_os = "linux"))]
+pub fn available_memory() -> Option<u64> {
+    None
+}
+
+/// Parses the `MemAvailable` line of `/proc/meminfo`, which is stated in KiB.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn meminfo_available(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use odbc_api::DataType;
+
+    use super::{batch_size, meminfo_available, BufferBudget};
+    use crate::fake::{FakeColumn, FakeResultSet};
+
+    /// `VARCHAR(1000)`, bound to a text buffer of 4000 bytes.
+    fn comment() -> FakeColumn {
+        FakeColumn::text("comment", &[]).with_data_type(DataType::Varchar { length: 1000 })
+    }
+
+    #[test]
+    fn fixed_and_variable_width_columns() {
+        let mappings = FakeResultSet::new(vec![
+            FakeColumn::i64("id", &[]),
+            comment(),
+            FakeColumn::bit("flag", &[]),
+        ])
+        .mappings();
+        let budget = BufferBudget::new(&mappings, 1000);
+        // Values plus one indicator each.
+        assert_eq!((8 + 8 + 4001 + 8 + 1 + 8) * 1000, budget.total);
+
+        assert!(budget.check(None).is_ok());
+        assert!(budget.check(Some(budget.total)).is_ok());
+        let message = budget.check(Some(1024 * 1024)).unwrap_err().to_string();
+        assert!(
+            message.contains("Largest columns: 'comment' (3.8 MiB), 'id' (15.6 KiB), 'flag'"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn batch_size_from_memory() {
+        let mappings = FakeResultSet::new(vec![FakeColumn::i64("id", &[]), comment()]).mappings();
+        // Values plus one indicator each.
+        let bytes_per_row = 8 + 8 + 4001 + 8;
+        assert_eq!(100_000, batch_size(None, None, &mappings).unwrap());
+        assert_eq!(10, batch_size(Some(10), None, &mappings).unwrap());
+        assert_eq!(260, batch_size(None, Some(1 << 20), &mappings).unwrap());
+        assert_eq!(10, batch_size(Some(10), Some(1 << 20), &mappings).unwrap());
+        assert_eq!(
+            260,
+            batch_size(Some(1000), Some(1 << 20), &mappings).unwrap()
+        );
+        assert!(batch_size(None, Some(bytes_per_row - 1), &mappings).is_err());
+    }
+
+    #[test]
+    fn parse_meminfo() {
+        let meminfo = "MemTotal:       16314104 kB\nMemAvailable:    8000000 kB\n";
+        assert_eq!(Some(8_192_000_000), meminfo_available(meminfo));
+        assert_eq!(None, meminfo_available("MemTotal:       16314104 kB\n"));
+    }
+}
@@ -0,0 +1,594 @@
+use std::{convert::TryInto, fmt, mem::size_of};
+
+use anyhow::{bail, Error};
+use odbc_api::{
+    buffers::BufferKind,
+    sys::{Date, SqlDataType, Time, Timestamp},
+    ColumnDescription, Cursor, DataType, Nullability,
+};
+use parquet::{
+    basic::{LogicalType, Type as PhysicalType},
+    schema::types::{PrimitiveTypeBuilder, Type},
+};
+
+use crate::timestamp::TimestampPrecision;
+
+/// Type SQL Server reports for `TIME` columns, instead of the standard `TIME`.
+const SQL_SS_TIME2: SqlDataType = SqlDataType(-154);
+
+/// Type of `UNIQUEIDENTIFIER` and other GUID columns.
+const SQL_GUID: SqlDataType = SqlDataType::EXT_GUID;
+
+/// Fixed size `BINARY(n)`.
+const SQL_BINARY: SqlDataType = SqlDataType::EXT_BINARY;
+/// Variable sized binary types, e.g. `VARBINARY(n)`.
+const SQL_VARBINARY: &[SqlDataType] = &[
+    SqlDataType::EXT_VAR_BINARY,
+    SqlDataType::EXT_LONG_VAR_BINARY,
+];
+
+/// The parts of a result set needed to decide how its columns are fetched and written. Implemented
+/// by every cursor, and by in-memory result sets in tests.
+pub trait DescribeColumns {
+    fn num_result_cols(&self) -> Result<i16, odbc_api::Error>;
+
+    /// Name, type and nullability of the column at the one based `index`.
+    fn describe_col(&self, index: u16, cd: &mut ColumnDescription) -> Result<(), odbc_api::Error>;
+
+    /// Maximum number of characters needed to display the values of the column.
+    fn col_display_size(&self, index: u16) -> Result<isize, odbc_api::Error>;
+}
+
+impl<C> DescribeColumns for C
+where
+    C: Cursor,
+{
+    fn num_result_cols(&self) -> Result<i16, odbc_api::Error> {
+        Cursor::num_result_cols(self)
+    }
+
+    fn describe_col(&self, index: u16, cd: &mut ColumnDescription) -> Result<(), odbc_api::Error> {
+        Cursor::describe_col(self, index, cd)
+    }
+
+    fn col_display_size(&self, index: u16) -> Result<isize, odbc_api::Error> {
+        Cursor::col_display_size(self, index)
+    }
+}
+
+/// Describes how a column of the result set is fetched from the data source and how it is
+/// represented in parquet.
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    /// One based index of the column in the result set.
+    pub index: u16,
+    pub name: String,
+    /// SQL type as reported by the driver.
+    pub data_type: DataType,
+    /// Nullability as reported by the driver.
+    pub nullability: Nullability,
+    /// Kind of the ODBC buffer bound to the column.
+    pub buffer_kind: BufferKind,
+    pub physical_type: PhysicalType,
+    pub logical_type: LogicalType,
+    /// Length in bytes of `FIXED_LEN_BYTE_ARRAY` columns.
+    pub length: Option<i32>,
+    /// Precision and scale of `DECIMAL` columns.
+    pub decimal: Option<(i32, i32)>,
+    /// Fractional second digits of `TIME` columns, which are fetched as text and written as
+    /// `TIME_MILLIS` or `TIME_MICROS`. Reported by the driver, unless overridden.
+    pub time_precision: Option<i16>,
+}
+
+impl ColumnMapping {
+    /// Describe the column at the one based `index` of the result set and decide how to fetch and
+    /// write it.
+    pub fn new(cursor: &impl DescribeColumns, index: u16) -> Result<Self, Error> {
+        let mut cd = ColumnDescription::default();
+        // Reserving helps with drivers not reporting column name size correctly.
+        cd.name.reserve(128);
+        cursor.describe_col(index, &mut cd)?;
+
+        let name = cd.name_to_string()?;
+        // Give a generated name, should we fail to retrieve one from the ODBC data source.
+        let name = if name.is_empty() {
+            format!("Column{}", index)
+        } else {
+            name
+        };
+
+        let mut length = None;
+        let mut decimal = None;
+        let mut time_precision = None;
+        let (physical_type, logical_type, buffer_kind) = match cd.data_type {
+            DataType::Double => (PhysicalType::DOUBLE, LogicalType::NONE, BufferKind::F64),
+            DataType::Float | DataType::Real => {
+                (PhysicalType::FLOAT, LogicalType::NONE, BufferKind::F32)
+            }
+            DataType::SmallInt => (PhysicalType::INT32, LogicalType::INT_16, BufferKind::I32),
+            DataType::Integer => (PhysicalType::INT32, LogicalType::INT_32, BufferKind::I32),
+            DataType::Date => (PhysicalType::INT32, LogicalType::DATE, BufferKind::Date),
+            DataType::Decimal {
+                scale: 0,
+                precision: p @ 0..=9,
+            }
+            | DataType::Numeric {
+                scale: 0,
+                precision: p @ 0..=9,
+            } => {
+                decimal = Some((p as i32, 0));
+                (PhysicalType::INT32, LogicalType::DECIMAL, BufferKind::I32)
+            }
+            DataType::Decimal {
+                scale: 0,
+                precision: p @ 0..=18,
+            }
+            | DataType::Numeric {
+                scale: 0,
+                precision: p @ 0..=18,
+            } => {
+                decimal = Some((p as i32, 0));
+                (PhysicalType::INT64, LogicalType::DECIMAL, BufferKind::I64)
+            }
+            DataType::Numeric { scale, precision } | DataType::Decimal { scale, precision } => {
+                // Length of the two's complement.
+                let num_binary_digits = precision as f64 * 10f64.log2();
+                // Plus one bit for the sign (+/-)
+                let length_in_bits = num_binary_digits + 1.0;
+                length = Some((length_in_bits / 8.0).ceil() as i32);
+                decimal = Some((precision.try_into().unwrap(), scale.into()));
+                (
+                    PhysicalType::FIXED_LEN_BYTE_ARRAY,
+                    LogicalType::DECIMAL,
+                    BufferKind::Text {
+                        max_str_len: cd.data_type.column_size(),
+                    },
+                )
+            }
+            DataType::Timestamp { precision: 0..=3 } => (
+                PhysicalType::INT64,
+                LogicalType::TIMESTAMP_MILLIS,
+                BufferKind::Timestamp,
+            ),
+            DataType::Timestamp { .. } => (
+                PhysicalType::INT64,
+                LogicalType::TIMESTAMP_MICROS,
+                BufferKind::Timestamp,
+            ),
+            DataType::Bigint => (PhysicalType::INT64, LogicalType::INT_64, BufferKind::I64),
+            DataType::Bit => (PhysicalType::BOOLEAN, LogicalType::NONE, BufferKind::Bit),
+            DataType::Tinyint => (PhysicalType::INT32, LogicalType::INT_8, BufferKind::I32),
+            DataType::Time { precision }
+            | DataType::Other {
+                data_type: SQL_SS_TIME2,
+                decimal_digits: precision,
+                ..
+            } => {
+                // The ODBC time struct has no fractional seconds, so we parse the text instead.
+                time_precision = Some(precision);
+                let (physical_type, logical_type) = time_types(precision);
+                (
+                    physical_type,
+                    logical_type,
+                    BufferKind::Text {
+                        max_str_len: text_buffer_len(cursor, index, &cd.data_type)?,
+                    },
+                )
+            }
+            // ODBC has no binary buffers in the version of `odbc-api` used, so binary data is
+            // fetched as text, which drivers represent as two hexadecimal digits per byte.
+            DataType::Other {
+                data_type: SQL_BINARY,
+                column_size,
+                ..
+            } if column_size != 0 => {
+                length = Some(column_size.try_into()?);
+                (
+                    PhysicalType::FIXED_LEN_BYTE_ARRAY,
+                    LogicalType::NONE,
+                    BufferKind::Text {
+                        max_str_len: 2 * column_size,
+                    },
+                )
+            }
+            DataType::Other { data_type, .. } if is_binary(data_type) => (
+                PhysicalType::BYTE_ARRAY,
+                LogicalType::NONE,
+                BufferKind::Text {
+                    max_str_len: text_buffer_len(cursor, index, &cd.data_type)?,
+                },
+            ),
+            DataType::Char { .. }
+            | DataType::Varchar { .. }
+            | DataType::WVarchar { .. }
+            | DataType::Unknown
+            | DataType::Other { .. } => (
+                PhysicalType::BYTE_ARRAY,
+                LogicalType::UTF8,
+                BufferKind::Text {
+                    max_str_len: text_buffer_len(cursor, index, &cd.data_type)?,
+                },
+            ),
+        };
+
+        Ok(ColumnMapping {
+            index,
+            name,
+            data_type: cd.data_type,
+            nullability: cd.nullability,
+            buffer_kind,
+            physical_type,
+            logical_type,
+            length,
+            decimal,
+            time_precision,
+        })
+    }
+
+    /// Write a `TIME` column with `digits` fractional second digits, rather than those reported
+    /// by the driver. Fails for columns of any other type.
+    pub fn with_time_precision(self, digits: i16) -> Result<Self, Error> {
+        if self.time_precision.is_none() {
+            bail!(
+                "Column '{}' specified in --time-precision must be an unmasked time.",
+                self.name
+            );
+        }
+        let (physical_type, logical_type) = time_types(digits);
+        // The text buffer must hold all the digits, rather than only the reported ones.
+        let buffer_kind = match self.buffer_kind {
+            BufferKind::Text { max_str_len } => BufferKind::Text {
+                max_str_len: max_str_len.max(time_text_len(digits)),
+            },
+            other => other,
+        };
+        Ok(ColumnMapping {
+            buffer_kind,
+            physical_type,
+            logical_type,
+            time_precision: Some(digits),
+            ..self
+        })
+    }
+
+    /// Write a timestamp column with `precision`, rather than the one derived from the digits
+    /// reported by the driver. Other columns are returned unchanged.
+    pub fn with_timestamp_precision(self, precision: TimestampPrecision) -> Self {
+        if !matches!(self.buffer_kind, BufferKind::Timestamp) {
+            return self;
+        }
+        ColumnMapping {
+            logical_type: precision.logical_type(),
+            ..self
+        }
+    }
+
+    /// `true` if the column holds GUIDs, which are written as 16 bytes rather than as text.
+    pub fn is_guid_bytes(&self) -> bool {
+        matches!(self.data_type, DataType::Other { data_type, .. } if data_type == SQL_GUID)
+            && self.physical_type == PhysicalType::FIXED_LEN_BYTE_ARRAY
+    }
+
+    /// `true` if the column holds binary data, which is fetched as hexadecimal text and written as
+    /// bytes.
+    pub fn is_binary(&self) -> bool {
+        matches!(self.data_type, DataType::Other { data_type, .. } if is_binary(data_type))
+            && self.logical_type != LogicalType::UTF8
+    }
+
+    /// Write a GUID column as `FIXED_LEN_BYTE_ARRAY(16)` in the byte order of RFC 4122, rather
+    /// than as text. The values are still fetched as text, which is in that order already, unlike
+    /// the binary layout of SQL Server. Other columns are returned unchanged.
+    pub fn with_guid_as_bytes(self) -> Self {
+        if !matches!(self.data_type, DataType::Other { data_type, .. } if data_type == SQL_GUID) {
+            return self;
+        }
+        ColumnMapping {
+            physical_type: PhysicalType::FIXED_LEN_BYTE_ARRAY,
+            logical_type: LogicalType::NONE,
+            length: Some(16),
+            ..self
+        }
+    }
+
+    /// Fetch the column as text and write it as UTF-8, independent of its type. E.g. because it is
+    /// masked.
+    pub fn into_text(self, cursor: &impl DescribeColumns) -> Result<Self, Error> {
+        Ok(ColumnMapping {
+            buffer_kind: BufferKind::Text {
+                max_str_len: text_buffer_len(cursor, self.index, &self.data_type)?,
+            },
+            physical_type: PhysicalType::BYTE_ARRAY,
+            logical_type: LogicalType::UTF8,
+            length: None,
+            decimal: None,
+            time_precision: None,
+            ..self
+        })
+    }
+
+    /// Builder for the parquet field the column is written to. Repetition is left to the caller.
+    pub fn field_builder(&self) -> PrimitiveTypeBuilder<'_> {
+        let mut builder = Type::primitive_type_builder(&self.name, self.physical_type)
+            .with_logical_type(self.logical_type);
+        if let Some(length) = self.length {
+            builder = builder.with_length(length);
+        }
+        if let Some((precision, scale)) = self.decimal {
+            builder = builder.with_precision(precision).with_scale(scale);
+        }
+        builder
+    }
+
+    /// Memory required for each row in the bound ODBC buffer, including the indicator.
+    pub fn bytes_per_row(&self) -> usize {
+        let value = match self.buffer_kind {
+            // One extra byte for the terminating zero.
+            BufferKind::Text { max_str_len } => max_str_len + 1,
+            BufferKind::F64 => size_of::<f64>(),
+            BufferKind::F32 => size_of::<f32>(),
+            BufferKind::Date => size_of::<Date>(),
+            BufferKind::Time => size_of::<Time>(),
+            BufferKind::Timestamp => size_of::<Timestamp>(),
+            BufferKind::I8 | BufferKind::U8 | BufferKind::Bit => 1,
+            BufferKind::I16 => size_of::<i16>(),
+            BufferKind::I32 => size_of::<i32>(),
+            BufferKind::I64 => size_of::<i64>(),
+        };
+        value + size_of::<isize>()
+    }
+}
+
+impl fmt::Display for ColumnMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Column {} '{}': SQL {:?} (size {}, decimal digits {}, {:?}) -> ODBC buffer {:?} ({} \
+            bytes per row) -> parquet {}",
+            self.index,
+            self.name,
+            self.data_type,
+            self.data_type.column_size(),
+            self.data_type.decimal_digits(),
+            self.nullability,
+            self.buffer_kind,
+            self.bytes_per_row(),
+            self.physical_type,
+        )?;
+        if self.logical_type != LogicalType::NONE {
+            write!(f, " {}", self.logical_type)?;
+        }
+        if let Some(length) = self.length {
+            write!(f, " length {}", length)?;
+        }
+        if let Some((precision, scale)) = self.decimal {
+            write!(f, " precision {} scale {}", precision, scale)?;
+        }
+        if let Some(digits) = self.time_precision {
+            write!(f, " from {} fractional digits", digits)?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of the column at the one based `index`. Falls back to a generated name, should the driver
+/// fail to describe the column.
+pub fn column_name(cursor: &impl DescribeColumns, index: u16) -> String {
+    let mut cd = ColumnDescription::default();
+    match cursor
+        .describe_col(index, &mut cd)
+        .map(|()| cd.name_to_string())
+    {
+        Ok(Ok(name)) if !name.is_empty() => name,
+        _ => format!("Column{}", index),
+    }
+}
+
+/// Milliseconds fit into 32 Bit, anything more precise is written as microseconds. Parquet 3 lacks
+/// a logical type for nanoseconds, so further digits are truncated.
+fn time_types(precision: i16) -> (PhysicalType, LogicalType) {
+    if precision <= 3 {
+        (PhysicalType::INT32, LogicalType::TIME_MILLIS)
+    } else {
+        (PhysicalType::INT64, LogicalType::TIME_MICROS)
+    }
+}
+
+fn is_binary(data_type: SqlDataType) -> bool {
+    data_type == SQL_BINARY || SQL_VARBINARY.contains(&data_type)
+}
+
+/// Length of `hh:mm:ss.fffffff` with `precision` fractional digits.
+fn time_text_len(precision: i16) -> usize {
+    DataType::Time { precision }.utf8_len().unwrap_or_default()
+}
+
+/// Maximum length of a text buffer able to hold the string representation of the column.
+fn text_buffer_len(
+    cursor: &impl DescribeColumns,
+    index: u16,
+    data_type: &DataType,
+) -> Result<usize, Error> {
+    let max_str_len = if let Some(len) = data_type.utf8_len() {
+        len
+    } else {
+        cursor.col_display_size(index)? as usize
+    };
+    Ok(max_str_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use odbc_api::{buffers::BufferKind, sys::SqlDataType, DataType};
+    use parquet::basic::{LogicalType, Type as PhysicalType};
+
+    use super::ColumnMapping;
+    use crate::{
+        fake::{FakeColumn, FakeResultSet},
+        timestamp::TimestampPrecision,
+    };
+
+    fn mapping(column: FakeColumn) -> ColumnMapping {
+        ColumnMapping::new(&FakeResultSet::new(vec![column]), 1).unwrap()
+    }
+
+    /// Maximum string length of the bound text buffer. `None` for other buffers.
+    fn max_str_len(mapping: &ColumnMapping) -> Option<usize> {
+        match mapping.buffer_kind {
+            BufferKind::Text { max_str_len } => Some(max_str_len),
+            _ => None,
+        }
+    }
+
+    /// Column of an SQL type unknown to `odbc-api`, which reports it as `Other`.
+    fn other(data_type: SqlDataType, column_size: usize, display_size: isize) -> FakeColumn {
+        FakeColumn::text("a", &[])
+            .with_data_type(DataType::Other {
+                data_type,
+                column_size,
+                decimal_digits: 0,
+            })
+            .with_display_size(display_size)
+    }
+
+    #[test]
+    fn binary() {
+        let mapping = mapping(other(SqlDataType::EXT_BINARY, 16, 32));
+        assert_eq!(PhysicalType::FIXED_LEN_BYTE_ARRAY, mapping.physical_type);
+        assert_eq!(LogicalType::NONE, mapping.logical_type);
+        assert_eq!(Some(16), mapping.length);
+        // Two hexadecimal digits per byte.
+        assert_eq!(Some(32), max_str_len(&mapping));
+        assert!(mapping.is_binary());
+    }
+
+    #[test]
+    fn varbinary() {
+        for data_type in [
+            SqlDataType::EXT_VAR_BINARY,
+            SqlDataType::EXT_LONG_VAR_BINARY,
+        ] {
+            let mapping = mapping(other(data_type, 256, 512));
+            assert_eq!(PhysicalType::BYTE_ARRAY, mapping.physical_type);
+            assert_eq!(LogicalType::NONE, mapping.logical_type);
+            assert_eq!(None, mapping.length);
+            assert_eq!(Some(512), max_str_len(&mapping));
+            assert!(mapping.is_binary());
+        }
+    }
+
+    #[test]
+    fn time_precision() {
+        let millis = mapping(FakeColumn::time("a", 3, &[]));
+        assert_eq!(PhysicalType::INT32, millis.physical_type);
+        assert_eq!(LogicalType::TIME_MILLIS, millis.logical_type);
+        assert_eq!(Some(3), millis.time_precision);
+
+        let micros = mapping(FakeColumn::time("a", 4, &[]));
+        assert_eq!(PhysicalType::INT64, micros.physical_type);
+        assert_eq!(LogicalType::TIME_MICROS, micros.logical_type);
+
+        // `hh:mm:ss.fffffff` must fit, even if the driver reports fewer digits.
+        let overridden = millis.with_time_precision(7).unwrap();
+        assert_eq!(LogicalType::TIME_MICROS, overridden.logical_type);
+        assert_eq!(Some(16), max_str_len(&overridden));
+
+        assert!(mapping(FakeColumn::i32("a", &[]))
+            .with_time_precision(3)
+            .is_err());
+    }
+
+    #[test]
+    fn guid_as_bytes() {
+        let guid = mapping(other(SqlDataType::EXT_GUID, 36, 36));
+        assert_eq!(PhysicalType::BYTE_ARRAY, guid.physical_type);
+        assert_eq!(LogicalType::UTF8, guid.logical_type);
+        assert!(!guid.is_guid_bytes());
+
+        let bytes = guid.with_guid_as_bytes();
+        assert_eq!(PhysicalType::FIXED_LEN_BYTE_ARRAY, bytes.physical_type);
+        assert_eq!(LogicalType::NONE, bytes.logical_type);
+        assert_eq!(Some(16), bytes.length);
+        // Still fetched as text.
+        assert_eq!(Some(36), max_str_len(&bytes));
+        assert!(bytes.is_guid_bytes());
+
+        // Other columns are left alone.
+        let text = mapping(FakeColumn::text("a", &[])).with_guid_as_bytes();
+        assert_eq!(PhysicalType::BYTE_ARRAY, text.physical_type);
+    }
+
+    #[test]
+    fn timestamp_precision() {
+        let timestamp = mapping(FakeColumn::timestamp("a", 7, &[]));
+        assert_eq!(LogicalType::TIMESTAMP_MICROS, timestamp.logical_type);
+        let nanos = timestamp.with_timestamp_precision(TimestampPrecision::Nanoseconds);
+        assert_eq!(PhysicalType::INT64, nanos.physical_type);
+        assert_eq!(LogicalType::NONE, nanos.logical_type);
+
+        let date = mapping(FakeColumn::date("a", &[]))
+            .with_timestamp_precision(TimestampPrecision::Milliseconds);
+        assert_eq!(LogicalType::DATE, date.logical_type);
+    }
+
+    #[test]
+    fn into_text() {
+        // Masked columns are fetched as text, independent of their type.
+        let into_text = |column: fn() -> FakeColumn| {
+            mapping(column())
+                .into_text(&FakeResultSet::new(vec![column()]))
+                .unwrap()
+        };
+
+        let text = into_text(|| other(SqlDataType::EXT_BINARY, 16, 32));
+        assert_eq!(PhysicalType::BYTE_ARRAY, text.physical_type);
+        assert_eq!(LogicalType::UTF8, text.logical_type);
+        assert_eq!(None, text.length);
+        assert!(!text.is_binary());
+
+        let text = into_text(|| FakeColumn::decimal("a", 10, 2, &[]));
+        assert_eq!(None, text.decimal);
+        // Digits, sign and decimal point.
+        assert_eq!(Some(12), max_str_len(&text));
+    }
+
+    #[test]
+    fn bytes_per_row() {
+        let indicator = std::mem::size_of::<isize>();
+        // Terminating zero
+        assert_eq!(
+            33 + indicator,
+            mapping(other(SqlDataType::EXT_BINARY, 16, 32)).bytes_per_row()
+        );
+        assert_eq!(
+            4 + indicator,
+            mapping(FakeColumn::i32("a", &[])).bytes_per_row()
+        );
+        assert_eq!(
+            16 + indicator,
+            mapping(FakeColumn::timestamp("a", 3, &[])).bytes_per_row()
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            "Column 1 'a': SQL Other { data_type: SqlDataType(-2), column_size: 16, \
+            decimal_digits: 0 } (size 16, decimal digits 0, Nullable) -> ODBC buffer Text { \
+            max_str_len: 32 } (41 bytes per row) -> parquet FIXED_LEN_BYTE_ARRAY length 16",
+            mapping(other(SqlDataType::EXT_BINARY, 16, 32)).to_string()
+        );
+        assert_eq!(
+            "Column 1 'a': SQL Decimal { precision: 10, scale: 2 } (size 10, decimal digits 2, \
+            Nullable) -> ODBC buffer Text { max_str_len: 10 } (19 bytes per row) -> parquet \
+            FIXED_LEN_BYTE_ARRAY DECIMAL length 5 precision 10 scale 2",
+            mapping(FakeColumn::decimal("a", 10, 2, &[])).to_string()
+        );
+        assert_eq!(
+            "Column 1 'a': SQL Time { precision: 7 } (size 0, decimal digits 7, Nullable) -> \
+            ODBC buffer Text { max_str_len: 16 } (25 bytes per row) -> parquet INT64 \
+            TIME_MICROS from 7 fractional digits",
+            mapping(FakeColumn::time("a", 7, &[])).to_string()
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::{bail, Error};
+use parquet::{basic::Compression, compression::create_codec};
+
+/// Parses the name of a compression codec given on the command line. Fails for codecs the parquet
+/// library has been built without, so this is noticed before the export starts.
+pub fn parse_compression(text: &str) -> Result<Compression, Error> {
+    let compression = match text {
+        "uncompressed" => Compression::UNCOMPRESSED,
+        "snappy" => Compression::SNAPPY,
+        "gzip" => Compression::GZIP,
+        "brotli" => Compression::BROTLI,
+        "lz4" => Compression::LZ4,
+        "zstd" => Compression::ZSTD,
+        _ => bail!(
+            "Unknown compression '{}'. Supported are `uncompressed`, `snappy`, `gzip`, `brotli`, \
+            `lz4` and `zstd`.",
+            text
+        ),
+    };
+    if create_codec(compression).is_err() {
+        bail!(
+            "Compression '{}' is not available in this build of odbc2parquet.",
+            text
+        )
+    }
+    Ok(compression)
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::basic::Compression;
+
+    use super::parse_compression;
+
+    #[test]
+    fn parse_codec_names() {
+        assert_eq!(Compression::SNAPPY, parse_compression("snappy").unwrap());
+        assert_eq!(Compression::ZSTD, parse_compression("zstd").unwrap());
+        assert_eq!(
+            Compression::UNCOMPRESSED,
+            parse_compression("uncompressed").unwrap()
+        );
+        assert!(parse_compression("lzo").is_err());
+        assert!(parse_compression("SNAPPY").is_err());
+    }
+}
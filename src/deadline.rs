@@ -0,0 +1,105 @@
+//! `--max-duration`. Stops fetching at a batch boundary, so the output is completed properly
+//! within a fixed time window, rather than being killed halfway through writing it.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{format_err, Error};
+use log::info;
+
+/// Parses durations given on the command line. Accepts a number of seconds, optionally followed
+/// by one of the units `s`, `m` or `h`. E.g. `30m`.
+pub fn parse_duration(text: &str) -> Result<Duration, Error> {
+    let invalid = || {
+        format_err!(
+            "'{}' is not a valid duration. Expected digits, optionally followed by one of the \
+            units s, m or h. E.g. `90s` or `30m`.",
+            text
+        )
+    };
+    let end_of_digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (digits, unit) = text.split_at(end_of_digits);
+    let seconds_per_unit = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let number: u64 = digits.parse().map_err(|_| invalid())?;
+    let seconds = number
+        .checked_mul(seconds_per_unit)
+        .ok_or_else(|| format_err!("'{}' exceeds the longest supported duration.", text))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Point in time by which the export must be done.
+pub struct Deadline {
+    max_duration: Duration,
+    started: Instant,
+    /// Start of the batch currently processed, if any.
+    batch_started: Option<Instant>,
+    /// Longest time fetching, converting and writing a single batch took so far.
+    longest_batch: Duration,
+}
+
+impl Deadline {
+    /// The deadline is `max_duration` from now.
+    pub fn new(max_duration: Duration) -> Self {
+        Deadline {
+            max_duration,
+            started: Instant::now(),
+            batch_started: None,
+            longest_batch: Duration::default(),
+        }
+    }
+
+    /// Call this before fetching each batch. `false` if another batch, taking as long as the
+    /// slowest one so far, would not be done before the deadline.
+    pub fn next_batch(&mut self) -> bool {
+        let now = Instant::now();
+        if let Some(batch_started) = self.batch_started {
+            self.longest_batch = self.longest_batch.max(now - batch_started);
+        }
+        let elapsed = now - self.started;
+        if elapsed + self.longest_batch >= self.max_duration {
+            info!(
+                "Stopping after {:.1}s, since the slowest batch took {:.1}s and --max-duration is \
+                {}s.",
+                elapsed.as_secs_f64(),
+                self.longest_batch.as_secs_f64(),
+                self.max_duration.as_secs()
+            );
+            return false;
+        }
+        self.batch_started = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_duration, Deadline};
+
+    #[test]
+    fn parse_durations() {
+        let secs = |text| parse_duration(text).ok().map(|d| d.as_secs());
+        assert_eq!(Some(90), secs("90"));
+        assert_eq!(Some(90), secs("90s"));
+        assert_eq!(Some(1800), secs("30m"));
+        assert_eq!(Some(7200), secs("2h"));
+        assert_eq!(None, secs(""));
+        assert_eq!(None, secs("m"));
+        assert_eq!(None, secs("30min"));
+        assert_eq!(None, secs("1h30m"));
+    }
+
+    #[test]
+    fn passed_deadline() {
+        let mut deadline = Deadline::new(Duration::from_secs(3600));
+        assert!(deadline.next_batch());
+        assert!(!Deadline::new(Duration::default()).next_batch());
+    }
+}
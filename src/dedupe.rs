@@ -0,0 +1,309 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use anyhow::{bail, Error};
+use log::info;
+use odbc_api::buffers::{AnyColumnView, BufferDescription, BufferKind, ColumnarRowSet};
+
+use crate::strict::{LossPolicy, LossyRule};
+
+/// Number of hash functions used by the bloom filter.
+const BLOOM_NUM_HASHES: u64 = 7;
+/// Size of the bloom filter in bits per key the exact set may hold. With seven hash functions,
+/// less than one percent of new keys are mistaken for seen ones, until the filter holds that many
+/// keys. The exact set takes at least 16 bytes per key, so the filter is more than ten times
+/// smaller.
+const BLOOM_BITS_PER_KEY: usize = 10;
+
+/// Drops rows, whose key has already been seen earlier during this export.
+pub struct Deduplicator {
+    /// Indices of the buffers holding the key columns.
+    key_buffers: Vec<usize>,
+    /// Names of the columns forming the key. Used in messages.
+    keys: Vec<String>,
+    seen: Seen,
+    /// Number of distinct keys the exact set may hold, before we switch to a bloom filter.
+    max_exact_keys: usize,
+    num_dropped: u64,
+    /// Reused between batches. One entry per row with the fingerprint of its key.
+    fingerprints: Vec<(DefaultHasher, DefaultHasher)>,
+    /// Decides whether switching to the bloom filter is acceptable.
+    loss_policy: LossPolicy,
+}
+
+/// Fingerprints of the keys seen so far.
+enum Seen {
+    /// 128 Bit fingerprints of the keys. Collisions are so unlikely we consider this exact.
+    Exact(HashSet<u128>),
+    /// Bounded memory, but may mistake a new key for one we have already seen.
+    Bloom(Vec<u64>),
+}
+
+impl Deduplicator {
+    /// # Parameters
+    ///
+    /// * `keys`: Names of the columns forming the key.
+    /// * `buffer_names`: Name of the result set column bound to each buffer.
+    /// * `buffers`: Description of each bound buffer.
+    /// * `max_exact_keys`: Number of distinct keys to remember exactly, before switching to a bloom
+    ///   filter.
+    /// * `loss_policy`: With `--strict` exceeding `max_exact_keys` is an error.
+    pub fn new(
+        keys: &[String],
+        buffer_names: &[String],
+        buffers: &[(u16, BufferDescription)],
+        max_exact_keys: usize,
+        loss_policy: LossPolicy,
+    ) -> Result<Self, Error> {
+        let mut key_buffers = Vec::new();
+        for key in keys {
+            let index = match buffer_names.iter().position(|name| name == key) {
+                Some(index) => index,
+                None => bail!(
+                    "Column '{}' specified in --dedupe-on is not part of the result set.",
+                    key
+                ),
+            };
+            if !matches!(
+                buffers[index].1.kind,
+                BufferKind::I32 | BufferKind::I64 | BufferKind::Text { .. } | BufferKind::Date
+            ) {
+                bail!(
+                    "Column '{}' specified in --dedupe-on has an unsupported type. Supported are \
+                    integers, text and dates.",
+                    key
+                )
+            }
+            key_buffers.push(index);
+        }
+        Ok(Self {
+            key_buffers,
+            keys: keys.to_vec(),
+            seen: Seen::Exact(HashSet::new()),
+            max_exact_keys,
+            num_dropped: 0,
+            fingerprints: Vec::new(),
+            loss_policy,
+        })
+    }
+
+    /// Deselect rows in `selection` whose key has been seen before, either in a previous batch or
+    /// earlier in this one. Rows which are not selected are ignored. Returns the number of rows
+    /// still selected. `num_batch` is only used in messages.
+    pub fn select(
+        &mut self,
+        batch: &ColumnarRowSet,
+        selection: &mut [bool],
+        num_batch: u32,
+    ) -> Result<usize, Error> {
+        let num_rows = selection.len();
+        self.fingerprints.clear();
+        // The second hasher is salted, so we get 128 Bits of independent hash values.
+        self.fingerprints.resize_with(num_rows, || {
+            let mut salted = DefaultHasher::new();
+            salted.write_u8(0xff);
+            (DefaultHasher::new(), salted)
+        });
+        for &buffer_index in &self.key_buffers {
+            let fingerprints = self.fingerprints.iter_mut();
+            match batch.column(buffer_index) {
+                AnyColumnView::NullableI32(it) => {
+                    for (item, fp) in it.zip(fingerprints) {
+                        hash_into(fp, item.map(|&i| i as i64))
+                    }
+                }
+                AnyColumnView::NullableI64(it) => {
+                    for (item, fp) in it.zip(fingerprints) {
+                        hash_into(fp, item)
+                    }
+                }
+                AnyColumnView::Text(it) => {
+                    for (item, fp) in it.zip(fingerprints) {
+                        hash_into(fp, item.map(|text| text.to_bytes()))
+                    }
+                }
+                AnyColumnView::NullableDate(it) => {
+                    for (item, fp) in it.zip(fingerprints) {
+                        hash_into(fp, item)
+                    }
+                }
+                _ => unreachable!("Key buffer kinds are checked on construction."),
+            }
+        }
+
+        let fingerprints = std::mem::take(&mut self.fingerprints);
+        let mut num_selected = 0;
+        for (row, (first, second)) in fingerprints.iter().enumerate() {
+            if !selection[row] {
+                continue;
+            }
+            let fingerprint = ((first.finish() as u128) << 64) | second.finish() as u128;
+            if self.insert(fingerprint, num_batch)? {
+                num_selected += 1;
+            } else {
+                selection[row] = false;
+                self.num_dropped += 1;
+            }
+        }
+        self.fingerprints = fingerprints;
+        Ok(num_selected)
+    }
+
+    /// Number of rows dropped so far, because their key has already been seen.
+    pub fn num_dropped(&self) -> u64 {
+        self.num_dropped
+    }
+
+    /// `true` if the fingerprint has not been seen before.
+    fn insert(&mut self, fingerprint: u128, num_batch: u32) -> Result<bool, Error> {
+        let (is_new, exceeded) = match &mut self.seen {
+            Seen::Exact(set) => (set.insert(fingerprint), set.len() > self.max_exact_keys),
+            Seen::Bloom(bits) => (bloom_insert(bits, fingerprint), false),
+        };
+        if exceeded {
+            self.switch_to_bloom(num_batch)?;
+        }
+        Ok(is_new)
+    }
+
+    fn switch_to_bloom(&mut self, num_batch: u32) -> Result<(), Error> {
+        let keys: Vec<_> = self.keys.iter().map(|key| format!("'{}'", key)).collect();
+        self.loss_policy.report(
+            LossyRule::ApproximateDeduplication,
+            &format!("key {} in batch {}", keys.join(", "), num_batch),
+            &format!(
+                "More than {} distinct keys seen. Switching to a bloom filter for deduplication. \
+                From now on rows may be dropped, even though their key is unique.",
+                self.max_exact_keys
+            ),
+        )?;
+        // At least one word, so tiny limits still yield a working filter.
+        let num_bits = (self.max_exact_keys * BLOOM_BITS_PER_KEY)
+            .next_power_of_two()
+            .max(64);
+        let mut bits = vec![0u64; num_bits / 64];
+        if let Seen::Exact(set) = &self.seen {
+            for &fingerprint in set {
+                bloom_insert(&mut bits, fingerprint);
+            }
+        }
+        info!(
+            "Bloom filter for deduplication uses {} bytes.",
+            bits.len() * 8
+        );
+        self.seen = Seen::Bloom(bits);
+        Ok(())
+    }
+}
+
+fn hash_into(fingerprint: &mut (DefaultHasher, DefaultHasher), value: impl Hash) {
+    value.hash(&mut fingerprint.0);
+    value.hash(&mut fingerprint.1);
+}
+
+/// Sets the bits for `fingerprint` and returns `true` if at least one of them has not been set
+/// before. Uses double hashing to derive the bit positions from the two halves of the fingerprint.
+fn bloom_insert(bits: &mut [u64], fingerprint: u128) -> bool {
+    let num_bits = bits.len() as u64 * 64;
+    let first = (fingerprint >> 64) as u64;
+    let second = fingerprint as u64;
+    let mut is_new = false;
+    for i in 0..BLOOM_NUM_HASHES {
+        let bit = first.wrapping_add(i.wrapping_mul(second)) % num_bits;
+        let (word, mask) = ((bit / 64) as usize, 1u64 << (bit % 64));
+        if bits[word] & mask == 0 {
+            is_new = true;
+            bits[word] |= mask;
+        }
+    }
+    is_new
+}
+
+#[cfg(test)]
+mod tests {
+    use odbc_api::buffers::{BufferDescription, BufferKind};
+
+    use super::{Deduplicator, Seen};
+    use crate::{
+        fake::{FakeColumn, FakeResultSet},
+        strict::LossPolicy,
+    };
+
+    const BUFFERS: [(u16, BufferDescription); 1] = [(
+        1,
+        BufferDescription {
+            nullable: true,
+            kind: BufferKind::I64,
+        },
+    )];
+
+    fn deduplicator(max_exact_keys: usize, strict: bool) -> Deduplicator {
+        Deduplicator::new(
+            &["id".to_owned()],
+            &["id".to_owned()],
+            &BUFFERS,
+            max_exact_keys,
+            LossPolicy::new(strict),
+        )
+        .unwrap()
+    }
+
+    /// Rows of the batch which are still selected after deduplication.
+    fn select(deduplicator: &mut Deduplicator, ids: &[Option<i64>]) -> Vec<bool> {
+        let result_set = FakeResultSet::new(vec![FakeColumn::i64("id", ids)]);
+        let mut selection = vec![true; ids.len()];
+        deduplicator
+            .select(&result_set.batch(&BUFFERS), &mut selection, 1)
+            .unwrap();
+        selection
+    }
+
+    #[test]
+    fn drop_keys_seen_before() {
+        let mut deduplicator = deduplicator(10, false);
+        assert_eq!(
+            vec![true, true, false, true, false],
+            select(&mut deduplicator, &[Some(1), Some(2), Some(1), None, None])
+        );
+        assert_eq!(
+            vec![false, true],
+            select(&mut deduplicator, &[Some(2), Some(3)])
+        );
+        assert_eq!(3, deduplicator.num_dropped());
+        assert!(matches!(deduplicator.seen, Seen::Exact(_)));
+    }
+
+    #[test]
+    fn switch_to_bloom_filter() {
+        let mut deduplicator = deduplicator(1, false);
+        assert_eq!(
+            vec![true, true, false, false],
+            select(&mut deduplicator, &[Some(1), Some(2), Some(1), Some(2)])
+        );
+        // Keys seen before the switch are still known to the bloom filter.
+        let bits = match &deduplicator.seen {
+            Seen::Bloom(bits) => bits,
+            Seen::Exact(_) => panic!("Must have switched to the bloom filter."),
+        };
+        assert_eq!(1, bits.len());
+        assert_eq!(vec![false], select(&mut deduplicator, &[Some(1)]));
+    }
+
+    #[test]
+    fn approximate_deduplication_is_an_error_if_strict() {
+        let mut deduplicator = deduplicator(1, true);
+        let result_set = FakeResultSet::new(vec![FakeColumn::i64("id", &[Some(1), Some(2)])]);
+        let mut selection = vec![true; 2];
+        let error = deduplicator
+            .select(&result_set.batch(&BUFFERS), &mut selection, 3)
+            .unwrap_err();
+        assert_eq!(
+            "Key 'id' in batch 3 violates rule 'approximate-deduplication': More than 1 distinct \
+            keys seen. Switching to a bloom filter for deduplication. From now on rows may be \
+            dropped, even though their key is unique. Aborting due to --strict.",
+            error.to_string()
+        );
+    }
+}
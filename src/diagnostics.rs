@@ -0,0 +1,70 @@
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// Target of the log records odbc-api emits for each diagnostic record of the driver. These are
+/// logged at warn level after any ODBC call returning `SQL_SUCCESS_WITH_INFO`, which includes
+/// preparing and executing statements.
+const DIAGNOSTICS_TARGET: &str = "odbc_api::handles::logging";
+
+/// Wraps the logger, so diagnostic records repeated by the ODBC driver are only logged the first
+/// time they occur. How often each one has been repeated is logged on `flush`.
+pub struct DedupeDiagnostics<L> {
+    inner: L,
+    /// Messages of the diagnostic records seen since the last flush, in order of appearance, and
+    /// how often each one has been seen.
+    seen: Mutex<Vec<(String, u64)>>,
+}
+
+impl<L> DedupeDiagnostics<L>
+where
+    L: Log + 'static,
+{
+    /// Install as the global logger.
+    pub fn init(inner: L, max_level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_max_level(max_level);
+        log::set_boxed_logger(Box::new(DedupeDiagnostics {
+            inner,
+            seen: Mutex::new(Vec::new()),
+        }))
+    }
+}
+
+impl<L> Log for DedupeDiagnostics<L>
+where
+    L: Log,
+{
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target() == DIAGNOSTICS_TARGET && self.enabled(record.metadata()) {
+            let message = record.args().to_string();
+            let mut seen = self.seen.lock().unwrap();
+            if let Some((_, count)) = seen.iter_mut().find(|(seen, _)| *seen == message) {
+                *count += 1;
+                return;
+            }
+            seen.push((message, 1));
+        }
+        self.inner.log(record)
+    }
+
+    fn flush(&self) {
+        let seen = std::mem::take(&mut *self.seen.lock().unwrap());
+        for (message, count) in seen.iter().filter(|(_, count)| *count > 1) {
+            self.inner.log(
+                &Record::builder()
+                    .args(format_args!(
+                        "Diagnostic record occurred {} times in total: {}",
+                        count, message
+                    ))
+                    .level(Level::Warn)
+                    .target(DIAGNOSTICS_TARGET)
+                    .build(),
+            );
+        }
+        self.inner.flush()
+    }
+}
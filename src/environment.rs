@@ -0,0 +1,64 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use anyhow::Error;
+use odbc_api::Environment;
+
+/// The one ODBC environment of this process. Created on first access.
+static ENVIRONMENT: Mutex<Option<Environment>> = Mutex::new(None);
+
+/// Exclusive access to the ODBC environment of this process. Connections borrow from it, so it is
+/// held for as long as they are open. It may be shared between threads by reference.
+pub struct OdbcEnvironment(MutexGuard<'static, Option<Environment>>);
+
+impl Deref for OdbcEnvironment {
+    type Target = Environment;
+
+    fn deref(&self) -> &Environment {
+        self.0
+            .as_ref()
+            .expect("Environment is created before handing out access to it.")
+    }
+}
+
+impl DerefMut for OdbcEnvironment {
+    fn deref_mut(&mut self) -> &mut Environment {
+        self.0
+            .as_mut()
+            .expect("Environment is created before handing out access to it.")
+    }
+}
+
+/// Access the ODBC environment, creating it on first use. Blocks, while another thread holds it.
+/// This is the only place creating an environment, so callers need not care about there only
+/// being one in the process at any time.
+pub fn odbc_environment() -> Result<OdbcEnvironment, Error> {
+    // The environment stays valid, even if a thread panicked while holding it.
+    let mut environment = ENVIRONMENT.lock().unwrap_or_else(PoisonError::into_inner);
+    if environment.is_none() {
+        // Safe, since we hold the lock and never create an environment anywhere else. So there is
+        // at most one environment in the entire process.
+        *environment = Some(unsafe { Environment::new() }?);
+    }
+    Ok(OdbcEnvironment(environment))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::odbc_environment;
+
+    #[test]
+    fn same_environment_from_multiple_threads() {
+        let addresses: Vec<_> = (0..4)
+            .map(|_| thread::spawn(|| &*odbc_environment().unwrap() as *const _ as usize))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        assert!(addresses.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+}
@@ -0,0 +1,187 @@
+//! Rough estimate of the size of an export, printed by `--estimate` before the export starts.
+
+use std::{fmt, time::Duration};
+
+use odbc_api::buffers::BufferKind;
+use parquet::basic::Type as PhysicalType;
+
+use crate::column_mapping::ColumnMapping;
+
+/// Bytes of bound ODBC buffers filled per second. Rule of thumb for fetching from a database in
+/// the same network, the actual rate depends mostly on the data source.
+const ASSUMED_FETCH_BYTES_PER_SEC: u64 = 50 * 1024 * 1024;
+
+/// Estimated size and duration of an export.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// `None` if the rows could not be counted.
+    pub num_rows: Option<u64>,
+    /// Bytes of the ODBC buffers bound per row.
+    pub fetched_bytes_per_row: u64,
+    /// Bytes each row is expected to take in the output, after encoding.
+    pub written_bytes_per_row: f64,
+}
+
+impl Estimate {
+    /// # Parameters
+    ///
+    /// * `mappings`: Mapping of every bound column. Ignored columns must not be passed.
+    /// * `num_rows`: Result of counting the rows of the query, if possible.
+    pub fn new(mappings: &[ColumnMapping], num_rows: Option<u64>) -> Self {
+        Estimate {
+            num_rows,
+            fetched_bytes_per_row: mappings.iter().map(|m| m.bytes_per_row() as u64).sum(),
+            written_bytes_per_row: mappings.iter().map(written_bytes).sum(),
+        }
+    }
+
+    /// Estimated size of the output in bytes.
+    pub fn output_bytes(&self) -> Option<u64> {
+        self.num_rows
+            .map(|num_rows| (num_rows as f64 * self.written_bytes_per_row).round() as u64)
+    }
+
+    /// Estimated duration of fetching all rows.
+    pub fn duration(&self) -> Option<Duration> {
+        self.num_rows.map(|num_rows| {
+            Duration::from_secs(num_rows * self.fetched_bytes_per_row / ASSUMED_FETCH_BYTES_PER_SEC)
+        })
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.num_rows {
+            Some(num_rows) => writeln!(f, "Estimated number of rows: {}", num_rows)?,
+            None => writeln!(f, "Estimated number of rows: unknown")?,
+        }
+        writeln!(
+            f,
+            "Estimated bytes per row: {:.0}",
+            self.written_bytes_per_row.ceil()
+        )?;
+        if let (Some(bytes), Some(duration)) = (self.output_bytes(), self.duration()) {
+            writeln!(f, "Estimated output size: {}", format_bytes(bytes))?;
+            writeln!(f, "Estimated duration: {}s", duration.as_secs().max(1))?;
+        }
+        Ok(())
+    }
+}
+
+/// Query counting the rows of `query`, or `None` if it can not be wrapped safely. We give up on
+/// anything but a single plain `SELECT`, since e.g. `ORDER BY` is not allowed in subqueries on
+/// all data sources and `TOP` or `LIMIT` in combination with it changes the count.
+pub fn count_query(query: &str) -> Option<String> {
+    let query = query.trim().trim_end_matches(';').trim_end();
+    if query.contains(';') {
+        return None;
+    }
+    let words: Vec<_> = query
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .map(str::to_uppercase)
+        .collect();
+    let starts_with_select = words.first().is_some_and(|word| word == "SELECT");
+    let complicated = words.iter().any(|word| {
+        matches!(
+            word.as_str(),
+            "TOP" | "LIMIT" | "OFFSET" | "FETCH" | "ORDER" | "UNION" | "INTO"
+        )
+    });
+    if !starts_with_select || complicated {
+        return None;
+    }
+    Some(format!(
+        "SELECT COUNT(*) FROM ({}) AS odbc2parquet_estimate",
+        query
+    ))
+}
+
+/// Bytes a value of the column is expected to take in the output. Values are not compressed, but
+/// dictionary and run length encoding shrink many integer and text columns.
+fn written_bytes(mapping: &ColumnMapping) -> f64 {
+    match (mapping.physical_type, mapping.buffer_kind) {
+        (PhysicalType::BOOLEAN, _) => 1.0 / 8.0,
+        (PhysicalType::INT32, _) => 4.0 * 0.6,
+        (PhysicalType::INT64, _) => 8.0 * 0.6,
+        (PhysicalType::INT96, _) => 12.0,
+        (PhysicalType::FLOAT, _) => 4.0,
+        (PhysicalType::DOUBLE, _) => 8.0,
+        (PhysicalType::FIXED_LEN_BYTE_ARRAY, _) => mapping.length.unwrap_or(0) as f64,
+        // Assume text fills half its maximum length on average. Plus 4 bytes length prefix.
+        (PhysicalType::BYTE_ARRAY, BufferKind::Text { max_str_len }) => {
+            (max_str_len as f64 / 2.0 + 4.0) * 0.5
+        }
+        (PhysicalType::BYTE_ARRAY, _) => 4.0,
+    }
+}
+
+/// Size with a binary unit, e.g. `1.5 KiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_query, format_bytes, Estimate};
+    use crate::fake::{FakeColumn, FakeResultSet};
+
+    #[test]
+    fn count_plain_select() {
+        assert_eq!(
+            Some("SELECT COUNT(*) FROM (SELECT a FROM t WHERE b = ?) AS odbc2parquet_estimate"),
+            count_query(" SELECT a FROM t WHERE b = ?; ").as_deref()
+        );
+    }
+
+    #[test]
+    fn do_not_count_complicated_queries() {
+        assert_eq!(None, count_query("SELECT TOP 10 a FROM t"));
+        assert_eq!(None, count_query("SELECT a FROM t ORDER BY a"));
+        assert_eq!(None, count_query("SELECT a FROM t LIMIT 10"));
+        assert_eq!(None, count_query("SELECT a FROM t; SELECT b FROM t"));
+        assert_eq!(
+            None,
+            count_query("WITH x AS (SELECT a FROM t) SELECT a FROM x")
+        );
+        assert_eq!(None, count_query("EXEC my_procedure"));
+    }
+
+    #[test]
+    fn estimate_size() {
+        let mappings = FakeResultSet::new(vec![
+            FakeColumn::i64("a", &[]),
+            FakeColumn::f64("b", &[]),
+            // Up to four bytes per character.
+            FakeColumn::text("c", &[Some("abcde")]),
+        ])
+        .mappings();
+        let estimate = Estimate::new(&mappings, Some(1000));
+        // Buffers plus one indicator each.
+        assert_eq!(8 + 8 + 21 + 3 * 8, estimate.fetched_bytes_per_row);
+        assert!((estimate.written_bytes_per_row - (4.8 + 8.0 + 7.0)).abs() < 1e-9);
+        assert_eq!(Some(19_800), estimate.output_bytes());
+
+        let unknown = Estimate::new(&mappings, None);
+        assert_eq!(None, unknown.output_bytes());
+        assert_eq!(None, unknown.duration());
+    }
+
+    #[test]
+    fn human_readable_sizes() {
+        assert_eq!("512 B", format_bytes(512));
+        assert_eq!("1.5 KiB", format_bytes(1536));
+        assert_eq!("2.0 GiB", format_bytes(2 * 1024 * 1024 * 1024));
+    }
+}
@@ -0,0 +1,184 @@
+//! Machine readable description of how each column of the result set is mapped to parquet.
+//!
+//! Printed by `--explain-mapping json`. The output is a JSON object of the following form. Keys
+//! are only ever added, never renamed or removed without incrementing `version`. Keys always appear
+//! in the same order, so the output of two runs can be compared textually.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "columns": [
+//!     {
+//!       "index": 1,
+//!       "name": "price",
+//!       "odbc": {
+//!         "data_type": "Decimal { precision: 10, scale: 2 }",
+//!         "column_size": 10,
+//!         "decimal_digits": 2,
+//!         "nullability": "Nullable"
+//!       },
+//!       "overrides": { "null_default": "0" },
+//!       "buffer": { "kind": "Text { max_str_len: 12 }", "nullable": true, "bytes_per_row": 21 },
+//!       "parquet": [
+//!         {
+//!           "name": "price",
+//!           "physical_type": "FIXED_LEN_BYTE_ARRAY",
+//!           "logical_type": "DECIMAL",
+//!           "repetition": "REQUIRED",
+//!           "length": 5,
+//!           "precision": 10,
+//!           "scale": 2
+//!         }
+//!       ],
+//!       "warnings": []
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! * `odbc`: Type information as reported by the driver.
+//! * `overrides`: Command line options changing the mapping of this column. Possible keys are
+//!   `mask`, `null_default`, `split_timestamp`, `time_precision`, `timestamp_precision` and
+//!   `on_conversion_error`.
+//! * `buffer`: The ODBC buffer bound to the column. `null` if the column is ignored.
+//! * `parquet`: The fields written for this column. Usually one, two for split timestamps and none
+//!   for ignored columns. `length`, `precision`, `scale` and `field_id` are only present if
+//!   applicable.
+//! * `warnings`: Human readable notes on conversions which may lose information.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use odbc_api::buffers::BufferDescription;
+use parquet::{
+    basic::{LogicalType, Type as PhysicalType},
+    schema::types::{Type, TypePtr},
+};
+use serde_json::{json, Map, Value};
+
+use crate::{column_mapping::ColumnMapping, mask::MaskMethod, strict::column_losses};
+
+/// Version of the JSON format. Incremented for every change, which is not purely additive.
+const VERSION: u32 = 1;
+
+/// Output formats supported by `--explain-mapping`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExplainFormat {
+    Json,
+}
+
+impl FromStr for ExplainFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ExplainFormat::Json),
+            other => bail!(
+                "Unknown format '{}' for --explain-mapping. Supported is `json`.",
+                other
+            ),
+        }
+    }
+}
+
+/// Decision trail for a single column of the result set.
+pub struct ColumnExplanation {
+    /// Mapping of the column after overrides have been applied.
+    pub mapping: ColumnMapping,
+    /// Command line options changing the mapping of this column. Option name and its value.
+    pub overrides: Map<String, Value>,
+    /// `None` if the column is ignored.
+    pub buffer: Option<BufferDescription>,
+    /// Parquet fields written for this column.
+    pub fields: Vec<TypePtr>,
+    pub warnings: Vec<String>,
+}
+
+impl ColumnExplanation {
+    /// Starts the explanation of a column with the warnings following from its type alone.
+    /// `mapping` is expected to already reflect overrides like masking.
+    pub fn new(mapping: ColumnMapping) -> Self {
+        let warnings = column_losses(&mapping)
+            .into_iter()
+            .map(|(_rule, message)| message)
+            .collect();
+        ColumnExplanation {
+            mapping,
+            overrides: Map::new(),
+            buffer: None,
+            fields: Vec::new(),
+            warnings,
+        }
+    }
+
+    /// Record a command line option changing the mapping of this column.
+    pub fn add_override(&mut self, option: &str, value: impl Into<Value>) {
+        self.overrides.insert(option.to_owned(), value.into());
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mapping = &self.mapping;
+        let buffer = match &self.buffer {
+            Some(description) => json!({
+                "kind": format!("{:?}", description.kind),
+                "nullable": description.nullable,
+                "bytes_per_row": mapping.bytes_per_row(),
+            }),
+            None => Value::Null,
+        };
+        json!({
+            "index": mapping.index,
+            "name": mapping.name,
+            "odbc": {
+                "data_type": format!("{:?}", mapping.data_type),
+                "column_size": mapping.data_type.column_size(),
+                "decimal_digits": mapping.data_type.decimal_digits(),
+                "nullability": format!("{:?}", mapping.nullability),
+            },
+            "overrides": self.overrides,
+            "buffer": buffer,
+            "parquet": self.fields.iter().map(|field| field_to_json(field)).collect::<Vec<_>>(),
+            "warnings": self.warnings,
+        })
+    }
+}
+
+/// Text used to specify a mask method on the command line.
+pub fn mask_method_text(method: &MaskMethod) -> String {
+    match method {
+        MaskMethod::Sha256 => "sha256".to_owned(),
+        MaskMethod::Null => "null".to_owned(),
+        MaskMethod::Fixed(constant) => format!("fixed:{}", constant),
+    }
+}
+
+/// The complete explanation of the mapping of a result set.
+pub fn to_json(columns: &[ColumnExplanation]) -> Value {
+    json!({
+        "version": VERSION,
+        "columns": columns.iter().map(ColumnExplanation::to_json).collect::<Vec<_>>(),
+    })
+}
+
+fn field_to_json(field: &Type) -> Value {
+    let info = field.get_basic_info();
+    let mut json = json!({
+        "name": info.name(),
+        "physical_type": field.get_physical_type().to_string(),
+        "logical_type": info.logical_type().to_string(),
+        "repetition": info.repetition().to_string(),
+    });
+    if info.has_id() {
+        json["field_id"] = info.id().into();
+    }
+    if let Type::PrimitiveType { type_length, .. } = field {
+        if field.get_physical_type() == PhysicalType::FIXED_LEN_BYTE_ARRAY {
+            json["length"] = (*type_length).into();
+        }
+    }
+    if info.logical_type() == LogicalType::DECIMAL {
+        json["precision"] = field.get_precision().into();
+        json["scale"] = field.get_scale().into();
+    }
+    json
+}
@@ -0,0 +1,314 @@
+//! In-memory stand-in for a result set, so the mapping and conversion of columns can be tested
+//! without a data source.
+
+use odbc_api::{
+    buffers::{AnyColumnViewMut, BufferDescription, ColumnarRowSet},
+    sys::{Date, Timestamp},
+    Bit, ColumnDescription, DataType, Nullability,
+};
+
+use crate::column_mapping::{ColumnMapping, DescribeColumns};
+
+/// Result set with a single batch, described and filled from in-memory columns.
+pub struct FakeResultSet {
+    columns: Vec<FakeColumn>,
+}
+
+/// A column of a `FakeResultSet`. NULLs are represented as `None`.
+pub struct FakeColumn {
+    name: String,
+    data_type: DataType,
+    nullability: Nullability,
+    display_size: isize,
+    values: FakeValues,
+}
+
+enum FakeValues {
+    Text(Vec<Option<String>>),
+    I32(Vec<Option<i32>>),
+    I64(Vec<Option<i64>>),
+    F32(Vec<Option<f32>>),
+    F64(Vec<Option<f64>>),
+    Date(Vec<Option<Date>>),
+    Timestamp(Vec<Option<Timestamp>>),
+    Bit(Vec<Option<bool>>),
+}
+
+impl FakeResultSet {
+    pub fn new(columns: Vec<FakeColumn>) -> Self {
+        FakeResultSet { columns }
+    }
+
+    /// Mappings of all columns, as decided for a real result set.
+    pub fn mappings(&self) -> Vec<ColumnMapping> {
+        (1..=self.columns.len() as u16)
+            .map(|index| ColumnMapping::new(self, index).unwrap())
+            .collect()
+    }
+
+    /// The batch a driver would fetch into `buffers`. Every buffer is filled with the values of
+    /// the column it is bound to, converted to the kind of the buffer, like a driver would.
+    pub fn batch(&self, buffers: &[(u16, BufferDescription)]) -> ColumnarRowSet {
+        let num_rows = self.columns.first().map_or(0, FakeColumn::num_rows);
+        let mut batch =
+            ColumnarRowSet::with_column_indices(num_rows.max(1) as u32, buffers.iter().copied());
+        batch.set_num_rows(num_rows);
+        for (buffer_index, (col_index, _)) in buffers.iter().enumerate() {
+            let column = &self.columns[*col_index as usize - 1];
+            assert_eq!(
+                num_rows,
+                column.num_rows(),
+                "Columns must have the same length."
+            );
+            column.fill(batch.column_mut(buffer_index));
+        }
+        batch
+    }
+}
+
+impl DescribeColumns for FakeResultSet {
+    fn num_result_cols(&self) -> Result<i16, odbc_api::Error> {
+        Ok(self.columns.len() as i16)
+    }
+
+    fn describe_col(&self, index: u16, cd: &mut ColumnDescription) -> Result<(), odbc_api::Error> {
+        let column = &self.columns[index as usize - 1];
+        cd.name = column.name.encode_utf16().collect();
+        cd.data_type = column.data_type;
+        cd.nullability = column.nullability.clone();
+        Ok(())
+    }
+
+    fn col_display_size(&self, index: u16) -> Result<isize, odbc_api::Error> {
+        Ok(self.columns[index as usize - 1].display_size)
+    }
+}
+
+impl FakeColumn {
+    /// `VARCHAR` column, as long as its longest value.
+    pub fn text(name: &str, values: &[Option<&str>]) -> Self {
+        let length = values.iter().flatten().map(|v| v.len()).max().unwrap_or(1);
+        Self::new(
+            name,
+            DataType::Varchar { length },
+            FakeValues::Text(to_owned(values)),
+        )
+    }
+
+    pub fn i32(name: &str, values: &[Option<i32>]) -> Self {
+        Self::new(name, DataType::Integer, FakeValues::I32(values.to_vec()))
+    }
+
+    pub fn i64(name: &str, values: &[Option<i64>]) -> Self {
+        Self::new(name, DataType::Bigint, FakeValues::I64(values.to_vec()))
+    }
+
+    pub fn f32(name: &str, values: &[Option<f32>]) -> Self {
+        Self::new(name, DataType::Real, FakeValues::F32(values.to_vec()))
+    }
+
+    pub fn f64(name: &str, values: &[Option<f64>]) -> Self {
+        Self::new(name, DataType::Double, FakeValues::F64(values.to_vec()))
+    }
+
+    /// `DECIMAL` column. Values are given as text, like the driver would return them.
+    pub fn decimal(name: &str, precision: usize, scale: i16, values: &[Option<&str>]) -> Self {
+        Self::new(
+            name,
+            DataType::Decimal { precision, scale },
+            FakeValues::Text(to_owned(values)),
+        )
+    }
+
+    /// `DATE` column. Values are given as `YYYY-MM-DD`.
+    pub fn date(name: &str, values: &[Option<&str>]) -> Self {
+        let values = values.iter().map(|v| v.map(parse_date)).collect();
+        Self::new(name, DataType::Date, FakeValues::Date(values))
+    }
+
+    /// `TIME` column with `precision` fractional digits. Values are given as
+    /// `hh:mm:ss.fffffff`, since times are fetched as text.
+    pub fn time(name: &str, precision: i16, values: &[Option<&str>]) -> Self {
+        Self::new(
+            name,
+            DataType::Time { precision },
+            FakeValues::Text(to_owned(values)),
+        )
+    }
+
+    /// `TIMESTAMP` column with `precision` fractional digits. Values are given as
+    /// `YYYY-MM-DD hh:mm:ss.fffffffff`, the fraction being optional.
+    pub fn timestamp(name: &str, precision: i16, values: &[Option<&str>]) -> Self {
+        let values = values.iter().map(|v| v.map(parse_timestamp)).collect();
+        Self::new(
+            name,
+            DataType::Timestamp { precision },
+            FakeValues::Timestamp(values),
+        )
+    }
+
+    pub fn bit(name: &str, values: &[Option<bool>]) -> Self {
+        Self::new(name, DataType::Bit, FakeValues::Bit(values.to_vec()))
+    }
+
+    /// Report the column as not nullable. NULLs in its values are still written to the batch,
+    /// like a driver reporting the wrong nullability would.
+    pub fn not_null(self) -> Self {
+        FakeColumn {
+            nullability: Nullability::NoNulls,
+            ..self
+        }
+    }
+
+    /// Replace the data type reported by the driver, e.g. to mimic types unknown to ODBC.
+    pub fn with_data_type(self, data_type: DataType) -> Self {
+        FakeColumn { data_type, ..self }
+    }
+
+    /// Display size reported for types without a known maximum length, e.g. `Unknown`.
+    pub fn with_display_size(self, display_size: isize) -> Self {
+        FakeColumn {
+            display_size,
+            ..self
+        }
+    }
+
+    fn new(name: &str, data_type: DataType, values: FakeValues) -> Self {
+        FakeColumn {
+            name: name.to_owned(),
+            data_type,
+            nullability: Nullability::Nullable,
+            display_size: data_type.utf8_len().unwrap_or(0) as isize,
+            values,
+        }
+    }
+
+    fn num_rows(&self) -> usize {
+        match &self.values {
+            FakeValues::Text(v) => v.len(),
+            FakeValues::I32(v) => v.len(),
+            FakeValues::I64(v) => v.len(),
+            FakeValues::F32(v) => v.len(),
+            FakeValues::F64(v) => v.len(),
+            FakeValues::Date(v) => v.len(),
+            FakeValues::Timestamp(v) => v.len(),
+            FakeValues::Bit(v) => v.len(),
+        }
+    }
+
+    /// Values as text, e.g. for columns fetched as text due to masking.
+    fn values_as_text(&self) -> Vec<Option<String>> {
+        fn format<T: ToString>(values: &[Option<T>]) -> Vec<Option<String>> {
+            values
+                .iter()
+                .map(|v| v.as_ref().map(T::to_string))
+                .collect()
+        }
+        match &self.values {
+            FakeValues::Text(v) => v.clone(),
+            FakeValues::I32(v) => format(v),
+            FakeValues::I64(v) => format(v),
+            FakeValues::F32(v) => format(v),
+            FakeValues::F64(v) => format(v),
+            FakeValues::Date(v) => v
+                .iter()
+                .map(|d| d.map(|d| format!("{:04}-{:02}-{:02}", d.year, d.month, d.day)))
+                .collect(),
+            FakeValues::Timestamp(v) => v
+                .iter()
+                .map(|t| {
+                    t.map(|t| {
+                        format!(
+                            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09}",
+                            t.year, t.month, t.day, t.hour, t.minute, t.second, t.fraction
+                        )
+                    })
+                })
+                .collect(),
+            FakeValues::Bit(v) => v.iter().map(|b| b.map(|b| (b as u8).to_string())).collect(),
+        }
+    }
+
+    fn fill(&self, view: AnyColumnViewMut) {
+        match (view, &self.values) {
+            (AnyColumnViewMut::Text(mut writer), _) => {
+                let text = self.values_as_text();
+                writer.write(text.iter().map(|v| v.as_ref().map(|s| s.as_bytes())))
+            }
+            (AnyColumnViewMut::NullableI32(mut writer), FakeValues::I32(v)) => {
+                writer.write(v.iter().copied())
+            }
+            // Decimals with scale 0 are fetched as integers.
+            (AnyColumnViewMut::NullableI32(mut writer), FakeValues::Text(v)) => {
+                writer.write(v.iter().map(|v| v.as_ref().map(|s| s.parse().unwrap())))
+            }
+            (AnyColumnViewMut::NullableI64(mut writer), FakeValues::I64(v)) => {
+                writer.write(v.iter().copied())
+            }
+            (AnyColumnViewMut::NullableI64(mut writer), FakeValues::Text(v)) => {
+                writer.write(v.iter().map(|v| v.as_ref().map(|s| s.parse().unwrap())))
+            }
+            (AnyColumnViewMut::NullableF32(mut writer), FakeValues::F32(v)) => {
+                writer.write(v.iter().copied())
+            }
+            (AnyColumnViewMut::NullableF64(mut writer), FakeValues::F64(v)) => {
+                writer.write(v.iter().copied())
+            }
+            (AnyColumnViewMut::NullableDate(mut writer), FakeValues::Date(v)) => {
+                writer.write(v.iter().copied())
+            }
+            (AnyColumnViewMut::NullableTimestamp(mut writer), FakeValues::Timestamp(v)) => {
+                writer.write(v.iter().copied())
+            }
+            (AnyColumnViewMut::NullableBit(mut writer), FakeValues::Bit(v)) => {
+                writer.write(v.iter().map(|b| b.map(|b| Bit(b as u8))))
+            }
+            (AnyColumnViewMut::Bit(bits), FakeValues::Bit(v)) => {
+                for (bit, value) in bits.iter_mut().zip(v) {
+                    *bit = Bit(value.expect("Bit column bound without indicators.") as u8);
+                }
+            }
+            (view, _) => panic!(
+                "Values of fake column '{}' can not be written into {:?}.",
+                self.name, view
+            ),
+        }
+    }
+}
+
+fn to_owned(values: &[Option<&str>]) -> Vec<Option<String>> {
+    values.iter().map(|v| v.map(str::to_owned)).collect()
+}
+
+fn parse_date(text: &str) -> Date {
+    let parts: Vec<i32> = text.split('-').map(|p| p.parse().unwrap()).collect();
+    Date {
+        year: parts[0] as i16,
+        month: parts[1] as u16,
+        day: parts[2] as u16,
+    }
+}
+
+fn parse_timestamp(text: &str) -> Timestamp {
+    let (date, time) = text.split_at(text.find(' ').unwrap());
+    let date = parse_date(date);
+    let (time, fraction) = match time.trim().find('.') {
+        Some(pos) => {
+            let time = time.trim();
+            // Pad the fractional digits to nanoseconds.
+            let fraction = format!("{:0<9}", &time[(pos + 1)..]);
+            (&time[..pos], fraction.parse().unwrap())
+        }
+        None => (time.trim(), 0),
+    };
+    let parts: Vec<u16> = time.split(':').map(|p| p.parse().unwrap()).collect();
+    Timestamp {
+        year: date.year,
+        month: date.month,
+        day: date.day,
+        hour: parts[0],
+        minute: parts[1],
+        second: parts[2],
+        fraction,
+    }
+}
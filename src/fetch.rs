@@ -0,0 +1,328 @@
+use anyhow::{bail, Error};
+use log::{info, warn};
+use odbc_api::{
+    buffers::{AnyColumnView, AnyColumnViewMut, BufferDescription, BufferKind, ColumnarRowSet},
+    sys::{Date, Timestamp},
+    Bit, Cursor, RowSetCursor,
+};
+
+/// Source of the batches written to parquet.
+pub enum Batches<C> {
+    /// Fetch many rows at once into the bound buffer. This is the default.
+    Block(RowSetCursor<C, ColumnarRowSet>),
+    /// Fetch one row at a time and collect the rows into a batch. Used for drivers which do not
+    /// support array binding.
+    SingleRow {
+        cursor: RowSetCursor<C, ColumnarRowSet>,
+        /// Values of the rows collected for the current batch, one entry per bound column.
+        columns: Vec<Values>,
+        batch: ColumnarRowSet,
+        batch_size: usize,
+    },
+}
+
+impl<C: Cursor> Batches<C> {
+    /// Bind buffers of `batch_size` rows to the cursor. Should the driver reject array binding
+    /// (SQLSTATE HYC00), we fall back to fetching one row at a time. Binding consumes the cursor,
+    /// so in this case the query is executed again using `execute_again`.
+    pub fn bind(
+        cursor: C,
+        execute_again: impl FnOnce() -> Result<Option<C>, odbc_api::Error>,
+        buffers: &[(u16, BufferDescription)],
+        batch_size: u32,
+    ) -> Result<Self, Error> {
+        let batch = ColumnarRowSet::with_column_indices(batch_size, buffers.iter().copied());
+        let error = match cursor.bind_buffer(batch) {
+            Ok(row_set_cursor) => {
+                info!("Fetching rows in blocks of up to {}.", batch_size);
+                return Ok(Batches::Block(row_set_cursor));
+            }
+            Err(error) => error,
+        };
+        if !is_optional_feature_not_implemented(&error) {
+            return Err(error.into());
+        }
+        warn!(
+            "The ODBC driver does not support fetching rows in blocks ({}). Falling back to \
+            fetching one row at a time, which is considerably slower. The query is executed again \
+            for this.",
+            error
+        );
+        let cursor = match execute_again()? {
+            Some(cursor) => cursor,
+            None => bail!("Query did not return a result set, when executed again."),
+        };
+        let row = ColumnarRowSet::with_column_indices(1, buffers.iter().copied());
+        let cursor = cursor.bind_buffer(row)?;
+        info!(
+            "Fetching rows one at a time and collecting up to {} of them per batch.",
+            batch_size
+        );
+        Ok(Batches::SingleRow {
+            cursor,
+            columns: buffers
+                .iter()
+                .map(|(_, desc)| Values::new(desc.kind))
+                .collect(),
+            batch: ColumnarRowSet::with_column_indices(batch_size, buffers.iter().copied()),
+            batch_size: batch_size as usize,
+        })
+    }
+
+    /// `true` if rows are fetched one at a time.
+    pub fn is_single_row(&self) -> bool {
+        matches!(self, Batches::SingleRow { .. })
+    }
+
+    /// Fetch the next batch. `None` if the result set is consumed.
+    pub fn fetch(&mut self) -> Result<Option<&ColumnarRowSet>, Error> {
+        match self {
+            Batches::Block(cursor) => Ok(cursor.fetch()?),
+            Batches::SingleRow {
+                cursor,
+                columns,
+                batch,
+                batch_size,
+            } => {
+                let mut num_rows = 0;
+                while num_rows != *batch_size {
+                    let row = match cursor.fetch()? {
+                        Some(row) => row,
+                        None => break,
+                    };
+                    // Some drivers report zero rows rather than no data at the end of the result
+                    // set.
+                    if row.num_rows() == 0 {
+                        break;
+                    }
+                    check_num_rows_fetched(row.num_rows(), 1)?;
+                    for (index, values) in columns.iter_mut().enumerate() {
+                        values.push(row.column(index), None);
+                    }
+                    num_rows += 1;
+                }
+                if num_rows == 0 {
+                    return Ok(None);
+                }
+                batch.set_num_rows(num_rows);
+                for (index, values) in columns.iter_mut().enumerate() {
+                    values.write_into(batch.column_mut(index));
+                    values.clear();
+                }
+                Ok(Some(batch))
+            }
+        }
+    }
+}
+
+/// Fails if the ODBC driver reports more rows fetched than the row set bound to the cursor can
+/// hold. Reading them would access memory beyond the ODBC buffers. We can not know which of the
+/// rows are valid, so we do not try to recover from this.
+pub fn check_num_rows_fetched(num_rows_fetched: usize, capacity: usize) -> Result<(), Error> {
+    if num_rows_fetched > capacity {
+        bail!(
+            "The ODBC driver reported {} rows fetched, but the row set has only room for {}. This \
+            is a bug in the ODBC driver.",
+            num_rows_fetched,
+            capacity
+        )
+    }
+    Ok(())
+}
+
+/// Collects the selected rows of several fetched batches, so they can be written as a single row
+/// group. Rows are copied, since the buffers bound to the cursor are overwritten by the next fetch.
+pub struct Accumulator {
+    /// Values of the rows collected so far, one entry per bound column.
+    columns: Vec<Values>,
+    /// Filled with the collected rows, once they are written.
+    batch: ColumnarRowSet,
+    capacity: usize,
+}
+
+impl Accumulator {
+    /// Collects up to `capacity` rows of batches fetched into `buffers`.
+    pub fn new(buffers: &[(u16, BufferDescription)], capacity: usize) -> Self {
+        Accumulator {
+            columns: buffers
+                .iter()
+                .map(|(_, desc)| Values::new(desc.kind))
+                .collect(),
+            batch: ColumnarRowSet::with_column_indices(capacity as u32, buffers.iter().copied()),
+            capacity,
+        }
+    }
+
+    /// Copies the selected rows of `batch`. `None` selects every row.
+    pub fn push(&mut self, batch: &ColumnarRowSet, selection: Option<&[bool]>) {
+        for (index, values) in self.columns.iter_mut().enumerate() {
+            values.push(batch.column(index), selection);
+        }
+        debug_assert!(self
+            .columns
+            .first()
+            .is_none_or(|v| v.len() <= self.capacity));
+    }
+
+    /// All rows collected since the last call. Starts collecting anew.
+    pub fn take(&mut self) -> &ColumnarRowSet {
+        let num_rows = self.columns.first().map_or(0, Values::len);
+        self.batch.set_num_rows(num_rows);
+        for (index, values) in self.columns.iter_mut().enumerate() {
+            values.write_into(self.batch.column_mut(index));
+            values.clear();
+        }
+        &self.batch
+    }
+}
+
+/// Values of a single column, copied out of a fetched buffer.
+pub enum Values {
+    Text(Vec<Option<Vec<u8>>>),
+    F64(Vec<Option<f64>>),
+    F32(Vec<Option<f32>>),
+    I32(Vec<Option<i32>>),
+    I64(Vec<Option<i64>>),
+    Date(Vec<Option<Date>>),
+    Timestamp(Vec<Option<Timestamp>>),
+    Bit(Vec<Option<Bit>>),
+}
+
+impl Values {
+    fn new(kind: BufferKind) -> Self {
+        match kind {
+            BufferKind::Text { .. } => Values::Text(Vec::new()),
+            BufferKind::F64 => Values::F64(Vec::new()),
+            BufferKind::F32 => Values::F32(Vec::new()),
+            BufferKind::I32 => Values::I32(Vec::new()),
+            BufferKind::I64 => Values::I64(Vec::new()),
+            BufferKind::Date => Values::Date(Vec::new()),
+            BufferKind::Timestamp => Values::Timestamp(Vec::new()),
+            BufferKind::Bit => Values::Bit(Vec::new()),
+            other => unreachable!("Buffer kind {:?} is never chosen by the schema.", other),
+        }
+    }
+
+    /// Copies the values of the selected rows. `None` selects every row.
+    fn push(&mut self, column: AnyColumnView, selection: Option<&[bool]>) {
+        match (self, column) {
+            (Values::Text(values), AnyColumnView::Text(it)) => extend_selected(
+                values,
+                it.map(|text| text.map(|text| text.to_bytes().to_vec())),
+                selection,
+            ),
+            (Values::F64(values), AnyColumnView::NullableF64(it)) => {
+                extend_selected(values, it.map(Option::<&_>::copied), selection)
+            }
+            (Values::F32(values), AnyColumnView::NullableF32(it)) => {
+                extend_selected(values, it.map(Option::<&_>::copied), selection)
+            }
+            (Values::I32(values), AnyColumnView::NullableI32(it)) => {
+                extend_selected(values, it.map(Option::<&_>::copied), selection)
+            }
+            (Values::I64(values), AnyColumnView::NullableI64(it)) => {
+                extend_selected(values, it.map(Option::<&_>::copied), selection)
+            }
+            (Values::Date(values), AnyColumnView::NullableDate(it)) => {
+                extend_selected(values, it.map(Option::<&_>::copied), selection)
+            }
+            (Values::Timestamp(values), AnyColumnView::NullableTimestamp(it)) => {
+                extend_selected(values, it.map(Option::<&_>::copied), selection)
+            }
+            (Values::Bit(values), AnyColumnView::NullableBit(it)) => {
+                extend_selected(values, it.map(Option::<&_>::copied), selection)
+            }
+            (Values::Bit(values), AnyColumnView::Bit(bits)) => {
+                extend_selected(values, bits.iter().copied().map(Some), selection)
+            }
+            _ => unreachable!("Values are copied from a buffer of the same kind."),
+        }
+    }
+
+    fn write_into(&self, column: AnyColumnViewMut) {
+        match (self, column) {
+            (Values::Text(values), AnyColumnViewMut::Text(mut writer)) => {
+                writer.write(values.iter().map(|value| value.as_deref()))
+            }
+            (Values::F64(values), AnyColumnViewMut::NullableF64(mut writer)) => {
+                writer.write(values.iter().copied())
+            }
+            (Values::F32(values), AnyColumnViewMut::NullableF32(mut writer)) => {
+                writer.write(values.iter().copied())
+            }
+            (Values::I32(values), AnyColumnViewMut::NullableI32(mut writer)) => {
+                writer.write(values.iter().copied())
+            }
+            (Values::I64(values), AnyColumnViewMut::NullableI64(mut writer)) => {
+                writer.write(values.iter().copied())
+            }
+            (Values::Date(values), AnyColumnViewMut::NullableDate(mut writer)) => {
+                writer.write(values.iter().copied())
+            }
+            (Values::Timestamp(values), AnyColumnViewMut::NullableTimestamp(mut writer)) => {
+                writer.write(values.iter().copied())
+            }
+            (Values::Bit(values), AnyColumnViewMut::NullableBit(mut writer)) => {
+                writer.write(values.iter().copied())
+            }
+            (Values::Bit(values), AnyColumnViewMut::Bit(bits)) => {
+                for (bit, value) in bits.iter_mut().zip(values) {
+                    *bit = value.unwrap_or_default()
+                }
+            }
+            _ => unreachable!("Values are copied from a buffer of the same kind."),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Values::Text(values) => values.len(),
+            Values::F64(values) => values.len(),
+            Values::F32(values) => values.len(),
+            Values::I32(values) => values.len(),
+            Values::I64(values) => values.len(),
+            Values::Date(values) => values.len(),
+            Values::Timestamp(values) => values.len(),
+            Values::Bit(values) => values.len(),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Values::Text(values) => values.clear(),
+            Values::F64(values) => values.clear(),
+            Values::F32(values) => values.clear(),
+            Values::I32(values) => values.clear(),
+            Values::I64(values) => values.clear(),
+            Values::Date(values) => values.clear(),
+            Values::Timestamp(values) => values.clear(),
+            Values::Bit(values) => values.clear(),
+        }
+    }
+}
+
+fn extend_selected<T>(
+    values: &mut Vec<T>,
+    items: impl Iterator<Item = T>,
+    selection: Option<&[bool]>,
+) {
+    match selection {
+        None => values.extend(items),
+        Some(selection) => values.extend(
+            items
+                .zip(selection)
+                .filter(|(_, &selected)| selected)
+                .map(|(item, _)| item),
+        ),
+    }
+}
+
+/// `true` if the error has SQLSTATE HYC00 (Optional feature not implemented). Reported by drivers
+/// which do not support binding arrays of rows.
+fn is_optional_feature_not_implemented(error: &odbc_api::Error) -> bool {
+    matches!(
+        error,
+        odbc_api::Error::Diagnostics(record)
+            if String::from_utf16_lossy(&record.state[..5]) == "HYC00"
+    )
+}
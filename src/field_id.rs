@@ -0,0 +1,65 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+/// Field id of a column in the parquet schema. Parsed from command line arguments of the form
+/// `column=id`.
+#[derive(Debug, Clone)]
+pub struct FieldId {
+    /// Name of the field in the parquet schema.
+    pub column: String,
+    pub id: i32,
+}
+
+impl FromStr for FieldId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, id) = match s.find('=') {
+            Some(pos) => (&s[..pos], &s[(pos + 1)..]),
+            None => bail!(
+                "Field id '{}' must be of the form `column=id`. E.g. `customer_id=1`.",
+                s
+            ),
+        };
+        if column.is_empty() {
+            bail!("Field id '{}' does not specify a column name.", s)
+        }
+        let id = match id.parse::<i32>() {
+            Ok(id) if id > 0 => id,
+            _ => bail!(
+                "Field id of column '{}' must be a positive integer. Found: '{}'",
+                column,
+                id
+            ),
+        };
+        Ok(FieldId {
+            column: column.to_owned(),
+            id,
+        })
+    }
+}
+
+/// Fails if the same id is assigned to more than one column, or a column is assigned more than
+/// one id.
+pub fn check_unique(field_ids: &[FieldId]) -> Result<(), Error> {
+    for (index, field_id) in field_ids.iter().enumerate() {
+        for other in &field_ids[..index] {
+            if other.id == field_id.id {
+                bail!(
+                    "Field id {} is assigned to both '{}' and '{}'.",
+                    field_id.id,
+                    other.column,
+                    field_id.column
+                )
+            }
+            if other.column == field_id.column {
+                bail!(
+                    "Column '{}' is assigned more than one field id.",
+                    field_id.column
+                )
+            }
+        }
+    }
+    Ok(())
+}
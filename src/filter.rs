@@ -0,0 +1,496 @@
+//! `filter` subcommand. Copies the rows of a parquet file satisfying a predicate into a new file.
+
+use std::{
+    fs::{self, File},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{bail, Error};
+use log::info;
+use parquet::{
+    basic::Encoding,
+    column::{
+        reader::{get_typed_column_reader, ColumnReader},
+        writer::{get_typed_column_writer_mut, ColumnWriter},
+    },
+    data_type::{
+        BoolType, ByteArray, ByteArrayType, DataType, DoubleType, FixedLenByteArray,
+        FixedLenByteArrayType, FloatType, Int32Type, Int64Type, Int96, Int96Type,
+    },
+    file::{
+        metadata::RowGroupMetaData,
+        properties::WriterProperties,
+        reader::{FileReader, RowGroupReader, SerializedFileReader},
+        writer::{FileWriter, SerializedFileWriter},
+    },
+    schema::types::ColumnDescriptor,
+};
+
+use crate::{
+    append::temporary_path,
+    output_path::refuse_existing,
+    parquet_buffer::write_with_null_count,
+    predicate::{BoundCondition, Value},
+    FilterOpt,
+};
+
+/// Execute the `filter` subcommand.
+pub fn filter(opt: &FilterOpt) -> Result<(), Error> {
+    if !opt.overwrite {
+        refuse_existing(&opt.output)?;
+    }
+    // Written under a temporary name, so a failing filter does not leave a truncated file behind.
+    let writing_path = temporary_path(&opt.output);
+    let result = filter_into(opt, &writing_path);
+    if result.is_err() {
+        // The error is more interesting than a failure to clean up.
+        let _ = fs::remove_file(&writing_path);
+    }
+    result?;
+    fs::rename(&writing_path, &opt.output)?;
+    Ok(())
+}
+
+/// Writes the qualifying rows of the input to `path`.
+fn filter_into(opt: &FilterOpt, path: &Path) -> Result<(), Error> {
+    let reader = SerializedFileReader::new(File::open(&opt.input)?)?;
+    let metadata = reader.metadata();
+    let schema = metadata.file_metadata().schema_descr();
+    if let Some(column) = schema.columns().iter().find(|c| c.max_rep_level() > 0) {
+        bail!(
+            "Column '{}' is repeated. Filtering files with repeated fields is not supported.",
+            column.path()
+        );
+    }
+    let conditions = opt.predicate.bind(schema)?;
+
+    let properties = Arc::new(writer_properties(&reader));
+    let mut writer =
+        SerializedFileWriter::new(File::create(path)?, schema.root_schema_ptr(), properties)?;
+
+    let mut num_skipped_row_groups = 0;
+    let mut num_rows_written = 0;
+    for index in 0..reader.num_row_groups() {
+        let row_group_metadata = metadata.row_group(index);
+        if excluded(&conditions, row_group_metadata) {
+            num_skipped_row_groups += 1;
+            continue;
+        }
+        let chunks = read_chunks(reader.get_row_group(index)?.as_ref())?;
+        let num_rows = row_group_metadata.num_rows() as usize;
+        let selection: Vec<bool> = (0..num_rows)
+            .map(|row| {
+                conditions
+                    .iter()
+                    .all(|condition| condition.matches(chunks[condition.column].value(row)))
+            })
+            .collect();
+        let num_selected = selection.iter().filter(|&&selected| selected).count();
+        if num_selected == 0 {
+            continue;
+        }
+        write_chunks(&mut writer, &chunks, &selection)?;
+        num_rows_written += num_selected;
+    }
+    writer.close()?;
+
+    info!(
+        "Skipped {} of {} row groups based on their statistics.",
+        num_skipped_row_groups,
+        reader.num_row_groups()
+    );
+    info!(
+        "Wrote {} of {} rows.",
+        num_rows_written,
+        metadata.file_metadata().num_rows()
+    );
+    Ok(())
+}
+
+/// Compression, dictionary encoding and key value metadata of the output are taken over from the
+/// input. Compression and encodings are stored per column chunk. We go by the first row group.
+fn writer_properties(reader: &SerializedFileReader<File>) -> WriterProperties {
+    let metadata = reader.metadata();
+    let mut builder = WriterProperties::builder()
+        .set_key_value_metadata(metadata.file_metadata().key_value_metadata().clone());
+    if let Some(row_group) = metadata.row_groups().first() {
+        for column in row_group.columns() {
+            let dictionary = column.encodings().iter().any(|encoding| {
+                matches!(
+                    encoding,
+                    Encoding::PLAIN_DICTIONARY | Encoding::RLE_DICTIONARY
+                )
+            });
+            builder = builder
+                .set_column_compression(column.column_path().clone(), column.compression())
+                .set_column_dictionary_enabled(column.column_path().clone(), dictionary);
+        }
+    }
+    builder.build()
+}
+
+/// `true` if the statistics of the row group prove that none of its rows satisfy all conditions.
+fn excluded(conditions: &[BoundCondition], row_group: &RowGroupMetaData) -> bool {
+    conditions.iter().any(|condition| {
+        condition.excludes(
+            row_group.column(condition.column).statistics(),
+            row_group.num_rows(),
+        )
+    })
+}
+
+/// Reads all column chunks of a row group into memory. Repeated columns are not supported.
+pub fn read_chunks(row_group: &dyn RowGroupReader) -> Result<Vec<Box<dyn AnyChunk>>, Error> {
+    let num_rows = row_group.metadata().num_rows() as usize;
+    (0..row_group.num_columns())
+        .map(|column| read_chunk(row_group, column, num_rows))
+        .collect()
+}
+
+/// Writes the selected rows of the chunks into the next row group of `writer`. The writer must
+/// have the same columns as the file the chunks have been read from.
+pub fn write_chunks(
+    writer: &mut dyn FileWriter,
+    chunks: &[Box<dyn AnyChunk>],
+    selection: &[bool],
+) -> Result<(), Error> {
+    let mut row_group_writer = writer.next_row_group()?;
+    for chunk in chunks {
+        let mut column_writer = row_group_writer
+            .next_column()?
+            .expect("Output has the same columns as the input.");
+        chunk.write(&mut column_writer, selection)?;
+        row_group_writer.close_column(column_writer)?;
+    }
+    writer.close_row_group(row_group_writer)?;
+    Ok(())
+}
+
+fn read_chunk(
+    row_group: &dyn RowGroupReader,
+    column: usize,
+    num_rows: usize,
+) -> Result<Box<dyn AnyChunk>, Error> {
+    let descr = row_group.metadata().column(column).column_descr();
+    let reader = row_group.get_column_reader(column)?;
+    let chunk: Box<dyn AnyChunk> = match reader {
+        ColumnReader::BoolColumnReader(_) => {
+            Box::new(Chunk::<BoolType>::read(reader, descr, num_rows)?)
+        }
+        ColumnReader::Int32ColumnReader(_) => {
+            Box::new(Chunk::<Int32Type>::read(reader, descr, num_rows)?)
+        }
+        ColumnReader::Int64ColumnReader(_) => {
+            Box::new(Chunk::<Int64Type>::read(reader, descr, num_rows)?)
+        }
+        ColumnReader::Int96ColumnReader(_) => {
+            Box::new(Chunk::<Int96Type>::read(reader, descr, num_rows)?)
+        }
+        ColumnReader::FloatColumnReader(_) => {
+            Box::new(Chunk::<FloatType>::read(reader, descr, num_rows)?)
+        }
+        ColumnReader::DoubleColumnReader(_) => {
+            Box::new(Chunk::<DoubleType>::read(reader, descr, num_rows)?)
+        }
+        ColumnReader::ByteArrayColumnReader(_) => {
+            Box::new(Chunk::<ByteArrayType>::read(reader, descr, num_rows)?)
+        }
+        ColumnReader::FixedLenByteArrayColumnReader(_) => Box::new(
+            Chunk::<FixedLenByteArrayType>::read(reader, descr, num_rows)?,
+        ),
+    };
+    Ok(chunk)
+}
+
+/// A column chunk held in memory, independent of its physical type.
+pub trait AnyChunk {
+    /// Value of the column in `row`. `None` represents NULL.
+    fn value(&self, row: usize) -> Option<Value<'_>>;
+
+    /// Write the values of the selected rows to the column writer.
+    fn write(&self, writer: &mut ColumnWriter, selection: &[bool]) -> Result<(), Error>;
+}
+
+struct Chunk<T: DataType> {
+    /// Values of the non-NULL rows.
+    values: Vec<T::T>,
+    /// `None` for required columns.
+    def_levels: Option<Vec<i16>>,
+    /// Index into `values` for each row. `None` for NULLs.
+    rows: Vec<Option<usize>>,
+}
+
+impl<T> Chunk<T>
+where
+    T: DataType,
+    T::T: AsValue,
+{
+    fn read(
+        reader: ColumnReader,
+        descr: &ColumnDescriptor,
+        num_rows: usize,
+    ) -> Result<Self, Error> {
+        let mut reader = get_typed_column_reader::<T>(reader);
+        let mut values = vec![T::T::default(); num_rows];
+        let max_def_level = descr.max_def_level();
+        let mut def_levels = if max_def_level > 0 {
+            Some(vec![0; num_rows])
+        } else {
+            None
+        };
+        let (num_values, _) =
+            reader.read_batch(num_rows, def_levels.as_deref_mut(), None, &mut values)?;
+        values.truncate(num_values);
+        let rows = match &def_levels {
+            Some(def_levels) => {
+                let mut next_value = 0;
+                def_levels
+                    .iter()
+                    .map(|&level| {
+                        (level == max_def_level).then(|| {
+                            next_value += 1;
+                            next_value - 1
+                        })
+                    })
+                    .collect()
+            }
+            None => (0..num_values).map(Some).collect(),
+        };
+        Ok(Chunk {
+            values,
+            def_levels,
+            rows,
+        })
+    }
+}
+
+impl<T> AnyChunk for Chunk<T>
+where
+    T: DataType,
+    T::T: AsValue,
+{
+    fn value(&self, row: usize) -> Option<Value<'_>> {
+        self.rows[row].map(|index| self.values[index].as_value())
+    }
+
+    fn write(&self, writer: &mut ColumnWriter, selection: &[bool]) -> Result<(), Error> {
+        let values: Vec<T::T> = self
+            .rows
+            .iter()
+            .zip(selection)
+            .filter_map(|(&index, &selected)| index.filter(|_| selected))
+            .map(|index| self.values[index].clone())
+            .collect();
+        let def_levels: Option<Vec<i16>> = self.def_levels.as_ref().map(|def_levels| {
+            def_levels
+                .iter()
+                .zip(selection)
+                .filter(|(_, &selected)| selected)
+                .map(|(&level, _)| level)
+                .collect()
+        });
+        let writer = get_typed_column_writer_mut::<T>(writer);
+        match &def_levels {
+            Some(def_levels) => write_with_null_count(writer, &values, def_levels)?,
+            None => {
+                writer.write_batch(&values, None, None)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Physical values as seen by predicates.
+trait AsValue: Clone + Default {
+    fn as_value(&self) -> Value<'_>;
+}
+
+impl AsValue for bool {
+    fn as_value(&self) -> Value<'_> {
+        Value::Boolean(*self)
+    }
+}
+
+impl AsValue for i32 {
+    fn as_value(&self) -> Value<'_> {
+        Value::Integer(*self as i64)
+    }
+}
+
+impl AsValue for i64 {
+    fn as_value(&self) -> Value<'_> {
+        Value::Integer(*self)
+    }
+}
+
+impl AsValue for Int96 {
+    fn as_value(&self) -> Value<'_> {
+        Value::Incomparable
+    }
+}
+
+impl AsValue for f32 {
+    fn as_value(&self) -> Value<'_> {
+        Value::Float(*self as f64)
+    }
+}
+
+impl AsValue for f64 {
+    fn as_value(&self) -> Value<'_> {
+        Value::Float(*self)
+    }
+}
+
+impl AsValue for ByteArray {
+    fn as_value(&self) -> Value<'_> {
+        Value::Bytes(self.data())
+    }
+}
+
+impl AsValue for FixedLenByteArray {
+    fn as_value(&self) -> Value<'_> {
+        Value::Bytes(self.data())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, path::Path, sync::Arc};
+
+    use parquet::{
+        column::writer::ColumnWriter,
+        data_type::ByteArray,
+        file::{
+            properties::WriterProperties,
+            reader::{FileReader, SerializedFileReader},
+            writer::{FileWriter, SerializedFileWriter},
+        },
+        schema::parser::parse_message_type,
+    };
+    use structopt::StructOpt;
+    use tempfile::tempdir;
+
+    use super::{filter, read_chunks};
+    use crate::{output_path::OutputExists, predicate::Value, FilterOpt};
+
+    /// Writes a row group for each element of `row_groups`, with an optional `INT64` column `a`
+    /// and a required text column `b` holding the text representation of `a`.
+    fn write(path: &Path, row_groups: &[&[Option<i64>]]) {
+        let schema = parse_message_type(
+            "message schema { OPTIONAL INT64 a; REQUIRED BYTE_ARRAY b (UTF8); }",
+        )
+        .unwrap();
+        // Hashing the values for the dictionary reads them as `u32`, which fails the alignment
+        // check of debug builds for values sliced out of a page. So the filter would fail to
+        // write a dictionary encoded copy of them.
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_dictionary_enabled(false)
+                .build(),
+        );
+        let mut writer =
+            SerializedFileWriter::new(File::create(path).unwrap(), Arc::new(schema), properties)
+                .unwrap();
+        for rows in row_groups {
+            let values: Vec<i64> = rows.iter().flatten().copied().collect();
+            let def_levels: Vec<i16> = rows.iter().map(|row| row.is_some() as i16).collect();
+            let texts: Vec<ByteArray> = rows
+                .iter()
+                .map(|row| format!("{:?}", row).into_bytes().into())
+                .collect();
+            let mut row_group_writer = writer.next_row_group().unwrap();
+            let mut column_writer = row_group_writer.next_column().unwrap().unwrap();
+            if let ColumnWriter::Int64ColumnWriter(cw) = &mut column_writer {
+                cw.write_batch(&values, Some(&def_levels), None).unwrap();
+            }
+            row_group_writer.close_column(column_writer).unwrap();
+            let mut column_writer = row_group_writer.next_column().unwrap().unwrap();
+            if let ColumnWriter::ByteArrayColumnWriter(cw) = &mut column_writer {
+                cw.write_batch(&texts, None, None).unwrap();
+            }
+            row_group_writer.close_column(column_writer).unwrap();
+            writer.close_row_group(row_group_writer).unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    /// Values of column `b` in each row group of the file.
+    fn read(path: &Path) -> Vec<Vec<String>> {
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        (0..reader.num_row_groups())
+            .map(|index| {
+                let row_group = reader.get_row_group(index).unwrap();
+                let num_rows = row_group.metadata().num_rows() as usize;
+                let chunks = read_chunks(row_group.as_ref()).unwrap();
+                (0..num_rows)
+                    .map(|row| match chunks[1].value(row) {
+                        Some(Value::Bytes(bytes)) => String::from_utf8(bytes.to_vec()).unwrap(),
+                        other => panic!("Unexpected value {:?}", other),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn opt(input: &Path, output: &Path, args: &[&str]) -> FilterOpt {
+        let paths = [input.to_str().unwrap(), output.to_str().unwrap()];
+        FilterOpt::from_iter_safe(["filter"].iter().chain(&paths).chain(args)).unwrap()
+    }
+
+    #[test]
+    fn filter_rows() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("in.par");
+        let output = dir.path().join("out.par");
+        write(
+            &input,
+            &[
+                &[Some(1), Some(2), Some(3)],
+                &[Some(10), None, Some(12)],
+                &[Some(50)],
+            ],
+        );
+
+        filter(&opt(&input, &output, &["--where", "a >= 2 AND a < 11"])).unwrap();
+
+        // The last row group is skipped based on its statistics, the first two are filtered.
+        assert_eq!(
+            vec![vec!["Some(2)", "Some(3)"], vec!["Some(10)"]],
+            read(&output)
+        );
+        assert!(!dir.path().join("out.par.tmp").exists());
+
+        let output = dir.path().join("nulls.par");
+        filter(&opt(&input, &output, &["--where", "a IS NULL"])).unwrap();
+        assert_eq!(vec![vec!["None"]], read(&output));
+    }
+
+    #[test]
+    fn refuse_to_overwrite() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("in.par");
+        let output = dir.path().join("out.par");
+        write(&input, &[&[Some(1), Some(2)]]);
+        write(&output, &[&[Some(3)]]);
+
+        let error = filter(&opt(&input, &output, &["--where", "a = 1"])).unwrap_err();
+        assert!(error.is::<OutputExists>());
+        assert_eq!(vec![vec!["Some(3)"]], read(&output));
+
+        filter(&opt(&input, &output, &["--where", "a = 1", "--overwrite"])).unwrap();
+        assert_eq!(vec![vec!["Some(1)"]], read(&output));
+    }
+
+    #[test]
+    fn failing_filter_leaves_no_output() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("in.par");
+        let output = dir.path().join("out.par");
+        write(&input, &[&[Some(1)]]);
+
+        assert!(filter(&opt(&input, &output, &["--where", "x = 1"])).is_err());
+        assert!(!output.exists());
+        assert!(!dir.path().join("out.par.tmp").exists());
+    }
+}
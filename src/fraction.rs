@@ -0,0 +1,156 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use log::warn;
+use odbc_api::sys::Timestamp;
+
+/// How the ODBC driver fills the `fraction` field of timestamps. The ODBC specification demands
+/// nanoseconds, yet some drivers put the fractional digits there as they are, e.g. `123` for
+/// `.123` seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FractionUnit {
+    /// As demanded by the specification.
+    Nanoseconds,
+    /// Nanoseconds, but warn if the fractions of the first batch look like raw digits.
+    Auto,
+    /// The given number of fractional digits.
+    Digits(u32),
+}
+
+impl FromStr for FractionUnit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nanoseconds" => Ok(FractionUnit::Nanoseconds),
+            "auto" => Ok(FractionUnit::Auto),
+            other => match other.strip_prefix("digits:").map(str::parse) {
+                Some(Ok(digits @ 1..=9)) => Ok(FractionUnit::Digits(digits)),
+                Some(_) => bail!(
+                    "Invalid number of digits in '{}'. Must be between 1 and 9.",
+                    other
+                ),
+                None => bail!(
+                    "Unknown timestamp fraction unit '{}'. Supported are `nanoseconds`, `auto` \
+                    and `digits:<n>`.",
+                    other
+                ),
+            },
+        }
+    }
+}
+
+/// Copies the timestamps with their fractions of `digits` fractional digits converted to
+/// nanoseconds. Only needed for `FractionUnit::Digits`, since all other units are nanoseconds
+/// already.
+pub fn scale_digits<'a>(
+    digits: u32,
+    timestamps: impl Iterator<Item = Option<&'a Timestamp>>,
+) -> Result<Vec<Option<Timestamp>>, Error> {
+    timestamps
+        .map(|ts| {
+            ts.map(|&ts| {
+                if ts.fraction >= 10u32.pow(digits) {
+                    bail!(
+                        "Fraction {} of timestamp has more than {} digits. Check \
+                        `--timestamp-fraction-unit`.",
+                        ts.fraction,
+                        digits
+                    )
+                }
+                Ok(Timestamp {
+                    fraction: ts.fraction * 10u32.pow(9 - digits),
+                    ..ts
+                })
+            })
+            .transpose()
+        })
+        .collect()
+}
+
+/// Warns if the fractions of a timestamp column look like raw digits rather than nanoseconds.
+/// This is the case if all of them are below 1000 and not all are zero. A driver following the
+/// specification can only produce such fractions for columns with seven or more fractional digits.
+///
+/// Returns `true` if a warning has been emitted.
+pub fn warn_if_implausible<'a>(
+    column: &str,
+    precision: i16,
+    timestamps: impl Iterator<Item = Option<&'a Timestamp>>,
+) -> bool {
+    if precision >= 7 {
+        return false;
+    }
+    let mut any_non_zero = false;
+    for fraction in timestamps.flatten().map(|ts| ts.fraction) {
+        if fraction >= 1000 {
+            return false;
+        }
+        any_non_zero |= fraction != 0;
+    }
+    if any_non_zero {
+        warn!(
+            "Fractional seconds of column '{}' are all below 1000 nanoseconds, although it has \
+            only {} fractional digits. The ODBC driver likely reports the digits rather than \
+            nanoseconds. Consider `--timestamp-fraction-unit digits:{}`.",
+            column, precision, precision
+        );
+    }
+    any_non_zero
+}
+
+#[cfg(test)]
+mod tests {
+    use odbc_api::sys::Timestamp;
+
+    use super::{scale_digits, warn_if_implausible, FractionUnit};
+
+    fn ts(fraction: u32) -> Timestamp {
+        Timestamp {
+            year: 2021,
+            month: 3,
+            day: 4,
+            hour: 5,
+            minute: 6,
+            second: 7,
+            fraction,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(FractionUnit::Auto, "auto".parse().unwrap());
+        assert_eq!(FractionUnit::Digits(3), "digits:3".parse().unwrap());
+        assert!("digits:0".parse::<FractionUnit>().is_err());
+        assert!("digits:10".parse::<FractionUnit>().is_err());
+        assert!("micros".parse::<FractionUnit>().is_err());
+    }
+
+    #[test]
+    fn scale_raw_digits() {
+        let timestamps = [Some(ts(123)), None, Some(ts(5))];
+        let normalized = scale_digits(3, timestamps.iter().map(Option::as_ref)).unwrap();
+        assert_eq!(
+            vec![Some(123_000_000), None, Some(5_000_000)],
+            normalized
+                .iter()
+                .map(|ts| ts.map(|ts| ts.fraction))
+                .collect::<Vec<_>>()
+        );
+
+        let too_many_digits = [Some(ts(1234))];
+        assert!(scale_digits(3, too_many_digits.iter().map(Option::as_ref)).is_err());
+    }
+
+    #[test]
+    fn detect_raw_digits() {
+        let implausible = |precision, fractions: &[Option<u32>]| {
+            let timestamps: Vec<_> = fractions.iter().map(|f| f.map(ts)).collect();
+            warn_if_implausible("a", precision, timestamps.iter().map(Option::as_ref))
+        };
+        assert!(implausible(3, &[Some(123), None, Some(0)]));
+        assert!(!implausible(3, &[Some(123_000_000), Some(0)]));
+        assert!(!implausible(3, &[Some(0), None]));
+        assert!(!implausible(7, &[Some(123)]));
+    }
+}
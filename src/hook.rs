@@ -0,0 +1,318 @@
+use std::{
+    fmt, fs,
+    io::Read,
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Error};
+use log::{debug, info, warn};
+
+/// How often we check whether a hook command has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A command executed each time an output file has been completed, e.g. to notify an ingestion
+/// pipeline.
+pub struct FileHook {
+    /// Command line, interpreted by the shell of the operating system.
+    pub command: String,
+    /// Kill the command, if it did not finish within this time. `None` means waiting forever.
+    pub timeout: Option<Duration>,
+    /// If `true` a failing hook causes the export to fail. Otherwise failures are only logged.
+    pub failure_aborts: bool,
+}
+
+impl FileHook {
+    /// Run the hook for a completed output file. Path, number of rows and size in bytes are passed
+    /// as positional arguments, as well as in the environment variables `ODBC2PARQUET_PATH`,
+    /// `ODBC2PARQUET_NUM_ROWS` and `ODBC2PARQUET_NUM_BYTES`.
+    pub fn on_file_complete(&self, path: &Path, num_rows: u64) -> Result<(), Error> {
+        let num_bytes = path.metadata()?.len();
+        info!(
+            "Running hook for '{}' with {} rows and {} bytes.",
+            path.display(),
+            num_rows,
+            num_bytes
+        );
+        match self.execute(path, num_rows, num_bytes) {
+            Ok(()) => Ok(()),
+            Err(error) if self.failure_aborts => Err(error),
+            Err(error) => {
+                warn!("{}", error);
+                Ok(())
+            }
+        }
+    }
+
+    fn execute(&self, path: &Path, num_rows: u64, num_bytes: u64) -> Result<(), Error> {
+        let num_rows = num_rows.to_string();
+        let num_bytes = num_bytes.to_string();
+        let mut command = shell_command(&self.command);
+        command
+            .arg(path)
+            .arg(&num_rows)
+            .arg(&num_bytes)
+            .env("ODBC2PARQUET_PATH", path)
+            .env("ODBC2PARQUET_NUM_ROWS", &num_rows)
+            .env("ODBC2PARQUET_NUM_BYTES", &num_bytes);
+        match run(command, self.timeout)? {
+            Some(status) if status.success() => Ok(()),
+            Some(status) => bail!(
+                "Hook '{}' for '{}' failed with {}.",
+                self.command,
+                path.display(),
+                status
+            ),
+            None => bail!(
+                "Hook '{}' for '{}' did not finish in time and has been killed.",
+                self.command,
+                path.display()
+            ),
+        }
+    }
+}
+
+/// A command handing each completed output file over to another system, e.g. a distributed file
+/// system.
+pub struct Upload {
+    /// Command line, interpreted by the shell of the operating system. Each `{}` is replaced with
+    /// the quoted path of the file.
+    pub command: String,
+    /// Remove the local file, once it has been uploaded.
+    pub remove_after_upload: bool,
+}
+
+impl Upload {
+    /// Run the upload command for a completed output file. The path is also passed as positional
+    /// argument and in the environment variable `ODBC2PARQUET_PATH`.
+    pub fn upload(&self, path: &Path) -> Result<(), Error> {
+        info!("Uploading '{}'.", path.display());
+        let command_line = self.command.replace("{}", &quote(path));
+        let mut command = shell_command(&command_line);
+        command.arg(path).env("ODBC2PARQUET_PATH", path);
+        match run(command, None)? {
+            Some(status) if status.success() => Ok(()),
+            status => Err(UploadFailed {
+                command: command_line,
+                status,
+            }
+            .into()),
+        }
+    }
+
+    /// Remove the local copy of an uploaded file, if requested.
+    pub fn remove_local(&self, path: &Path) -> Result<(), Error> {
+        if self.remove_after_upload {
+            debug!("Removing uploaded file '{}'.", path.display());
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// An output file could not be uploaded. Distinguished from other errors, so the process can exit
+/// with a dedicated code.
+#[derive(Debug)]
+pub struct UploadFailed {
+    command: String,
+    /// `None` if the command did not terminate on its own.
+    status: Option<ExitStatus>,
+}
+
+impl fmt::Display for UploadFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "Upload '{}' failed with {}.", self.command, status),
+            None => write!(f, "Upload '{}' has been terminated.", self.command),
+        }
+    }
+}
+
+impl std::error::Error for UploadFailed {}
+
+/// Runs the command and logs its output at debug level. `None` if it had to be killed due to the
+/// timeout.
+fn run(mut command: Command, timeout: Option<Duration>) -> Result<Option<ExitStatus>, Error> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+
+    // Read the output in separate threads, so the child does not block on a full pipe while we
+    // wait for it.
+    let stdout = capture(child.stdout.take());
+    let stderr = capture(child.stderr.take());
+    let status = wait(&mut child, timeout)?;
+    // Processes started by a killed hook may still hold on to the pipes, so we only wait for
+    // the output of hooks which finished on their own.
+    if status.is_some() {
+        log_output("stdout", stdout);
+        log_output("stderr", stderr);
+    }
+    Ok(status)
+}
+
+/// Wait for the child to finish. `None` if it had to be killed due to the timeout.
+fn wait(child: &mut Child, timeout: Option<Duration>) -> Result<Option<ExitStatus>, Error> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(Some(child.wait()?)),
+    };
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn capture(pipe: Option<impl Read + Send + 'static>) -> Option<JoinHandle<String>> {
+    pipe.map(|mut pipe| {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            // Whatever we could read is good enough for a log message.
+            let _ = pipe.read_to_end(&mut bytes);
+            String::from_utf8_lossy(&bytes).into_owned()
+        })
+    })
+}
+
+fn log_output(name: &str, output: Option<JoinHandle<String>>) {
+    if let Some(output) = output.and_then(|handle| handle.join().ok()) {
+        for line in output.lines() {
+            debug!("Hook {}: {}", name, line);
+        }
+    }
+}
+
+/// Path as a single argument on the command line of the shell.
+#[cfg(windows)]
+fn quote(path: &Path) -> String {
+    format!("\"{}\"", path.display())
+}
+
+/// Path as a single argument on the command line of the shell.
+#[cfg(not(windows))]
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
+
+#[cfg(windows)]
+fn shell_command(command_line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(command_line);
+    command
+}
+
+#[cfg(not(windows))]
+fn shell_command(command_line: &str) -> Command {
+    // Additional arguments are available as `$1`, `$2`, ... in the command line. The first one
+    // would be `$0`, so we pass the name of the shell there.
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(command_line).arg("sh");
+    command
+}
+
+// The commands of the tests are written for a POSIX shell.
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::tempdir;
+
+    use super::{FileHook, Upload, UploadFailed};
+
+    fn hook(command: &str, timeout: Option<Duration>) -> FileHook {
+        FileHook {
+            command: command.to_owned(),
+            timeout,
+            failure_aborts: true,
+        }
+    }
+
+    #[test]
+    fn quote_paths_with_spaces_and_quotes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("it's an output.par");
+        fs::write(&path, "").unwrap();
+        // Compares the substituted path with the one passed as positional argument.
+        let upload = Upload {
+            command: "test -f {} && test {} = \"$1\"".to_owned(),
+            remove_after_upload: true,
+        };
+        upload.upload(&path).unwrap();
+        upload.remove_local(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn failing_upload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.par");
+        let upload = Upload {
+            command: "test -f {}".to_owned(),
+            remove_after_upload: false,
+        };
+        let error = upload.upload(&path).unwrap_err();
+        assert!(error.is::<UploadFailed>());
+    }
+
+    #[test]
+    fn pass_file_to_hook() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.par");
+        fs::write(&path, "abc").unwrap();
+        let command = "test \"$1\" = \"$ODBC2PARQUET_PATH\" && test \"$2 $3\" = '42 3' \
+            && test \"$ODBC2PARQUET_NUM_ROWS $ODBC2PARQUET_NUM_BYTES\" = '42 3'";
+        hook(command, None).on_file_complete(&path, 42).unwrap();
+    }
+
+    #[test]
+    fn kill_hook_after_timeout() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.par");
+        fs::write(&path, "").unwrap();
+        let start = Instant::now();
+        let error = hook("sleep 10", Some(Duration::from_millis(100)))
+            .on_file_complete(&path, 0)
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(
+            error.to_string().contains("did not finish in time"),
+            "{}",
+            error
+        );
+
+        // Hooks finishing in time are not affected by the timeout.
+        hook("exit 0", Some(Duration::from_secs(10)))
+            .on_file_complete(&path, 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn failing_hook_only_aborts_if_requested() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.par");
+        fs::write(&path, "").unwrap();
+        let error = hook("exit 3", None).on_file_complete(&path, 0).unwrap_err();
+        assert!(error.to_string().contains("failed with"), "{}", error);
+
+        let lenient = FileHook {
+            failure_aborts: false,
+            ..hook("exit 3", None)
+        };
+        lenient.on_file_complete(&path, 0).unwrap();
+    }
+}
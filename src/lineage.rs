@@ -0,0 +1,103 @@
+//! Key value metadata describing how a file has been produced, so its lineage can be traced
+//! without knowing the command line. Written into the footer of every file, including each part
+//! of a split output.
+
+use chrono::Local;
+use parquet::file::metadata::KeyValue;
+
+use crate::{ConnectOpts, QueryOpt};
+
+/// Keys of connection string attributes, which are dropped from the data source. Compared case
+/// insensitively.
+const CREDENTIAL_KEYS: &[&str] = &["uid", "user", "user id", "username", "pwd", "password"];
+
+/// Query text and data source. Unless `reproducible`, also the version of odbc2parquet and the
+/// time of the export, since both change between runs.
+pub fn lineage_metadata(opt: &QueryOpt) -> Vec<KeyValue> {
+    let entry = |key: &str, value: String| KeyValue::new(format!("odbc2parquet.{}", key), value);
+    let mut entries = vec![
+        entry("query", opt.query.clone()),
+        entry("data_source", data_source(&opt.connect_opts)),
+    ];
+    if !opt.reproducible {
+        entries.push(entry("version", env!("CARGO_PKG_VERSION").to_owned()));
+        entries.push(entry("exported_at", Local::now().to_rfc3339()));
+    }
+    entries
+}
+
+/// Data source as a connection string without credentials, e.g. `DSN=sales;` for `--dsn sales`.
+fn data_source(opt: &ConnectOpts) -> String {
+    match (&opt.dsn, &opt.connection_string) {
+        (Some(dsn), _) => format!("DSN={};", dsn),
+        (None, Some(connection_string)) => strip_credentials(connection_string),
+        (None, None) => String::new(),
+    }
+}
+
+/// Removes user names, passwords and secrets from a connection string. Values may be enclosed in
+/// braces, in order to contain `;`.
+fn strip_credentials(connection_string: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = connection_string;
+    while !rest.is_empty() {
+        let attribute = &rest[..attribute_len(rest)];
+        rest = rest[attribute.len()..].trim_start_matches(';');
+        let key = attribute.split('=').next().unwrap_or_default().trim();
+        if key.is_empty() || is_credential(key) {
+            continue;
+        }
+        stripped.push_str(attribute);
+        stripped.push(';');
+    }
+    stripped
+}
+
+/// Length of the first attribute of `text` in bytes, up to the `;` terminating it.
+fn attribute_len(text: &str) -> usize {
+    let mut in_braces = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '{' if !in_braces => in_braces = true,
+            // `}}` is an escaped brace within braces.
+            '}' if in_braces && chars.peek().map(|&(_, c)| c) == Some('}') => {
+                chars.next();
+            }
+            '}' if in_braces => in_braces = false,
+            ';' if !in_braces => return index,
+            _ => (),
+        }
+    }
+    text.len()
+}
+
+fn is_credential(key: &str) -> bool {
+    let key = key.to_lowercase();
+    CREDENTIAL_KEYS.contains(&key.as_str())
+        || ["password", "secret", "token"]
+            .iter()
+            .any(|part| key.contains(part))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_credentials;
+
+    #[test]
+    fn credentials_are_stripped() {
+        assert_eq!(
+            "Driver={ODBC Driver 17 for SQL Server};Server=localhost;",
+            strip_credentials(
+                "Driver={ODBC Driver 17 for SQL Server};Server=localhost;UID=SA;\
+                PWD=<YourStrong@Passw0rd>;"
+            )
+        );
+        // Braces may contain `;` and escaped braces.
+        assert_eq!(
+            "Server=db;",
+            strip_credentials("Password={a;b}}c};Server=db;user id=x;AccessToken=t")
+        );
+        assert_eq!("DSN=sales;", strip_credentials("DSN=sales"));
+    }
+}
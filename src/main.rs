@@ -1,10 +1,88 @@
+mod append;
+mod budget;
+mod column_mapping;
+mod compression;
+mod deadline;
+mod dedupe;
+mod diagnostics;
+mod environment;
+mod estimate;
+mod explain;
+#[cfg(test)]
+mod fake;
+mod fetch;
+mod field_id;
+mod filter;
+mod fraction;
+mod hook;
+mod lineage;
+mod mask;
+mod metrics;
+mod null_default;
+mod output_path;
 mod parquet_buffer;
+mod predicate;
+mod profile;
 mod query;
+mod sampling;
+mod size;
+mod strict;
+mod summary;
+mod time;
+mod timestamp;
+mod user_metadata;
+mod validate;
 
 use anyhow::{bail, Error};
+use compression::parse_compression;
+use deadline::parse_duration;
+use diagnostics::DedupeDiagnostics;
+use environment::odbc_environment;
+use explain::ExplainFormat;
+use field_id::FieldId;
+use fraction::FractionUnit;
+use hook::UploadFailed;
+use log::LevelFilter;
+use mask::ColumnMask;
+use null_default::NullDefault;
 use odbc_api::{Connection, Environment};
-use std::path::PathBuf;
-use structopt::StructOpt;
+use output_path::{OutputExists, OutputPath};
+use parquet::basic::Compression;
+use parquet_buffer::ConversionErrorPolicy;
+use predicate::Predicate;
+use query::{EmptyResultSet, NoResultSet};
+use sampling::SampleRate;
+use size::{parse_byte_size, parse_count};
+use std::{
+    env,
+    io::{self, IsTerminal},
+    path::PathBuf,
+    process,
+    time::Duration,
+};
+use structopt::{
+    clap::{self, ErrorKind},
+    StructOpt,
+};
+use time::TimePrecision;
+use timestamp::TimestampPrecision;
+use user_metadata::UserMetadata;
+
+/// Exit code if the export completed, but parts of the result set have been left out. E.g. due to
+/// `--skip-unsupported-columns` or `--max-duration`.
+const EXIT_COMPLETED_WITH_WARNINGS: i32 = 2;
+
+/// Exit code if an output file could not be handed over with `--upload-command`.
+const EXIT_UPLOAD_FAILED: i32 = 3;
+
+/// Exit code if the statement did not produce a result set, e.g. because it is an `UPDATE`.
+const EXIT_NO_RESULT_SET: i32 = 4;
+
+/// Exit code if an output file exists already, and `--overwrite` has not been given.
+const EXIT_OUTPUT_EXISTS: i32 = 5;
+
+/// Exit code if no file has been written with `--no-empty-file`, since the result set is empty.
+const EXIT_EMPTY_RESULT_SET: i32 = 6;
 
 /// Query an ODBC data source at store the result in a Parquet file.
 #[derive(StructOpt)]
@@ -16,6 +94,8 @@ struct Cli {
     command: Command,
 }
 
+// Parsed only once at startup, so the size difference between variants does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(StructOpt)]
 enum Command {
     /// Query a data source and write the result as parquet.
@@ -23,6 +103,12 @@ enum Command {
         #[structopt(flatten)]
         query_opt: QueryOpt,
     },
+    /// Copy the rows of a parquet file satisfying a predicate into a new file. Does not connect
+    /// to any data source.
+    Filter {
+        #[structopt(flatten)]
+        filter_opt: FilterOpt,
+    },
     /// List available drivers and their attributes.
     ListDrivers,
     /// List preconfigured data sources. Useful to find data source name to connect to database.
@@ -40,12 +126,14 @@ struct ConnectOpts {
     /// the datasource. Data source name (dsn) and connection string, may not be specified both.
     #[structopt(long, conflicts_with = "connection-string")]
     dsn: Option<String>,
-    /// User used to access the datasource specified in dsn.
-    #[structopt(long, short = "u", env = "ODBC_USER")]
+    /// User used to access the datasource specified in dsn. Defaults to the environment variable
+    /// `ODBC_USER`. Can not be combined with a connection string, which holds the user itself.
+    #[structopt(long, short = "u")]
     user: Option<String>,
-    /// Password used to log into the datasource. Only used if dsn is specified, instead of a
-    /// connection string.
-    #[structopt(long, short = "p", env = "ODBC_PASSWORD", hide_env_values = true)]
+    /// Password used to log into the datasource specified in dsn. Defaults to the environment
+    /// variable `ODBC_PASSWORD`. Can not be combined with a connection string, which holds the
+    /// password itself.
+    #[structopt(long, short = "p")]
     password: Option<String>,
 }
 
@@ -55,48 +143,449 @@ pub struct QueryOpt {
     connect_opts: ConnectOpts,
     /// Size of a single batch in rows. The content of the data source is written into the output
     /// parquet files in batches. This way the content does never need to be materialized completely
-    /// in memory at once.
-    #[structopt(long, default_value = "100000")]
-    batch_size: u32,
+    /// in memory at once. Like all counts and sizes, it may be specified with a suffix, e.g.
+    /// `100k` or `64Ki`, and with underscores as separators, e.g. `100_000`. Defaults to 100000,
+    /// unless `--batch-memory` is specified.
+    #[structopt(long, parse(try_from_str = parse_count))]
+    batch_size: Option<u32>,
+    /// Choose the number of rows per batch, so the buffers bound for fetching take at most this
+    /// many bytes. The size of each row is derived from the column types and lengths reported by
+    /// the driver. If `--batch-size` is specified, too, the smaller number of rows wins. The
+    /// derived batch size is logged at info level. E.g. `2GiB`.
+    #[structopt(long, parse(try_from_str = parse_byte_size))]
+    batch_memory: Option<u64>,
     /// Maximum number of batches in a single output parquet file. If this option is omitted or 0 a
-    /// single output file is produces. Otherwise each output file is closed after the maximum
+    /// single output file is produced. Otherwise each output file is closed after the maximum
     /// number of batches have been written and a new one with the suffix `_n` is started. There n
-    /// is the of the produced output file starting at one for the first one. E.g. `out_1.par`,
-    /// `out_2.par`, ...
-    #[structopt(long, default_value = "0")]
+    /// is the number of the produced output file starting at one for the first one, zero padded to
+    /// `--suffix-length` digits. E.g. `out_01.par`, `out_02.par`, ... If the result fits into a
+    /// single file, it is written to the output path without a suffix, unless `--always-suffix` is
+    /// specified. Unless `--row-group-per-batch` is specified, each row group counts as a batch.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_count))]
     batches_per_file: u32,
-    /// Name of the output parquet file.
-    output: PathBuf,
+    /// Start a new output file, once the current one has grown to at least this many bytes. Files
+    /// are only split between batches, so each file exceeds the threshold by up to the size of a
+    /// batch. Files are suffixed like with `--batches-per-file`, which may be combined with this.
+    /// No file is started without any rows. E.g. `512MiB`.
+    #[structopt(long, parse(try_from_str = parse_byte_size))]
+    file_size_threshold: Option<u64>,
+    /// Compression of the column chunks in the output. One of `uncompressed`, `snappy`, `gzip`,
+    /// `brotli`, `lz4` or `zstd`.
+    #[structopt(
+        long,
+        default_value = "uncompressed",
+        parse(try_from_str = parse_compression)
+    )]
+    column_compression_default: Compression,
+    /// Close a row group early, once the values written to it are estimated to exceed this number
+    /// of bytes. The parquet writer holds the current row group in memory, so this bounds its
+    /// memory usage independent of `--batch-size`. Fetched batches are split into several row
+    /// groups if necessary. E.g. `256MiB`.
+    #[structopt(long, parse(try_from_str = parse_byte_size))]
+    row_group_memory_limit: Option<u64>,
+    /// Maximum number of bytes the buffers for fetching a batch may take. They are allocated
+    /// before the first row is fetched, with room for the maximum length of each text column. If
+    /// the buffers would exceed this limit, the export fails right away, naming the largest
+    /// columns. Defaults to the memory available on the system, if it is known. The size of each
+    /// buffer is logged at debug level. E.g. `2GiB`.
+    #[structopt(long, parse(try_from_str = parse_byte_size))]
+    buffer_memory_limit: Option<u64>,
+    /// Maximum number of rows in a row group. Rows of consecutive batches are collected into the
+    /// same row group, until it holds this many rows or reaches `--row-group-memory-limit`.
+    /// Defaults to `--batch-size`, so full batches are written as row groups of their own, while
+    /// batches thinned out by `--sample-rate` or `--dedupe-on` are merged. Collected rows are
+    /// copied, so memory usage grows by up to this many rows.
+    #[structopt(
+        long,
+        conflicts_with = "row-group-per-batch",
+        parse(try_from_str = parse_count)
+    )]
+    rows_per_row_group: Option<u32>,
+    /// Write the rows of each fetched batch into a row group of their own, no matter how few of
+    /// them there are. Batches exceeding `--row-group-memory-limit` are still split.
+    #[structopt(long)]
+    row_group_per_batch: bool,
+    /// Suffix the name of the output file with `_01`, even if `--batches-per-file` produced only a
+    /// single file.
+    #[structopt(long)]
+    always_suffix: bool,
+    /// Minimum number of digits in the suffix of split output files. Shorter numbers are padded
+    /// with zeros, so the files sort by their number, e.g. `out_001.par` for 3. Longer numbers are
+    /// written in full, so the export never runs out of names. `1` disables the padding.
+    #[structopt(long, default_value = "2")]
+    suffix_length: usize,
+    /// Do not leave an output file behind, if the result set is empty, and exit with code 6. This
+    /// tells an empty result set apart from a failed export. Without it, a file with the schema of
+    /// the result set but no rows is written, even if the output is split into several files.
+    #[structopt(long, conflicts_with = "output-base64")]
+    no_empty_file: bool,
+    /// Add the rows as new row groups to the end of an existing output file, rather than
+    /// replacing it. The export fails before fetching any rows, if the columns of the query
+    /// differ from the ones in the file. Existing row groups are copied into a temporary file
+    /// next to the output, which replaces it once complete. Key value metadata is merged, with
+    /// values of this run taking precedence. Starts a new file, if the output does not exist yet.
+    #[structopt(
+        long,
+        conflicts_with_all = &["batches-per-file", "file-size-threshold", "output-base64"]
+    )]
+    append: bool,
+    /// Write each output file directly to its final path. By default it is written to a
+    /// temporary file next to it, e.g. `out.par.tmp`, which is renamed once the file is complete.
+    /// So a failing export does not leave a truncated file behind, and split files can be used
+    /// as soon as they appear. Use this, if renaming is expensive on the file system.
+    #[structopt(long)]
+    no_atomic: bool,
+    /// Replace output files which exist already. Without it, the export fails with exit code 5
+    /// before connecting to the data source, if the output exists. With `--batches-per-file` or
+    /// `--file-size-threshold` any file with a suffixed name is checked, too, e.g. `out_03.par`.
+    #[structopt(long, conflicts_with = "append")]
+    overwrite: bool,
+    /// Replace the values of a column before they are written to the output file, so they never
+    /// land on disk in plain text. Expects `column=method`. Supported methods are `sha256` (hex
+    /// digest of the values text representation), `null` and `fixed:<value>` (replace with a
+    /// constant). NULL values stay NULL. Masked columns are always written as UTF-8 text. May be
+    /// specified multiple times. The names of masked columns are recorded in the file metadata
+    /// under the key `odbc2parquet.masked_columns`.
+    #[structopt(long = "mask", number_of_values = 1)]
+    masks: Vec<ColumnMask>,
+    /// Write minimum and maximum of masked columns into the file footer. By default no
+    /// statistics are written for masked columns.
+    #[structopt(long)]
+    force_statistics: bool,
+    /// Write a timestamp column as two separate columns instead: `<column>_date` holding the date
+    /// and `<column>_time` holding the time of day in microseconds. May be specified multiple
+    /// times.
+    #[structopt(long = "split-timestamp", number_of_values = 1)]
+    split_timestamps: Vec<String>,
+    /// Write a value instead of NULL into a column. Expects `column=value`. The value must be
+    /// valid for the type of the column, e.g. `0`, `1.5`, `unknown`, `1970-01-01` or
+    /// `1970-01-01 00:00:00`. The column is declared as required in the parquet schema. May be
+    /// specified multiple times. The number of replaced NULLs is logged and part of the summary
+    /// file.
+    #[structopt(long = "null-default", number_of_values = 1)]
+    null_defaults: Vec<NullDefault>,
+    /// Set the field id of a column in the parquet schema. Expects `column=id`, with a positive
+    /// and unique id. Refers to the name of the field in the parquet schema, so split timestamps
+    /// are addressed as `<column>_date` and `<column>_time`. May be specified multiple times.
+    /// Table formats like Iceberg track columns by their field id.
+    #[structopt(long = "field-id", number_of_values = 1)]
+    field_ids: Vec<FieldId>,
+    /// Assign field ids `1` to `n` to the fields of the parquet schema, in the order of the result
+    /// set.
+    #[structopt(long, conflicts_with = "field-id")]
+    auto_field_ids: bool,
+    /// Leave out columns whose type can not be mapped to parquet, instead of failing the export.
+    /// Each skipped column is logged as a warning with the reason and listed in the summary file.
+    /// If any column has been skipped, the process exits with code 2 after completing the export.
+    #[structopt(long)]
+    skip_unsupported_columns: bool,
+    /// Fail the export instead of losing information. Turns every warning about rounded floats,
+    /// truncated fractional seconds, types falling back to text, ignored columns and approximate
+    /// deduplication into an error naming the column and the rule violated. Conflicts with options
+    /// which drop information on purpose.
+    #[structopt(long, conflicts_with = "skip-unsupported-columns")]
+    strict: bool,
+    /// Declare all columns as `OPTIONAL` in the parquet schema. By default columns the ODBC driver
+    /// reports as not nullable are `REQUIRED`, and the export fails if they hold a NULL anyway.
+    /// Use this for drivers which do not report nullability reliably.
+    #[structopt(long)]
+    nullable_all: bool,
+    /// Write GUID columns, e.g. `UNIQUEIDENTIFIER` in SQL Server, as `FIXED_LEN_BYTE_ARRAY(16)` in
+    /// the byte order of RFC 4122, rather than as text with 36 characters. The parquet library
+    /// used can not annotate the column with the `UUID` logical type yet, so readers see plain
+    /// bytes.
+    #[structopt(long)]
+    guid_as_bytes: bool,
+    /// Write `TIME` columns as UTF-8 text, like `14:05:32.1234567`, rather than as INT32
+    /// TIME_MILLIS or INT64 TIME_MICROS. Provided for consumers relying on the text output of
+    /// earlier versions.
+    #[structopt(long, conflicts_with = "time-precisions")]
+    time_as_text: bool,
+    /// What to do if a fetched value can not be represented in parquet, e.g. an invalid date.
+    /// `abort` fails the export. `null` writes NULL instead and logs a warning. With `null` all
+    /// date, time, timestamp and decimal columns are declared nullable in the parquet schema.
+    #[structopt(long, default_value = "abort")]
+    on_conversion_error: ConversionErrorPolicy,
+    /// Round decimals half away from zero, if the ODBC driver returns more digits after the
+    /// decimal point than the scale it reports for the column. By default such values are
+    /// conversion errors, since writing them as is would change their value by a power of ten.
+    #[structopt(long, conflicts_with = "strict")]
+    round_decimals: bool,
+    /// Unit of the fractional seconds of timestamps reported by the ODBC driver. The ODBC
+    /// specification demands `nanoseconds`, yet some drivers report the fractional digits as they
+    /// are, e.g. `123` for `.123` seconds. Use `digits:<n>` for these, with `n` the number of
+    /// fractional digits, e.g. `digits:3`. `auto` assumes nanoseconds, but warns if the fractions
+    /// of the first batch look like digits.
+    #[structopt(long, default_value = "nanoseconds")]
+    timestamp_fraction_unit: FractionUnit,
+    /// Precision of all timestamp columns: `milliseconds`, `microseconds` or `nanoseconds`. By
+    /// default timestamps with up to 3 fractional digits are written as INT64 TIMESTAMP_MILLIS,
+    /// all others as TIMESTAMP_MICROS. `nanoseconds` keeps all digits of e.g. `DATETIME2(7)`, but
+    /// is written as plain INT64, since the parquet library used lacks a logical type for it.
+    /// Timestamps beyond the years 1677 to 2262 can not be written in nanoseconds.
+    #[structopt(long)]
+    timestamp_precision: Option<TimestampPrecision>,
+    /// Number of fractional second digits of a time column, overriding the one reported by the
+    /// driver. Expects `column=digits`. Times with up to 3 digits are written as INT32
+    /// TIME_MILLIS, all others as INT64 TIME_MICROS. May be specified multiple times.
+    #[structopt(long = "time-precision", number_of_values = 1)]
+    time_precisions: Vec<TimePrecision>,
+    /// Only write a random sample of the rows. E.g. `0.01` keeps roughly one percent of them.
+    /// Sampling happens on the client side, so the data source still has to transfer every row of
+    /// the result set. Use a sampling clause in the query itself, if this is too expensive.
+    #[structopt(long)]
+    sample_rate: Option<SampleRate>,
+    /// Seed for the random decisions made by `--sample-rate`. The same seed yields the same
+    /// sample, as long as the data source returns the rows in the same order. If omitted a seed
+    /// is derived from the current time and logged at info level.
+    #[structopt(long, requires = "sample-rate")]
+    sample_seed: Option<u64>,
+    /// Write byte identical files for identical result sets. Pins the `created_by` field of the
+    /// footer to `odbc2parquet`, rather than the version of the parquet library, and orders the
+    /// key value metadata independent of the order of the options. Leaves the version of
+    /// odbc2parquet and the time of the export out of the metadata. Requires `--sample-seed` if
+    /// sampling.
+    #[structopt(long)]
+    reproducible: bool,
+    /// Do not write the query text, the data source, the version of odbc2parquet and the time of
+    /// the export into the key value metadata of the footer. By default they are recorded in
+    /// every file, as `odbc2parquet.query`, `odbc2parquet.data_source`, `odbc2parquet.version` and
+    /// `odbc2parquet.exported_at`. User names and passwords are stripped from the data source.
+    /// With `--reproducible` the version and the time of the export are left out anyway.
+    #[structopt(long)]
+    no_query_metadata: bool,
+    /// Add a key value pair to the metadata in the footer of each file. Expects `key=value`. Only
+    /// the first `=` separates the key from the value, so the value may contain `=`. May be
+    /// specified multiple times, but only once for each key. Replaces generated entries with the
+    /// same key, e.g. `odbc2parquet.query`.
+    #[structopt(long = "metadata", number_of_values = 1)]
+    metadata: Vec<UserMetadata>,
+    /// Drop rows whose values in these columns have already been seen earlier during the export.
+    /// Only the first row with a given key is written. Supported key columns are integers, text
+    /// and dates. E.g. `--dedupe-on customer_id,order_date`.
+    ///
+    /// Seen keys are kept in memory as 16 byte fingerprints. Beyond `--dedupe-max-keys` distinct
+    /// keys they are moved into a bloom filter of fixed size instead, taking about 10 bits per key.
+    /// A bloom filter may mistake a new key for one already seen, so from this point on rows with
+    /// unique keys may be dropped as well. Up to `--dedupe-max-keys` keys this happens for less
+    /// than one percent of them. The more keys, the more likely this gets.
+    #[structopt(long, use_delimiter = true)]
+    dedupe_on: Vec<String>,
+    /// Number of distinct keys `--dedupe-on` remembers exactly, before switching to a bloom
+    /// filter.
+    #[structopt(long, default_value = "10000000", parse(try_from_str = parse_count))]
+    dedupe_max_keys: usize,
+    /// Print how each column of the result set is mapped to parquet and exit, without fetching
+    /// any rows or writing any file. The only supported format is `json`. For each column it
+    /// lists the type reported by the driver, the options overriding its mapping, the bound ODBC
+    /// buffer, the resulting parquet fields and warnings about conversions which may lose
+    /// information. The format is versioned and stable, so the output of two runs can be compared
+    /// to detect changes in the mapping.
+    #[structopt(long, conflicts_with_all = &["output-base64", "profile"])]
+    explain_mapping: Option<ExplainFormat>,
+    /// Estimate number of rows, output size and duration before starting the export and ask for
+    /// confirmation. Rows are counted with an additional `SELECT COUNT(*)` wrapping the query.
+    /// Queries which can not be wrapped safely, e.g. due to `TOP`, `LIMIT` or `ORDER BY`, are not
+    /// counted. Sizes assume typical encoding gains for each type and are rough. Printed to
+    /// standard out, so it can not be combined with --output-base64.
+    #[structopt(long, conflicts_with_all = &["explain-mapping", "output-base64"])]
+    estimate: bool,
+    /// Do not ask for confirmation after printing the estimate.
+    #[structopt(long, requires = "estimate")]
+    yes: bool,
+    /// Compute statistics for each column while fetching the result set and print them as a
+    /// table to standard out: Number of NULLs, minimum and maximum of numbers and dates, maximum
+    /// length of text and an approximate number of distinct values. Memory usage does not depend
+    /// on the size of the result set. Combine with `--no-write` to only profile the data.
+    #[structopt(long)]
+    profile: bool,
+    /// Fetch the result set, but do not create any output file. Useful together with `--profile`.
+    #[structopt(long)]
+    no_write: bool,
+    /// Write a summary of the export as JSON to this path. Includes the number of fetched and
+    /// written rows, as well as the column statistics if `--profile` is specified.
+    #[structopt(long)]
+    summary_file: Option<PathBuf>,
+    /// Stop fetching once another batch would not be done within this time after the start, e.g.
+    /// `30m`. The prediction goes by the slowest batch so far. Rows fetched up to this point are
+    /// written and the output is completed properly. Accepts the units `s`, `m` and `h`. The
+    /// export then exits with code 2 and the summary file reports `"time_boxed": true`.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    max_duration: Option<Duration>,
+    /// Write the durations of fetching, converting and writing as JSON to this path. Includes
+    /// the median, 90th and 99th percentile and maximum of each stage, as well as the rows fetched
+    /// per second. Intended for tracking performance across versions.
+    #[structopt(long)]
+    metrics_file: Option<PathBuf>,
+    /// Command executed each time an output file is completed. It is interpreted by the shell (`sh`
+    /// or `cmd`). Path, number of rows and size in bytes of the file are appended as arguments and
+    /// are also available in the environment variables `ODBC2PARQUET_PATH`,
+    /// `ODBC2PARQUET_NUM_ROWS` and `ODBC2PARQUET_NUM_BYTES`. The export waits for the command to
+    /// finish. Its output is logged at debug level.
+    #[structopt(long)]
+    on_file_complete: Option<String>,
+    /// Maximum time to wait for the `--on-file-complete` command, before killing it, e.g. `30s`.
+    /// Accepts the units `s`, `m` and `h`. Plain numbers are seconds. If omitted, there is no
+    /// time limit.
+    #[structopt(long, requires = "on-file-complete", parse(try_from_str = parse_duration))]
+    hook_timeout: Option<Duration>,
+    /// Abort the export if the `--on-file-complete` command fails or times out. By default a
+    /// failing command only causes a warning.
+    #[structopt(long, requires = "on-file-complete")]
+    hook_failure_aborts: bool,
+    /// Command handing each completed output file over to another system, e.g. `hdfs dfs -put {}
+    /// /landing/`. It is interpreted by the shell (`sh` or `cmd`), with each `{}` replaced by the
+    /// quoted path of the file. The path is also appended as argument and available in the
+    /// environment variable `ODBC2PARQUET_PATH`. The export waits for the command to finish before
+    /// continuing and fails with exit code 3 if it does not succeed. Runs before
+    /// `--on-file-complete`. Uploaded files are listed in the summary file.
+    #[structopt(long)]
+    upload_command: Option<String>,
+    /// Remove each output file once it has been uploaded with `--upload-command` and
+    /// `--on-file-complete` has run. Together with `--batches-per-file` this bounds the disk space
+    /// needed locally.
+    #[structopt(long, requires = "upload-command")]
+    remove_after_upload: bool,
+    /// Keep the parquet output in memory and print it base64 encoded to standard out, instead of
+    /// writing it to a file. Intended for small results, which are processed by another program.
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "batches-per-file",
+            "file-size-threshold",
+            "no-write",
+            "on-file-complete",
+            "profile",
+            "upload-command"
+        ]
+    )]
+    output_base64: bool,
+    /// Maximum size in bytes of the output written with `--output-base64`. The export fails, if
+    /// the output grows larger.
+    #[structopt(long, default_value = "4194304", parse(try_from_str = parse_byte_size))]
+    max_inline_size: u64,
+    /// Bind all parameters as `VARCHAR`. By default the ODBC driver is asked for the SQL type of
+    /// each placeholder, so e.g. parameters compared with a `DATE` or `DECIMAL` column are bound
+    /// as such. Their text is still converted by the driver. Use this if the driver reports wrong
+    /// parameter types.
+    #[structopt(long)]
+    no_describe_param: bool,
+    /// Name of the output parquet file. Ignored if `--no-write` or `--output-base64` is
+    /// specified. May contain the placeholders `{n}` for the number of the file if the output is
+    /// split, `{date}`, `{time}` and `{now:<format>}` for the time of the invocation, e.g.
+    /// `sales_{now:%Y%m%d}_part{n}.par`. Without `{n}` the number of the file is appended to the
+    /// stem, e.g. `out_01.par`. Write `{{` and `}}` for literal braces. Use `-` to write the
+    /// file to standard out, e.g. to pipe it into another program. Log output always goes to
+    /// standard error.
+    output: OutputPath,
     /// Query executed against the ODBC data source. Question marks (`?`) can be used as
-    /// placeholders for positional parameters.
+    /// placeholders for positional parameters. Fails with exit code 4, if the statement does not
+    /// produce a result set, e.g. an `UPDATE`.
     query: String,
     /// For each placeholder question mark (`?`) in the query text one parameter must be passed at
     /// the end of the command line.
     parameters: Vec<String>,
 }
 
+#[derive(StructOpt)]
+pub struct FilterOpt {
+    /// Parquet file to read the rows from.
+    input: PathBuf,
+    /// Parquet file to write the qualifying rows to. It has the same schema as the input, and the
+    /// same compression and dictionary encoding for each column. It is written to a temporary
+    /// file next to it, e.g. `out.par.tmp`, which is renamed once the file is complete.
+    output: PathBuf,
+    /// Only rows satisfying this predicate are written to the output. Conditions on single
+    /// columns are combined with `AND`, e.g. `"a >= 10 AND a < 20 AND b = 'x' AND c IS NOT NULL"`.
+    /// Supported comparisons are `=`, `<>`, `<`, `<=`, `>` and `>=`. Values are compared by their
+    /// physical representation, so columns whose logical type changes it, like decimals, dates,
+    /// times and timestamps, can only be tested for NULL. Row groups which can not contain any
+    /// qualifying row according to their statistics are skipped without reading them.
+    #[structopt(long = "where")]
+    predicate: Predicate,
+    /// Replace the output file, if it exists already. Without it, the command fails with exit
+    /// code 5 before reading the input, if the output exists.
+    #[structopt(long)]
+    overwrite: bool,
+}
+
 fn main() -> Result<(), Error> {
     let opt = Cli::from_args();
 
     // Initialize logging
-    stderrlog::new()
+    let mut logger = stderrlog::new();
+    logger
         .module(module_path!())
         .module("odbc_api")
         .quiet(false)
         .verbosity(opt.verbose)
-        .timestamp(stderrlog::Timestamp::Second)
-        .init()
-        .unwrap();
-
-    // We know this is going to be the only ODBC environment in the entire process, so this is safe.
-    let mut odbc_env = unsafe { Environment::new() }?;
+        .timestamp(stderrlog::Timestamp::Second);
+    // Same as `stderrlog::init` would do, which we can not use, since we wrap the logger.
+    if !io::stderr().is_terminal() {
+        logger.color(stderrlog::ColorChoice::Never);
+    }
+    let max_level = match opt.verbose {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        3 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    DedupeDiagnostics::init(logger, max_level).unwrap();
 
     match opt.command {
         Command::Query { query_opt } => {
-            query::query(&odbc_env, &query_opt)?;
+            let violations = validate::violations(&query_opt);
+            if !violations.is_empty() {
+                // Reported like the conflicts detected by clap, with one line for each rule.
+                clap::Error::with_description(
+                    &violations.join("\nerror: "),
+                    ErrorKind::ArgumentConflict,
+                )
+                .exit()
+            }
+            let odbc_env = odbc_environment()?;
+            let completed_with_warnings = match query::query(&odbc_env, &query_opt) {
+                Ok(completed_with_warnings) => completed_with_warnings,
+                Err(error) if error.chain().any(|cause| cause.is::<UploadFailed>()) => {
+                    log::logger().flush();
+                    eprintln!("Error: {:?}", error);
+                    process::exit(EXIT_UPLOAD_FAILED);
+                }
+                Err(error) if error.is::<NoResultSet>() => {
+                    log::logger().flush();
+                    eprintln!("Error: {}", error);
+                    process::exit(EXIT_NO_RESULT_SET);
+                }
+                Err(error) if error.is::<EmptyResultSet>() => {
+                    log::logger().flush();
+                    eprintln!("{}", error);
+                    process::exit(EXIT_EMPTY_RESULT_SET);
+                }
+                Err(error) if error.chain().any(|cause| cause.is::<OutputExists>()) => {
+                    log::logger().flush();
+                    eprintln!("Error: {:?}", error);
+                    process::exit(EXIT_OUTPUT_EXISTS);
+                }
+                Err(error) => return Err(error),
+            };
+            // Report repeated diagnostics, before exiting without running destructors.
+            log::logger().flush();
+            if completed_with_warnings {
+                process::exit(EXIT_COMPLETED_WITH_WARNINGS);
+            }
         }
+        Command::Filter { filter_opt } => match filter::filter(&filter_opt) {
+            Ok(()) => (),
+            Err(error) if error.is::<OutputExists>() => {
+                log::logger().flush();
+                eprintln!("Error: {:?}", error);
+                process::exit(EXIT_OUTPUT_EXISTS);
+            }
+            Err(error) => return Err(error),
+        },
         Command::ListDrivers => {
-            for driver_info in odbc_env.drivers()? {
+            for driver_info in odbc_environment()?.drivers()? {
                 println!("{}", driver_info.description);
                 for (key, value) in &driver_info.attributes {
                     println!("\t{}={}", key, value);
@@ -106,7 +595,7 @@ fn main() -> Result<(), Error> {
         }
         Command::ListDataSources => {
             let mut first = true;
-            for data_source_info in odbc_env.data_sources()? {
+            for data_source_info in odbc_environment()?.data_sources()? {
                 // After first item, always place an additional newline in between.
                 if first {
                     first = false;
@@ -128,13 +617,20 @@ fn open_connection<'e>(
     opt: &ConnectOpts,
 ) -> Result<Connection<'e>, Error> {
     let conn = if let Some(dsn) = &opt.dsn {
+        // Only looked up here, so credentials set in the environment do not conflict with a
+        // connection string.
+        let user = opt.user.clone().or_else(|| env::var("ODBC_USER").ok());
+        let password = opt
+            .password
+            .clone()
+            .or_else(|| env::var("ODBC_PASSWORD").ok());
         odbc_env.connect(
-            &dsn,
-            opt.user.as_deref().unwrap_or(""),
-            opt.password.as_deref().unwrap_or(""),
+            dsn,
+            user.as_deref().unwrap_or(""),
+            password.as_deref().unwrap_or(""),
         )?
     } else if let Some(connection_string) = &opt.connection_string {
-        odbc_env.connect_with_connection_string(&connection_string)?
+        odbc_env.connect_with_connection_string(connection_string)?
     } else {
         bail!("Please specify a data source either using --dsn or --connection-string.");
     };
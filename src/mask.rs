@@ -0,0 +1,77 @@
+use std::{ffi::CStr, str::FromStr};
+
+use anyhow::{bail, Error};
+use parquet::data_type::ByteArray;
+use sha2::{Digest, Sha256};
+
+/// A column which values are replaced before they reach the parquet writer. Parsed from command
+/// line arguments of the form `column=method`.
+#[derive(Debug, Clone)]
+pub struct ColumnMask {
+    /// Name of the column in the result set.
+    pub column: String,
+    /// How to replace the values of this column.
+    pub method: MaskMethod,
+}
+
+/// Strategy used to replace the values of a masked column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaskMethod {
+    /// Replace each value with the hex encoded SHA-256 digest of its text representation.
+    Sha256,
+    /// Replace each value with `NULL`.
+    Null,
+    /// Replace each value with a constant.
+    Fixed(String),
+}
+
+impl MaskMethod {
+    /// Masked representation of a single value. `NULL`s stay `NULL`, so do all values if the method
+    /// is [`MaskMethod::Null`].
+    pub fn apply(&self, value: &CStr) -> Option<ByteArray> {
+        match self {
+            MaskMethod::Sha256 => {
+                let digest = Sha256::digest(value.to_bytes());
+                Some(format!("{:x}", digest).into_bytes().into())
+            }
+            MaskMethod::Null => None,
+            MaskMethod::Fixed(constant) => Some(constant.as_bytes().to_owned().into()),
+        }
+    }
+}
+
+impl FromStr for ColumnMask {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, method) = match s.find('=') {
+            Some(pos) => (&s[..pos], &s[(pos + 1)..]),
+            None => bail!(
+                "Mask '{}' must be of the form `column=method`. E.g. `email=sha256`.",
+                s
+            ),
+        };
+        if column.is_empty() {
+            bail!("Mask '{}' does not specify a column name.", s)
+        }
+        let method = match method {
+            "sha256" => MaskMethod::Sha256,
+            "null" => MaskMethod::Null,
+            other => {
+                if let Some(constant) = other.strip_prefix("fixed:") {
+                    MaskMethod::Fixed(constant.to_owned())
+                } else {
+                    bail!(
+                        "Unknown masking method '{}'. Supported are `sha256`, `null` and \
+                        `fixed:<value>`.",
+                        other
+                    )
+                }
+            }
+        };
+        Ok(ColumnMask {
+            column: column.to_owned(),
+            method,
+        })
+    }
+}
@@ -0,0 +1,152 @@
+//! Durations of the stages of an export. Written as JSON to the path specified with
+//! `--metrics-file`, so performance can be tracked across versions without parsing logs.
+//!
+//! ```json
+//! {
+//!   "fetch": { "count": 3, "total": 0.2, "p50": 0.06, "p90": 0.08, "p99": 0.08, "max": 0.08 },
+//!   "convert": { ... },
+//!   "write": { ... },
+//!   "num_rows_fetched": 3000,
+//!   "rows_per_second": 12000.0,
+//!   "total": 0.25
+//! }
+//! ```
+//!
+//! All durations are in seconds. `fetch` has one sample per batch, `convert` one per row group
+//! and `write` one per row group plus one for closing the last file. `total` is measured from
+//! binding the buffers to the end of the export, so executing the query is not included.
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+/// A stage of the export loop.
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    /// Fetching a batch from the data source.
+    Fetch,
+    /// Converting the values of a row group and encoding them into pages.
+    Convert,
+    /// Writing a row group to the output, or closing the file.
+    Write,
+}
+
+pub struct Metrics {
+    started: Instant,
+    fetch: Vec<Duration>,
+    convert: Vec<Duration>,
+    write: Vec<Duration>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            fetch: Vec::new(),
+            convert: Vec::new(),
+            write: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, stage: Stage, duration: Duration) {
+        match stage {
+            Stage::Fetch => self.fetch.push(duration),
+            Stage::Convert => self.convert.push(duration),
+            Stage::Write => self.write.push(duration),
+        }
+    }
+
+    /// `num_rows_fetched` is used to compute the throughput since the metrics have been created.
+    pub fn to_json(&self, num_rows_fetched: u64) -> Value {
+        let total = self.started.elapsed().as_secs_f64();
+        let rows_per_second = if total > 0.0 {
+            num_rows_fetched as f64 / total
+        } else {
+            0.0
+        };
+        json!({
+            "fetch": histogram(&self.fetch),
+            "convert": histogram(&self.convert),
+            "write": histogram(&self.write),
+            "num_rows_fetched": num_rows_fetched,
+            "rows_per_second": rows_per_second,
+            "total": total,
+        })
+    }
+
+    pub fn write_json(&self, path: &Path, num_rows_fetched: u64) -> Result<(), Error> {
+        fs::write(
+            path,
+            serde_json::to_string_pretty(&self.to_json(num_rows_fetched))?,
+        )?;
+        Ok(())
+    }
+}
+
+/// Executes `f` and records its duration as part of `stage`. Only looks at the clock if metrics
+/// are collected.
+pub fn timed<T>(metrics: &mut Option<Metrics>, stage: Stage, f: impl FnOnce() -> T) -> T {
+    match metrics {
+        None => f(),
+        Some(metrics) => {
+            let start = Instant::now();
+            let result = f();
+            metrics.record(stage, start.elapsed());
+            result
+        }
+    }
+}
+
+fn histogram(samples: &[Duration]) -> Value {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    json!({
+        "count": sorted.len(),
+        "total": sorted.iter().sum::<Duration>().as_secs_f64(),
+        "p50": percentile(&sorted, 50).as_secs_f64(),
+        "p90": percentile(&sorted, 90).as_secs_f64(),
+        "p99": percentile(&sorted, 99).as_secs_f64(),
+        "max": sorted.last().copied().unwrap_or_default().as_secs_f64(),
+    })
+}
+
+/// Nearest rank percentile of ascending `sorted` samples. Zero if there are none.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{histogram, percentile};
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let samples: Vec<_> = (1..=10).map(Duration::from_secs).collect();
+        assert_eq!(Duration::from_secs(5), percentile(&samples, 50));
+        assert_eq!(Duration::from_secs(9), percentile(&samples, 90));
+        assert_eq!(Duration::from_secs(10), percentile(&samples, 99));
+        assert_eq!(Duration::from_secs(1), percentile(&samples[..1], 50));
+        assert_eq!(Duration::default(), percentile(&[], 50));
+    }
+
+    #[test]
+    fn histogram_of_unsorted_samples() {
+        let samples = [3, 1, 2].map(Duration::from_millis);
+        let histogram = histogram(&samples);
+        assert_eq!(3, histogram["count"]);
+        assert_eq!(0.002, histogram["p50"]);
+        assert_eq!(0.003, histogram["max"]);
+        assert_eq!(0.006, histogram["total"]);
+    }
+}
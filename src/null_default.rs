@@ -0,0 +1,152 @@
+use std::{convert::TryInto, ffi::CString, str::FromStr};
+
+use anyhow::{bail, format_err, Error};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use odbc_api::{
+    buffers::BufferKind,
+    sys::{Date, Timestamp},
+    Bit,
+};
+
+/// A value written instead of NULL into a column. Parsed from command line arguments of the form
+/// `column=value`.
+#[derive(Debug, Clone)]
+pub struct NullDefault {
+    /// Name of the column in the result set.
+    pub column: String,
+    /// Text representation of the value. Parsed once the type of the column is known.
+    pub value: String,
+}
+
+impl FromStr for NullDefault {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, value) = match s.find('=') {
+            Some(pos) => (&s[..pos], &s[(pos + 1)..]),
+            None => bail!(
+                "Null default '{}' must be of the form `column=value`. E.g. `age=0`.",
+                s
+            ),
+        };
+        if column.is_empty() {
+            bail!("Null default '{}' does not specify a column name.", s)
+        }
+        Ok(NullDefault {
+            column: column.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+/// Value substituted for NULL, in the representation of the ODBC buffer bound to the column. This
+/// way substitution happens before conversion, and works for every parquet type the buffer is
+/// written to.
+#[derive(Debug)]
+pub enum Sentinel {
+    Text(CString),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bit(Bit),
+    Date(Date),
+    Timestamp(Timestamp),
+}
+
+impl Sentinel {
+    /// Parse `value` into the representation used by a buffer of kind `kind`.
+    pub fn parse(value: &str, kind: BufferKind) -> Result<Self, Error> {
+        let sentinel = match kind {
+            BufferKind::Text { .. } => Sentinel::Text(CString::new(value)?),
+            BufferKind::I32 => Sentinel::I32(value.parse()?),
+            BufferKind::I64 => Sentinel::I64(value.parse()?),
+            BufferKind::F32 => Sentinel::F32(value.parse()?),
+            BufferKind::F64 => Sentinel::F64(value.parse()?),
+            BufferKind::Bit => Sentinel::Bit(Bit(value.parse::<bool>()? as u8)),
+            BufferKind::Date => {
+                let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")?;
+                Sentinel::Date(Date {
+                    year: date.year().try_into()?,
+                    month: date.month() as u16,
+                    day: date.day() as u16,
+                })
+            }
+            BufferKind::Timestamp => {
+                let ts = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f")?;
+                Sentinel::Timestamp(Timestamp {
+                    year: ts.year().try_into()?,
+                    month: ts.month() as u16,
+                    day: ts.day() as u16,
+                    hour: ts.hour() as u16,
+                    minute: ts.minute() as u16,
+                    second: ts.second() as u16,
+                    fraction: ts.nanosecond(),
+                })
+            }
+            other => return Err(format_err!("Unsupported buffer kind {:?}.", other)),
+        };
+        Ok(sentinel)
+    }
+}
+
+/// Bring a decimal into the text representation used by ODBC drivers for a column with `scale`
+/// digits after the decimal point. E.g. `1.5` becomes `1.50` for a scale of two.
+pub fn normalize_decimal(value: &str, scale: usize) -> Result<String, Error> {
+    let (integer, fraction) = match value.find('.') {
+        Some(pos) => (&value[..pos], &value[(pos + 1)..]),
+        None => (value, ""),
+    };
+    let digits = integer.strip_prefix('-').unwrap_or(integer);
+    let is_digits = |text: &str| text.bytes().all(|c| c.is_ascii_digit());
+    if digits.is_empty() || !is_digits(digits) || !is_digits(fraction) {
+        bail!("'{}' is not a decimal number.", value)
+    }
+    if fraction.len() > scale {
+        bail!(
+            "'{}' has more than {} digits after the decimal point.",
+            value,
+            scale
+        )
+    }
+    if scale == 0 {
+        Ok(integer.to_owned())
+    } else {
+        Ok(format!("{}.{:0<width$}", integer, fraction, width = scale))
+    }
+}
+
+/// Access the value of a [`Sentinel`] as the type of a column buffer.
+pub trait FromSentinel {
+    fn from_sentinel(sentinel: &Sentinel) -> Option<&Self>;
+}
+
+macro_rules! impl_from_sentinel {
+    ($type:ty, $variant:ident) => {
+        impl FromSentinel for $type {
+            fn from_sentinel(sentinel: &Sentinel) -> Option<&Self> {
+                match sentinel {
+                    Sentinel::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_from_sentinel!(i32, I32);
+impl_from_sentinel!(i64, I64);
+impl_from_sentinel!(f32, F32);
+impl_from_sentinel!(f64, F64);
+impl_from_sentinel!(Bit, Bit);
+impl_from_sentinel!(Date, Date);
+impl_from_sentinel!(Timestamp, Timestamp);
+
+impl FromSentinel for std::ffi::CStr {
+    fn from_sentinel(sentinel: &Sentinel) -> Option<&Self> {
+        match sentinel {
+            Sentinel::Text(text) => Some(text.as_c_str()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    fmt, fs, mem,
+    path::{is_separator, Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, format_err, Error};
+use chrono::{format::Item, format::StrftimeItems, DateTime, Local};
+
+/// Path of the output file as given on the command line. May contain placeholders, which are
+/// substituted once the export starts:
+///
+/// * `{n}`: Number of the file, if the output is split into several files.
+/// * `{date}`: Date of the invocation, e.g. `2021-03-01`.
+/// * `{time}`: Time of day of the invocation, e.g. `14-05-32`.
+/// * `{now:<format>}`: Time of the invocation in a strftime like format, e.g. `{now:%Y%m%d}`.
+///
+/// Literal braces are written as `{{` and `}}`.
+#[derive(Debug)]
+pub struct OutputPath {
+    /// The path with all placeholders but `{n}` substituted, split at each `{n}`.
+    parts: Vec<String>,
+}
+
+impl FromStr for OutputPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OutputPath::new(s, Local::now())
+    }
+}
+
+impl OutputPath {
+    /// Substitutes the time placeholders with `now`.
+    pub fn new(template: &str, now: DateTime<Local>) -> Result<Self, Error> {
+        let mut parts = Vec::new();
+        let mut part = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    part.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    part.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| {
+                        format_err!(
+                            "Unterminated placeholder in output path '{}'. Write `{{{{` for a \
+                            literal brace.",
+                            template
+                        )
+                    })?;
+                    let placeholder = &rest[..end];
+                    chars = rest[(end + 1)..].chars();
+                    match placeholder {
+                        "n" => parts.push(mem::take(&mut part)),
+                        "date" => part.push_str(&now.format("%Y-%m-%d").to_string()),
+                        "time" => part.push_str(&now.format("%H-%M-%S").to_string()),
+                        other => match other.strip_prefix("now:") {
+                            Some(format) => part.push_str(&format_time(now, format)?),
+                            None => bail!(
+                                "Unknown placeholder '{{{}}}' in output path '{}'. Supported are \
+                                `{{n}}`, `{{date}}`, `{{time}}` and `{{now:<format>}}`. Write \
+                                `{{{{` and `}}}}` for literal braces.",
+                                other,
+                                template
+                            ),
+                        },
+                    }
+                }
+                '}' => bail!(
+                    "Unmatched '}}' in output path '{}'. Write `}}}}` for a literal brace.",
+                    template
+                ),
+                c => part.push(c),
+            }
+        }
+        parts.push(part);
+        Ok(OutputPath { parts })
+    }
+
+    /// `true` if the output is `-`, i.e. written to standard out instead of a file.
+    pub fn is_stdout(&self) -> bool {
+        self.parts == ["-"]
+    }
+
+    /// Path of the output, if it consists of a single file. `{n}` is substituted with the first
+    /// number.
+    pub fn single(&self, suffix_length: usize) -> PathBuf {
+        self.join(1, suffix_length)
+    }
+
+    /// Path of the `num_file`th file, if the output is split into several files. Unless the path
+    /// contains `{n}`, the number is appended to its stem, e.g. `out_01.par`.
+    pub fn numbered(&self, num_file: u32, suffix_length: usize) -> Result<PathBuf, Error> {
+        if self.parts.len() > 1 {
+            return Ok(self.join(num_file, suffix_length));
+        }
+        let path = Path::new(&self.parts[0]);
+        let mut stem = path
+            .file_stem()
+            .ok_or_else(|| format_err!("Output needs To have a file stem."))?
+            .to_owned();
+        stem.push(format!("_{:0width$}", num_file, width = suffix_length));
+        let mut path_with_suffix = path.with_file_name(stem);
+        path_with_suffix = path_with_suffix.with_extension("par");
+        Ok(path_with_suffix)
+    }
+
+    /// Fails, if any file numbered like the files of a split output exists already. E.g. for
+    /// `out.par` this is `out_01.par`, but also `out_07.par` or `out_123.par`. Only the directory
+    /// of the output is searched, so if `{n}` is part of a directory name, only the first file is
+    /// checked.
+    pub fn refuse_existing_numbered(&self, suffix_length: usize) -> Result<(), Error> {
+        let pieces = self.numbered_pieces()?;
+        if pieces[1..].iter().any(|piece| piece.contains(is_separator)) {
+            refuse_existing(&self.numbered(1, suffix_length)?)?;
+            return Ok(());
+        }
+        let (dir, prefix) = match pieces[0].rfind(is_separator) {
+            Some(pos) => (&pieces[0][..=pos], &pieces[0][(pos + 1)..]),
+            None => ("", pieces[0].as_str()),
+        };
+        let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+            Ok(entries) => entries,
+            // Writing into the directory is going to fail, too. Let that report the error.
+            Err(_) => return Ok(()),
+        };
+        let mut existing: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| is_numbered(name, prefix, &pieces[1..], suffix_length))
+            })
+            .map(|entry| Path::new(dir).join(entry.file_name()))
+            .collect();
+        existing.sort();
+        match existing.into_iter().next() {
+            Some(path) => Err(OutputExists(path).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// The path of a numbered file, split at each occurrence of its number. E.g. `["out_",
+    /// ".par"]` for `out.par`.
+    fn numbered_pieces(&self) -> Result<Vec<String>, Error> {
+        if self.parts.len() > 1 {
+            return Ok(self.parts.clone());
+        }
+        // `numbered` appends the number to the stem and replaces the extension, e.g. `out_0.par`.
+        let path = self.numbered(0, 1)?.to_string_lossy().into_owned();
+        let number_pos = path.len() - ".par".len() - 1;
+        Ok(vec![
+            path[..number_pos].to_owned(),
+            path[(number_pos + 1)..].to_owned(),
+        ])
+    }
+
+    fn join(&self, num_file: u32, suffix_length: usize) -> PathBuf {
+        let number = format!("{:0width$}", num_file, width = suffix_length);
+        self.parts.join(&number).into()
+    }
+}
+
+/// An output file exists already, and `--overwrite` has not been given.
+#[derive(Debug)]
+pub struct OutputExists(PathBuf);
+
+impl fmt::Display for OutputExists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' already exists. Pass --overwrite to replace it.",
+            self.0.display()
+        )
+    }
+}
+
+impl std::error::Error for OutputExists {}
+
+/// Fails, if there is a file at `path` already.
+pub fn refuse_existing(path: &Path) -> Result<(), OutputExists> {
+    if path.exists() {
+        Err(OutputExists(path.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+/// `true` if `name` consists of `prefix` followed by the same file number before each of `pieces`.
+/// Numbers are written like `OutputPath::numbered` would, i.e. zero padded to `suffix_length`.
+fn is_numbered(name: &str, prefix: &str, pieces: &[String], suffix_length: usize) -> bool {
+    let mut rest = match name.strip_prefix(prefix) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut number = None;
+    for piece in pieces {
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let digits = &rest[..end];
+        let num_file = match digits.parse::<u32>() {
+            Ok(num_file) if num_file >= 1 => num_file,
+            _ => return false,
+        };
+        if format!("{:0width$}", num_file, width = suffix_length) != digits
+            || number.is_some_and(|number| number != num_file)
+        {
+            return false;
+        }
+        number = Some(num_file);
+        rest = match rest[end..].strip_prefix(piece.as_str()) {
+            Some(rest) => rest,
+            None => return false,
+        };
+    }
+    rest.is_empty()
+}
+
+fn format_time(now: DateTime<Local>, format: &str) -> Result<String, Error> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        bail!("Invalid time format '{}' in output path.", format)
+    }
+    Ok(now.format(format).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use chrono::{Local, TimeZone};
+    use tempfile::tempdir;
+
+    use super::OutputPath;
+
+    fn output_path(template: &str) -> Result<OutputPath, String> {
+        let now = Local.ymd(2021, 3, 1).and_hms(14, 5, 32);
+        OutputPath::new(template, now).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn time_placeholders() {
+        let path = output_path("sales_{date}T{now:%H}_{time}.par").unwrap();
+        assert_eq!(
+            Path::new("sales_2021-03-01T14_14-05-32.par"),
+            path.single(2)
+        );
+        let path = output_path("{{literal}}_{now:%Y%m%d}.par").unwrap();
+        assert_eq!(Path::new("{literal}_20210301.par"), path.single(2));
+    }
+
+    #[test]
+    fn number_placeholder() {
+        let path = output_path("dir/sales_part{n}.par").unwrap();
+        assert_eq!(Path::new("dir/sales_part01.par"), path.single(2));
+        assert_eq!(
+            Path::new("dir/sales_part003.par"),
+            path.numbered(3, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn zero_padded_suffixes() {
+        let path = output_path("dir/out.par").unwrap();
+        let numbered = |num_file, suffix_length| path.numbered(num_file, suffix_length).unwrap();
+        assert_eq!(Path::new("dir/out.par"), path.single(2));
+        assert_eq!(Path::new("dir/out_01.par"), numbered(1, 2));
+        assert_eq!(Path::new("dir/out_001.par"), numbered(1, 3));
+        assert_eq!(Path::new("dir/out_1.par"), numbered(1, 1));
+        // Widened, rather than running out of names.
+        assert_eq!(Path::new("dir/out_100.par"), numbered(100, 2));
+    }
+
+    #[test]
+    fn refuse_existing_numbered_files() {
+        let dir = tempdir().unwrap();
+        let existing = |template: &str, suffix_length| {
+            let template = dir.path().join(template);
+            output_path(template.to_str().unwrap())
+                .unwrap()
+                .refuse_existing_numbered(suffix_length)
+                .map_err(|error| error.to_string())
+        };
+        fs::write(dir.path().join("out.par"), "").unwrap();
+        fs::write(dir.path().join("out_x.par"), "").unwrap();
+        fs::write(dir.path().join("out_00.par"), "").unwrap();
+        fs::write(dir.path().join("out_7.par"), "").unwrap();
+        // None of them would be written by a split output with suffixes of two digits.
+        assert!(existing("out.par", 2).is_ok());
+
+        // Any part of a previous export counts, not just the first one.
+        fs::write(dir.path().join("out_03.par"), "").unwrap();
+        let error = existing("out.par", 2).unwrap_err();
+        assert!(error.ends_with("out_03.par' already exists. Pass --overwrite to replace it."));
+        assert!(existing("out.par", 3).is_ok());
+        assert!(existing("out.par", 1).unwrap_err().contains("out_7.par'"));
+
+        fs::write(dir.path().join("part12_of_12.par"), "").unwrap();
+        assert!(existing("part{n}_of_{n}.par", 2)
+            .unwrap_err()
+            .contains("part12_of_12.par'"));
+        fs::write(dir.path().join("day01_of_02.par"), "").unwrap();
+        assert!(existing("day{n}_of_{n}.par", 2).is_ok());
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(output_path("out_{x}.par")
+            .unwrap_err()
+            .starts_with("Unknown placeholder '{x}'"));
+        assert!(output_path("out_{n.par")
+            .unwrap_err()
+            .starts_with("Unterminated placeholder"));
+        assert!(output_path("out_}.par")
+            .unwrap_err()
+            .starts_with("Unmatched '}'"));
+        assert!(output_path("out_{now:%Q}.par")
+            .unwrap_err()
+            .starts_with("Invalid time format '%Q'"));
+    }
+}
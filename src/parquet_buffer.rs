@@ -1,5 +1,6 @@
-use anyhow::Error;
+use anyhow::{bail, format_err, Error};
 use chrono::NaiveDate;
+use log::warn;
 use num_bigint::BigInt;
 use odbc_api::{
     sys::{Date, Timestamp},
@@ -8,10 +9,104 @@ use odbc_api::{
 use parquet::{
     basic::Type as PhysicalType,
     column::writer::ColumnWriterImpl,
-    data_type::{ByteArray, DataType, FixedLenByteArray, FixedLenByteArrayType, Int64Type},
+    data_type::{
+        BoolType, ByteArray, ByteArrayType, DataType, FixedLenByteArray, FixedLenByteArrayType,
+        Int32Type, Int64Type,
+    },
     schema::types::Type,
 };
-use std::{convert::TryInto, ffi::CStr};
+use std::{convert::TryInto, ffi::CStr, str::FromStr};
+
+use crate::{
+    fraction::FractionUnit,
+    mask::MaskMethod,
+    strict::{violation, LossPolicy, LossyRule},
+    time::nanos_since_midnight,
+    timestamp::{timestamp_micros, timestamp_millis, timestamp_nanos, TimestampPrecision},
+};
+
+/// What to do, if a value fetched from the data source can not be converted into its parquet
+/// representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionErrorPolicy {
+    /// Fail the export.
+    Abort,
+    /// Write `NULL` instead and emit a warning. Only possible for nullable columns.
+    Null,
+}
+
+impl FromStr for ConversionErrorPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(ConversionErrorPolicy::Abort),
+            "null" => Ok(ConversionErrorPolicy::Null),
+            other => bail!(
+                "Unknown conversion error policy '{}'. Supported are `abort` and `null`.",
+                other
+            ),
+        }
+    }
+}
+
+/// Number of fractional digits a decimal column declares.
+#[derive(Clone, Copy)]
+struct Scale {
+    digits: usize,
+    /// Round values with more fractional digits, instead of rejecting them.
+    round: bool,
+}
+
+/// Fills `digits` with the sign and digits of a decimal in its text representation, without the
+/// decimal point. Missing fractional digits are padded with zeros, so `digits` always represents
+/// the unscaled value. Drivers may return more fractional digits than they declare. These are
+/// rounded half away from zero if requested, and rejected otherwise, since writing them would
+/// shift the value by a power of ten without anyone noticing.
+fn unscaled_digits(decimal: &CStr, scale: Scale, digits: &mut Vec<u8>) -> Result<(), Error> {
+    let text = decimal.to_bytes();
+    let (integer, fraction) = match text.iter().position(|&c| c == b'.') {
+        Some(pos) => (&text[..pos], &text[(pos + 1)..]),
+        None => (text, &b""[..]),
+    };
+    digits.clear();
+    digits.extend_from_slice(integer);
+    if fraction.len() <= scale.digits {
+        digits.extend_from_slice(fraction);
+        digits.resize(digits.len() + scale.digits - fraction.len(), b'0');
+        return Ok(());
+    }
+    if !scale.round {
+        bail!(
+            "Decimal '{}' has {} digits after the decimal point, but the scale of the column is \
+            {}. The ODBC driver returned more digits than it declared. Use --round-decimals to \
+            round them.",
+            decimal.to_string_lossy(),
+            fraction.len(),
+            scale.digits
+        )
+    }
+    digits.extend_from_slice(&fraction[..scale.digits]);
+    if fraction[scale.digits] >= b'5' {
+        // Increment the magnitude, carrying over nines.
+        let first_digit = usize::from(matches!(digits.first(), Some(b'-') | Some(b'+')));
+        let mut index = digits.len();
+        loop {
+            if index == first_digit {
+                digits.insert(first_digit, b'1');
+                break;
+            }
+            index -= 1;
+            if digits[index] == b'9' {
+                digits[index] = b'0';
+            } else {
+                digits[index] += 1;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
 
 /// Holds preallocated buffers for every possible physical parquet type. This way we do not need to
 /// reallocate them.
@@ -24,10 +119,26 @@ pub struct ParquetBuffer {
     pub values_fixed_bytes_array: Vec<FixedLenByteArray>,
     pub values_bool: Vec<bool>,
     pub def_levels: Vec<i16>,
+    /// Timestamps are normalized to nanoseconds using this, before they are written.
+    pub fraction_unit: FractionUnit,
+    on_conversion_error: ConversionErrorPolicy,
+    loss_policy: LossPolicy,
+    /// Round decimals with more fractional digits than the scale of their column, rather than
+    /// treating them as conversion errors.
+    round_decimals: bool,
+    /// `true` if the column currently written is `REQUIRED`. Its values are written without
+    /// definition levels.
+    required: bool,
 }
 
 impl ParquetBuffer {
-    pub fn new(batch_size: usize) -> ParquetBuffer {
+    pub fn new(
+        batch_size: usize,
+        on_conversion_error: ConversionErrorPolicy,
+        loss_policy: LossPolicy,
+        fraction_unit: FractionUnit,
+        round_decimals: bool,
+    ) -> ParquetBuffer {
         ParquetBuffer {
             values_i32: Vec::with_capacity(batch_size),
             values_i64: Vec::with_capacity(batch_size),
@@ -37,6 +148,11 @@ impl ParquetBuffer {
             values_fixed_bytes_array: Vec::with_capacity(batch_size),
             values_bool: Vec::with_capacity(batch_size),
             def_levels: Vec::with_capacity(batch_size),
+            fraction_unit,
+            on_conversion_error,
+            loss_policy,
+            round_decimals,
+            required: false,
         }
     }
 
@@ -52,39 +168,70 @@ impl ParquetBuffer {
         self.values_bool.resize(num_rows, false);
     }
 
+    /// Call this before writing a column, with `true` if the column is `REQUIRED`.
+    pub fn set_required(&mut self, required: bool) {
+        self.required = required;
+    }
+
     /// Use an i128 to calculate the twos complement of Decimals with a precision up to and including 38
     fn twos_complement_i128(
         decimal: &CStr,
         length: usize,
+        scale: Scale,
         digits: &mut Vec<u8>,
-    ) -> FixedLenByteArray {
-        use atoi::FromRadix10Signed;
+    ) -> Result<FixedLenByteArray, Error> {
+        use atoi::FromRadix10SignedChecked;
 
-        digits.clear();
-        digits.extend(decimal.to_bytes().iter().filter(|&&c| c != b'.'));
+        unscaled_digits(decimal, scale, digits)?;
 
-        let (num, _consumed) = i128::from_radix_10_signed(&digits);
+        let (num, _consumed) = i128::from_radix_10_signed_checked(digits);
+
+        // Only happens if the driver returns more digits than the precision of the column allows.
+        let too_large = || {
+            format_err!(
+                "Decimal '{}' does not fit into {} bytes. The ODBC driver returned more digits \
+                than the precision of the column allows.",
+                decimal.to_string_lossy(),
+                length
+            )
+        };
+        let num = num.ok_or_else(too_large)?;
+        let bytes = num.to_be_bytes();
+        // Truncated bytes must only consist of the sign extension.
+        let (truncated, out) = bytes.split_at(16 - length);
+        let fill = if num < 0 { 255 } else { 0 };
+        if truncated.iter().any(|&byte| byte != fill) || (out[0] >= 128) != (num < 0) {
+            return Err(too_large());
+        }
 
-        let out = num.to_be_bytes()[(16 - length)..].to_owned();
         // Vec<u8> -> ByteArray -> FixedLenByteArray
-        let out: ByteArray = out.into();
-        out.into()
+        let out: ByteArray = out.to_owned().into();
+        Ok(out.into())
     }
 
     // Use num big int to calculate the two complements of arbitrary size
     fn twos_complement_big_int(
         decimal: &CStr,
         length: usize,
+        scale: Scale,
         digits: &mut Vec<u8>,
-    ) -> FixedLenByteArray {
+    ) -> Result<FixedLenByteArray, Error> {
         use atoi::FromRadix10Signed;
 
-        digits.clear();
-        digits.extend(decimal.to_bytes().iter().filter(|&&c| c != b'.'));
+        unscaled_digits(decimal, scale, digits)?;
 
-        let (num, _consumed) = BigInt::from_radix_10_signed(&digits);
+        let (num, _consumed) = BigInt::from_radix_10_signed(digits);
         let mut out = num.to_signed_bytes_be();
 
+        // Only happens if the driver returns more digits than the precision of the column allows.
+        if out.len() > length {
+            bail!(
+                "Decimal '{}' does not fit into {} bytes. The ODBC driver returned more digits \
+                than the precision of the column allows.",
+                decimal.to_string_lossy(),
+                length
+            )
+        }
         let num_leading_bytes = length - out.len();
         let fill: u8 = if num.sign() == num_bigint::Sign::Minus {
             255
@@ -95,48 +242,103 @@ impl ParquetBuffer {
         out.rotate_right(num_leading_bytes);
         // Vec<u8> -> ByteArray -> FixedByteArray
         let out: ByteArray = out.into();
-        out.into()
-    }
-
-    fn timestamp_nanos(ts: &Timestamp) -> i64 {
-        let datetime = NaiveDate::from_ymd(ts.year as i32, ts.month as u32, ts.day as u32)
-            .and_hms_nano(
-                ts.hour as u32,
-                ts.minute as u32,
-                ts.second as u32,
-                ts.fraction as u32,
-            );
-        datetime.timestamp_nanos()
+        Ok(out.into())
     }
 
+    /// Writes timestamps with the precision of the logical type of the column.
     pub fn write_timestamp<'o>(
         &mut self,
         cw: &mut ColumnWriterImpl<Int64Type>,
         source: impl Iterator<Item = Option<&'o Timestamp>>,
         primitive_type: &Type,
     ) -> Result<(), Error> {
-        let &precision = match primitive_type {
-            Type::PrimitiveType {
-                basic_info: _,
-                physical_type: pt,
-                type_length: _,
-                scale: _,
-                precision,
-            } => {
-                debug_assert_eq!(*pt, PhysicalType::INT64);
-                precision
+        let strict = self.loss_policy.is_strict();
+        match TimestampPrecision::of_column(primitive_type.get_basic_info().logical_type()) {
+            TimestampPrecision::Milliseconds => self.write_optional_fallible(cw, source, |ts| {
+                if strict {
+                    check_fraction(ts.fraction, 1_000_000, "milliseconds")?;
+                }
+                timestamp_millis(ts)
+            }),
+            TimestampPrecision::Microseconds => self.write_optional_fallible(cw, source, |ts| {
+                if strict {
+                    check_fraction(ts.fraction, 1_000, "microseconds")?;
+                }
+                timestamp_micros(ts)
+            }),
+            TimestampPrecision::Nanoseconds => {
+                self.write_optional_fallible(cw, source, timestamp_nanos)
             }
-            Type::GroupType { .. } => panic!("Column must be a primitive type"),
-        };
+        }
+    }
+
+    /// Writes the date part of timestamps as days since epoch.
+    pub fn write_timestamp_date<'o>(
+        &mut self,
+        cw: &mut ColumnWriterImpl<Int32Type>,
+        source: impl Iterator<Item = Option<&'o Timestamp>>,
+    ) -> Result<(), Error> {
+        self.write_optional_fallible(cw, source, |ts| {
+            days_since_epoch(&Date {
+                year: ts.year,
+                month: ts.month,
+                day: ts.day,
+            })
+        })
+    }
 
-        if precision <= 3 {
-            // Milliseconds precision
-            self.write_optional_any(cw, source, |ts| Self::timestamp_nanos(ts) / 1_000_000)?;
+    /// Writes the time of day of timestamps as microseconds since midnight.
+    pub fn write_timestamp_time<'o>(
+        &mut self,
+        cw: &mut ColumnWriterImpl<Int64Type>,
+        source: impl Iterator<Item = Option<&'o Timestamp>>,
+    ) -> Result<(), Error> {
+        let time_micros = |ts: &Timestamp| {
+            (ts.hour as i64 * 3600 + ts.minute as i64 * 60 + ts.second as i64) * 1_000_000
+                + ts.fraction as i64 / 1_000
+        };
+        if self.loss_policy.is_strict() {
+            self.write_optional_fallible(cw, source, |ts| {
+                check_fraction(ts.fraction, 1_000, "microseconds")?;
+                Ok(time_micros(ts))
+            })
         } else {
-            // Microseconds precision
-            self.write_optional_any(cw, source, |ts| Self::timestamp_nanos(ts) / 1_000)?;
+            self.write_optional_any(cw, source, time_micros)
         }
-        Ok(())
+    }
+
+    /// Parses times of day from text and writes them as milliseconds (INT32) or microseconds
+    /// (INT64) since midnight, depending on the type of the column writer.
+    pub fn write_time<'o, T>(
+        &mut self,
+        cw: &mut ColumnWriterImpl<T>,
+        source: impl Iterator<Item = Option<&'o CStr>>,
+    ) -> Result<(), Error>
+    where
+        T: DataType,
+        T::T: BufferedDataType + TimeOfDay,
+    {
+        let strict = self.loss_policy.is_strict();
+        self.write_optional_fallible(cw, source, |text| {
+            let nanos = nanos_since_midnight(text)?;
+            if strict {
+                check_fraction(
+                    (nanos % 1_000_000_000) as u32,
+                    T::T::NANOS_PER_UNIT as u32,
+                    T::T::UNIT,
+                )?;
+            }
+            Ok(T::T::from_nanos(nanos))
+        })
+    }
+
+    /// Writes dates as days since epoch.
+    pub fn write_date<'o>(
+        &mut self,
+        cw: &mut ColumnWriterImpl<Int32Type>,
+        source: impl Iterator<Item = Option<&'o Date>>,
+    ) -> Result<(), Error> {
+        self.write_optional_fallible(cw, source, days_since_epoch)
     }
 
     pub fn write_decimal<'o>(
@@ -145,38 +347,113 @@ impl ParquetBuffer {
         source: impl Iterator<Item = Option<&'o CStr>>,
         primitive_type: &Type,
     ) -> Result<(), Error> {
-        let (&length, &precision) = match primitive_type {
+        let (&length, &precision, &scale) = match primitive_type {
             Type::PrimitiveType {
                 basic_info: _,
                 physical_type: pt,
                 type_length,
-                scale: _,
+                scale,
                 precision,
             } => {
                 debug_assert_eq!(*pt, PhysicalType::FIXED_LEN_BYTE_ARRAY);
-                (type_length, precision)
+                (type_length, precision, scale)
             }
             Type::GroupType { .. } => panic!("Column must be a primitive type"),
         };
 
         let precision: usize = precision.try_into().unwrap();
+        let scale = Scale {
+            digits: scale.try_into().unwrap(),
+            round: self.round_decimals,
+        };
 
         // This vec is going to hold the digits with sign, but without the decimal point. It is
         // allocated once and reused for each value.
         let mut digits: Vec<u8> = Vec::with_capacity(precision + 1);
 
         if precision < 39 {
-            self.write_optional_any(cw, source, |item| {
-                Self::twos_complement_i128(item, length.try_into().unwrap(), &mut digits)
+            self.write_optional_fallible(cw, source, |item| {
+                Self::twos_complement_i128(item, length.try_into().unwrap(), scale, &mut digits)
             })
         } else {
             // The big int implementation is slow, let's use it only if we have to
-            self.write_optional_any(cw, source, |item| {
-                Self::twos_complement_big_int(item, length.try_into().unwrap(), &mut digits)
+            self.write_optional_fallible(cw, source, |item| {
+                Self::twos_complement_big_int(item, length.try_into().unwrap(), scale, &mut digits)
             })
         }
     }
 
+    /// Writes GUIDs fetched as text as 16 bytes, in the order they appear in the text.
+    pub fn write_guid<'o>(
+        &mut self,
+        cw: &mut ColumnWriterImpl<FixedLenByteArrayType>,
+        source: impl Iterator<Item = Option<&'o CStr>>,
+    ) -> Result<(), Error> {
+        self.write_optional_fallible(cw, source, guid_bytes)
+    }
+
+    /// Writes binary data fetched as hexadecimal text. `length` is the number of bytes of each
+    /// value in a `FIXED_LEN_BYTE_ARRAY` column.
+    pub fn write_binary<'o, T>(
+        &mut self,
+        cw: &mut ColumnWriterImpl<T>,
+        source: impl Iterator<Item = Option<&'o CStr>>,
+        length: Option<i32>,
+    ) -> Result<(), Error>
+    where
+        T: DataType,
+        T::T: BufferedDataType + From<ByteArray>,
+    {
+        self.write_optional_fallible(cw, source, |text| {
+            let bytes = hex_bytes(text)?;
+            match length {
+                Some(length) if bytes.len() as i32 != length => bail!(
+                    "Binary value of {} bytes does not match the fixed length {} of the column.",
+                    bytes.len(),
+                    length
+                ),
+                _ => Ok(ByteArray::from(bytes).into()),
+            }
+        })
+    }
+
+    /// Writes the masked representation of text values, rather than the values themselves.
+    ///
+    /// # Parameters
+    ///
+    /// * `statistics`: If `false` no minimum and maximum is recorded for the column.
+    pub fn write_masked<'o>(
+        &mut self,
+        cw: &mut ColumnWriterImpl<ByteArrayType>,
+        source: impl Iterator<Item = Option<&'o CStr>>,
+        mask: &MaskMethod,
+        statistics: bool,
+    ) -> Result<(), Error> {
+        let masked = source.map(|item| item.and_then(|value| mask.apply(value)));
+        if statistics {
+            return self.write_optional_any(cw, masked, |value| value);
+        }
+        let num_values = self.fill_optional(masked, Ok)?;
+        let required = self.required;
+        let (values, def_levels) = ByteArray::mut_buf(self);
+        let num_nulls = (def_levels.len() - num_values) as u64;
+        if required {
+            check_no_nulls(def_levels)?;
+        }
+        // The column writer only computes minimum and maximum itself, if we do not tell it the
+        // number of NULLs. The `statistics_enabled` writer property is ignored by `parquet 3`.
+        cw.write_batch_with_statistics(
+            &values[..num_values],
+            if required { None } else { Some(def_levels) },
+            None,
+            &None,
+            &None,
+            Some(num_nulls),
+            None,
+        )?;
+        Ok(())
+    }
+
     fn write_optional_any<T, S>(
         &mut self,
         cw: &mut ColumnWriterImpl<T>,
@@ -187,18 +464,100 @@ impl ParquetBuffer {
         T: DataType,
         T::T: BufferedDataType,
     {
+        self.write_optional_fallible(cw, source, |s| Ok(into_physical(s)))
+    }
+
+    /// Like `write_optional_any`, but the transformation may fail, e.g. due to invalid values
+    /// returned by the driver. Failures are handled according to the conversion error policy.
+    fn write_optional_fallible<T, S>(
+        &mut self,
+        cw: &mut ColumnWriterImpl<T>,
+        source: impl Iterator<Item = Option<S>>,
+        into_physical: impl FnMut(S) -> Result<T::T, Error>,
+    ) -> Result<(), Error>
+    where
+        T: DataType,
+        T::T: BufferedDataType,
+    {
+        let num_values = self.fill_optional(source, into_physical)?;
+        let required = self.required;
         let (values, def_levels) = T::T::mut_buf(self);
+        if required {
+            write_required(cw, &values[..num_values], def_levels)
+        } else {
+            write_with_null_count(cw, &values[..num_values], def_levels)
+        }
+    }
+
+    /// Fill values and definition levels of the buffer from `source`. Returns the number of values,
+    /// which are not NULL.
+    fn fill_optional<B, S>(
+        &mut self,
+        source: impl Iterator<Item = Option<S>>,
+        mut into_physical: impl FnMut(S) -> Result<B, Error>,
+    ) -> Result<usize, Error>
+    where
+        B: BufferedDataType,
+    {
+        // Columns with fallible conversions are declared optional, if this policy is active.
+        let null_on_error = self.on_conversion_error == ConversionErrorPolicy::Null;
+        let (values, def_levels) = B::mut_buf(self);
         let mut values_index = 0;
+        let mut num_items = 0;
         for (item, definition_level) in source.zip(&mut def_levels.iter_mut()) {
-            *definition_level = if let Some(value) = item {
-                values[values_index] = into_physical(value);
-                values_index += 1;
-                1
-            } else {
-                0
-            }
+            *definition_level = match item.map(&mut into_physical) {
+                Some(Ok(value)) => {
+                    values[values_index] = value;
+                    values_index += 1;
+                    1
+                }
+                None => 0,
+                Some(Err(error)) => {
+                    let error = error.context(format!("Invalid value in row {}.", num_items));
+                    if null_on_error {
+                        warn!("{:#} Writing NULL instead.", error);
+                        0
+                    } else {
+                        return Err(error);
+                    }
+                }
+            };
+            num_items += 1;
+        }
+        // Otherwise we would write stale definition levels from a previous batch.
+        if num_items != def_levels.len() {
+            bail!(
+                "Expected {} values in column, but only got {}. This is a bug, please open an \
+                issue at https://github.com/pacman82/odbc2parquet/issues.",
+                def_levels.len(),
+                num_items
+            )
+        }
+        Ok(values_index)
+    }
+
+    /// Fast path for bit columns without NULLs. Wide tables often consist mostly of flags, so we
+    /// convert the whole column in one tight loop, rather than item by item through an iterator
+    /// over optional values. No definition levels are written, since the column is required.
+    pub fn write_bits(
+        &mut self,
+        cw: &mut ColumnWriterImpl<BoolType>,
+        bits: &[Bit],
+        selection: Option<&[bool]>,
+    ) -> Result<(), Error> {
+        self.values_bool.clear();
+        // Any value other than zero is considered `true`. Avoids the branch checking for invalid
+        // values in `Bit::as_bool`, which would prevent the loop from being vectorized.
+        match selection {
+            None => self.values_bool.extend(bits.iter().map(|bit| bit.0 != 0)),
+            Some(selection) => self.values_bool.extend(
+                bits.iter()
+                    .zip(selection)
+                    .filter(|(_, &selected)| selected)
+                    .map(|(bit, _)| bit.0 != 0),
+            ),
         }
-        cw.write_batch(values, Some(&def_levels), None)?;
+        cw.write_batch(&self.values_bool, None, None)?;
         Ok(())
     }
 
@@ -219,6 +578,161 @@ impl ParquetBuffer {
     }
 }
 
+/// Writes the values and definition levels of a nullable column, like `write_batch` would.
+///
+/// `parquet 3` computes the number of NULLs in the column chunk statistics from the definition
+/// levels, but forgets those of the last page, if that page holds nothing but NULLs. This happens
+/// e.g. for columns without any value. So we count them ourselves. Passing the count stops the
+/// writer from computing minimum and maximum, so we do that, too, the same way it would. As a
+/// consequence the data page headers carry no statistics, only the column chunk does.
+pub fn write_with_null_count<T>(
+    cw: &mut ColumnWriterImpl<T>,
+    values: &[T::T],
+    def_levels: &[i16],
+) -> Result<(), Error>
+where
+    T: DataType,
+{
+    let num_nulls = (def_levels.len() - values.len()) as u64;
+    let (min, max) = min_max(values);
+    cw.write_batch_with_statistics(
+        values,
+        Some(def_levels),
+        None,
+        &min,
+        &max,
+        Some(num_nulls),
+        None,
+    )?;
+    Ok(())
+}
+
+/// Writes the values of a `REQUIRED` column, without definition levels. `def_levels` are only
+/// used to verify that no value is NULL. Otherwise the values would silently be shifted into the
+/// wrong rows.
+pub fn write_required<T>(
+    cw: &mut ColumnWriterImpl<T>,
+    values: &[T::T],
+    def_levels: &[i16],
+) -> Result<(), Error>
+where
+    T: DataType,
+{
+    check_no_nulls(def_levels)?;
+    let (min, max) = min_max(values);
+    cw.write_batch_with_statistics(values, None, None, &min, &max, Some(0), None)?;
+    Ok(())
+}
+
+fn check_no_nulls(def_levels: &[i16]) -> Result<(), Error> {
+    if let Some(row) = def_levels.iter().position(|&level| level == 0) {
+        bail!(
+            "Row {} is NULL, but the column is REQUIRED, since the ODBC driver reported it as not \
+            nullable. Use --nullable-all, if the driver does not report nullability reliably.",
+            row
+        )
+    }
+    Ok(())
+}
+
+/// Smallest and largest value, compared like the parquet column writer does.
+fn min_max<T: PartialOrd + Clone>(values: &[T]) -> (Option<T>, Option<T>) {
+    let mut min: Option<&T> = None;
+    let mut max: Option<&T> = None;
+    for value in values {
+        if min.is_none_or(|min| min > value) {
+            min = Some(value);
+        }
+        if max.is_none_or(|max| max < value) {
+            max = Some(value);
+        }
+    }
+    (min.cloned(), max.cloned())
+}
+
+/// Transform date to days since unix epoch.
+fn days_since_epoch(date: &Date) -> Result<i32, Error> {
+    let unix_epoch = NaiveDate::from_ymd(1970, 1, 1);
+    let naive = NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)
+        .ok_or_else(|| {
+            format_err!(
+                "Invalid date {:04}-{:02}-{:02}.",
+                date.year,
+                date.month,
+                date.day
+            )
+        })?;
+    let num_days = naive.signed_duration_since(unix_epoch).num_days();
+    // Checked explicitly, so we can tell the user what is wrong. With the range of years in ODBC
+    // dates this should never happen, though.
+    if num_days < i32::MIN as i64 || num_days > i32::MAX as i64 {
+        bail!(
+            "Date {:04}-{:02}-{:02} is out of representable range.",
+            date.year,
+            date.month,
+            date.day
+        )
+    }
+    Ok(num_days as i32)
+}
+
+/// Parses a GUID like `6F9619FF-8B86-D011-B42D-00C04FC964FF`, optionally enclosed in braces, into
+/// its 16 bytes in the byte order of RFC 4122.
+fn guid_bytes(text: &CStr) -> Result<FixedLenByteArray, Error> {
+    let invalid = || format_err!("'{}' is not a valid GUID.", text.to_string_lossy());
+    let text = text.to_str().map_err(|_| invalid())?;
+    let text = text
+        .strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+        .unwrap_or(text);
+    let groups: Vec<&str> = text.split('-').collect();
+    if groups.iter().map(|group| group.len()).ne([8, 4, 4, 4, 12]) {
+        return Err(invalid());
+    }
+    let bytes = decode_hex(groups.concat().as_bytes()).ok_or_else(invalid)?;
+    Ok(ByteArray::from(bytes).into())
+}
+
+/// Decodes binary data, which drivers represent as two hexadecimal digits per byte if fetched as
+/// text.
+fn hex_bytes(text: &CStr) -> Result<Vec<u8>, Error> {
+    decode_hex(text.to_bytes()).ok_or_else(|| {
+        format_err!(
+            "'{}' is not binary data in hexadecimal representation.",
+            text.to_string_lossy()
+        )
+    })
+}
+
+/// `None` if `hex` is not an even number of hexadecimal digits.
+fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| {
+            let digit = |c: u8| (c as char).to_digit(16);
+            Some((digit(pair[0])? * 16 + digit(pair[1])?) as u8)
+        })
+        .collect()
+}
+
+/// Fails if the fractional seconds in nanoseconds are not a multiple of `nanos_per_unit`, i.e.
+/// writing them with the precision of `unit` would truncate them. Only checked with `--strict`.
+fn check_fraction(fraction: u32, nanos_per_unit: u32, unit: &str) -> Result<(), Error> {
+    if !fraction.is_multiple_of(nanos_per_unit) {
+        return Err(violation(
+            LossyRule::TimestampPrecision,
+            &format!("fraction {:09}", fraction),
+            &format!(
+                "Fractional seconds are truncated, if written with a precision of {}.",
+                unit
+            ),
+        ));
+    }
+    Ok(())
+}
+
 pub trait BufferedDataType: Sized {
     fn mut_buf(buffer: &mut ParquetBuffer) -> (&mut [Self], &mut [i16]);
 }
@@ -286,6 +800,34 @@ impl BufferedDataType for FixedLenByteArray {
     }
 }
 
+/// Physical type of `TIME_MILLIS` and `TIME_MICROS` columns.
+pub trait TimeOfDay {
+    const NANOS_PER_UNIT: i64;
+    /// Name of the unit, used in error messages.
+    const UNIT: &'static str;
+
+    /// Truncates nanoseconds since midnight to the unit.
+    fn from_nanos(nanos: i64) -> Self;
+}
+
+impl TimeOfDay for i32 {
+    const NANOS_PER_UNIT: i64 = 1_000_000;
+    const UNIT: &'static str = "milliseconds";
+
+    fn from_nanos(nanos: i64) -> Self {
+        (nanos / Self::NANOS_PER_UNIT) as i32
+    }
+}
+
+impl TimeOfDay for i64 {
+    const NANOS_PER_UNIT: i64 = 1_000;
+    const UNIT: &'static str = "microseconds";
+
+    fn from_nanos(nanos: i64) -> Self {
+        nanos / Self::NANOS_PER_UNIT
+    }
+}
+
 pub trait IntoPhysical<T> {
     fn into_physical(self) -> T;
 }
@@ -299,16 +841,6 @@ where
     }
 }
 
-impl IntoPhysical<i32> for &Date {
-    fn into_physical(self) -> i32 {
-        let unix_epoch = NaiveDate::from_ymd(1970, 1, 1);
-        // Transform date to days since unix epoch as i32
-        let date = NaiveDate::from_ymd(self.year as i32, self.month as u32, self.day as u32);
-        let duration = date.signed_duration_since(unix_epoch);
-        duration.num_days().try_into().unwrap()
-    }
-}
-
 impl IntoPhysical<bool> for &Bit {
     fn into_physical(self) -> bool {
         self.as_bool()
@@ -0,0 +1,558 @@
+//! Predicates for `filter --where`. A predicate is a conjunction of conditions, each of which
+//! tests a single column against a literal, e.g. `a >= 10 AND b = 'x' AND c IS NOT NULL`.
+
+use std::{cmp::Ordering, str::FromStr};
+
+use anyhow::{anyhow, bail, Error};
+use parquet::{
+    basic::{LogicalType, Type as PhysicalType},
+    file::statistics::Statistics,
+    schema::types::SchemaDescriptor,
+};
+
+/// Conjunction of conditions, as specified on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Predicate {
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    column: String,
+    test: Test,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Test {
+    IsNull,
+    IsNotNull,
+    Compare(Comparison, Literal),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// A value of a column, as far as predicates are concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value<'a> {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    Bytes(&'a [u8]),
+    /// Values no predicate can compare with, e.g. `INT96`.
+    Incomparable,
+}
+
+/// A condition with its column resolved against the schema of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundCondition {
+    /// Index of the column in the schema of the file.
+    pub column: usize,
+    test: Test,
+}
+
+impl FromStr for Predicate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut tokens = tokens.iter().peekable();
+        let mut conditions = Vec::new();
+        loop {
+            let column = match tokens.next() {
+                Some(Token::Identifier(name)) => name.clone(),
+                other => bail!("Expected a column name, found {}.", describe(other)),
+            };
+            let test = match tokens.next() {
+                Some(Token::Keyword(Keyword::Is)) => {
+                    let negated = tokens.peek() == Some(&&Token::Keyword(Keyword::Not));
+                    if negated {
+                        tokens.next();
+                    }
+                    match tokens.next() {
+                        Some(Token::Keyword(Keyword::Null)) if negated => Test::IsNotNull,
+                        Some(Token::Keyword(Keyword::Null)) => Test::IsNull,
+                        other => bail!("Expected NULL, found {}.", describe(other)),
+                    }
+                }
+                Some(Token::Operator(comparison)) => {
+                    let literal = match tokens.next() {
+                        Some(Token::Literal(literal)) => literal.clone(),
+                        other => bail!("Expected a literal, found {}.", describe(other)),
+                    };
+                    Test::Compare(*comparison, literal)
+                }
+                other => bail!(
+                    "Expected a comparison or IS NULL after '{}', found {}.",
+                    column,
+                    describe(other)
+                ),
+            };
+            conditions.push(Condition { column, test });
+            match tokens.next() {
+                None => break,
+                Some(Token::Keyword(Keyword::And)) => (),
+                other => bail!("Expected AND, found {}.", describe(other)),
+            }
+        }
+        Ok(Predicate { conditions })
+    }
+}
+
+impl Predicate {
+    /// Resolves the columns of the predicate and checks that each literal can be compared with
+    /// its column. Nested columns are referred to by their path, e.g. `a.b`.
+    pub fn bind(&self, schema: &SchemaDescriptor) -> Result<Vec<BoundCondition>, Error> {
+        self.conditions
+            .iter()
+            .map(|condition| {
+                let column = (0..schema.num_columns())
+                    .find(|&index| schema.column(index).path().string() == condition.column)
+                    .ok_or_else(|| anyhow!("Unknown column '{}'.", condition.column))?;
+                let descr = schema.column(column);
+                if let Test::Compare(_, literal) = &condition.test {
+                    // Literals are compared with the physical value. For these logical types it
+                    // differs from the value the literal would be written as, e.g. the unscaled
+                    // integer of a decimal, or the two's complement bytes of a large one.
+                    let encoded = matches!(
+                        descr.logical_type(),
+                        LogicalType::UINT_8
+                            | LogicalType::UINT_16
+                            | LogicalType::UINT_32
+                            | LogicalType::UINT_64
+                            | LogicalType::DECIMAL
+                            | LogicalType::DATE
+                            | LogicalType::TIME_MILLIS
+                            | LogicalType::TIME_MICROS
+                            | LogicalType::TIMESTAMP_MILLIS
+                            | LogicalType::TIMESTAMP_MICROS
+                            | LogicalType::INTERVAL
+                    );
+                    let comparable = match (descr.physical_type(), literal) {
+                        _ if encoded => false,
+                        (PhysicalType::BOOLEAN, Literal::Boolean(_)) => true,
+                        (PhysicalType::INT32, Literal::Integer(_) | Literal::Float(_))
+                        | (PhysicalType::INT64, Literal::Integer(_) | Literal::Float(_))
+                        | (PhysicalType::FLOAT, Literal::Integer(_) | Literal::Float(_))
+                        | (PhysicalType::DOUBLE, Literal::Integer(_) | Literal::Float(_)) => true,
+                        (PhysicalType::BYTE_ARRAY, Literal::Text(_))
+                        | (PhysicalType::FIXED_LEN_BYTE_ARRAY, Literal::Text(_)) => true,
+                        _ => false,
+                    };
+                    if !comparable {
+                        bail!(
+                            "Column '{}' of type {} {} can not be compared with {}.",
+                            condition.column,
+                            descr.physical_type(),
+                            descr.logical_type(),
+                            literal.describe()
+                        );
+                    }
+                }
+                Ok(BoundCondition {
+                    column,
+                    test: condition.test.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl BoundCondition {
+    /// Evaluates the condition for a value of its column. `None` represents NULL. Like in SQL,
+    /// NULL never satisfies a comparison.
+    pub fn matches(&self, value: Option<Value>) -> bool {
+        match (&self.test, value) {
+            (Test::IsNull, value) => value.is_none(),
+            (Test::IsNotNull, value) => value.is_some(),
+            (Test::Compare(_, _), None) => false,
+            (Test::Compare(comparison, literal), Some(value)) => literal
+                .compare(value)
+                .is_some_and(|ordering| comparison.holds(ordering)),
+        }
+    }
+
+    /// `true` if the statistics of a column chunk prove that none of its rows satisfies the
+    /// condition. Only minimum and maximum of integer columns are relied upon. Null counts are
+    /// only trusted if they are not zero, since a missing null count is reported as zero.
+    pub fn excludes(&self, statistics: Option<&Statistics>, num_rows: i64) -> bool {
+        let statistics = match statistics {
+            Some(statistics) => statistics,
+            None => return false,
+        };
+        let only_nulls = statistics.null_count() == num_rows as u64;
+        let (comparison, literal) = match &self.test {
+            Test::IsNull => return false,
+            Test::IsNotNull => return only_nulls,
+            Test::Compare(comparison, literal) => (comparison, literal),
+        };
+        if only_nulls {
+            return true;
+        }
+        if !statistics.has_min_max_set() {
+            return false;
+        }
+        let (min, max) = match statistics {
+            Statistics::Int32(s) => (*s.min() as i64, *s.max() as i64),
+            Statistics::Int64(s) => (*s.min(), *s.max()),
+            _ => return false,
+        };
+        let (min, max) = match (
+            literal.compare(Value::Integer(min)),
+            literal.compare(Value::Integer(max)),
+        ) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return false,
+        };
+        // `min` and `max` are the orderings of the smallest and largest value relative to the
+        // literal. Every other value lies in between.
+        match comparison {
+            Comparison::Eq => min == Ordering::Greater || max == Ordering::Less,
+            Comparison::Ne => min == Ordering::Equal && max == Ordering::Equal,
+            Comparison::Lt => min != Ordering::Less,
+            Comparison::Le => min == Ordering::Greater,
+            Comparison::Gt => max != Ordering::Greater,
+            Comparison::Ge => max == Ordering::Less,
+        }
+    }
+}
+
+impl Comparison {
+    /// Whether the comparison holds, given the ordering of the value relative to the literal.
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ne => ordering != Ordering::Equal,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl Literal {
+    /// Ordering of `value` relative to the literal. `None` if they can not be compared.
+    fn compare(&self, value: Value) -> Option<Ordering> {
+        match (value, self) {
+            (Value::Boolean(value), Literal::Boolean(literal)) => Some(value.cmp(literal)),
+            (Value::Integer(value), Literal::Integer(literal)) => Some(value.cmp(literal)),
+            (Value::Integer(value), Literal::Float(literal)) => (value as f64).partial_cmp(literal),
+            (Value::Float(value), Literal::Integer(literal)) => {
+                value.partial_cmp(&(*literal as f64))
+            }
+            (Value::Float(value), Literal::Float(literal)) => value.partial_cmp(literal),
+            (Value::Bytes(value), Literal::Text(literal)) => Some(value.cmp(literal.as_bytes())),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Literal::Boolean(value) => format!("boolean {}", value),
+            Literal::Integer(value) => format!("integer {}", value),
+            Literal::Float(value) => format!("number {}", value),
+            Literal::Text(value) => format!("text '{}'", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Keyword(Keyword),
+    Operator(Comparison),
+    Literal(Literal),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keyword {
+    And,
+    Is,
+    Not,
+    Null,
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        None => "end of input".to_owned(),
+        Some(Token::Identifier(name)) => format!("column '{}'", name),
+        Some(Token::Keyword(keyword)) => format!("{:?}", keyword).to_uppercase(),
+        Some(Token::Operator(comparison)) => format!("operator {:?}", comparison),
+        Some(Token::Literal(literal)) => literal.describe(),
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            // Text literals are single quoted, identifiers may be double quoted. Quotes are
+            // escaped by doubling them, like in SQL.
+            chars.next();
+            let mut quoted = String::new();
+            loop {
+                match chars.next() {
+                    Some(next) if next == c => {
+                        if chars.peek() == Some(&c) {
+                            chars.next();
+                            quoted.push(c);
+                        } else {
+                            break;
+                        }
+                    }
+                    Some(next) => quoted.push(next),
+                    None => bail!("Unterminated quote in predicate: {}", text),
+                }
+            }
+            tokens.push(if c == '\'' {
+                Token::Literal(Literal::Text(quoted))
+            } else {
+                Token::Identifier(quoted)
+            });
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let mut number = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_ascii_alphanumeric() || next == '.' || next == '-' || next == '+' {
+                    number.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let literal = if let Ok(integer) = number.parse() {
+                Literal::Integer(integer)
+            } else if let Ok(float) = number.parse() {
+                Literal::Float(float)
+            } else {
+                bail!("Invalid number '{}' in predicate.", number)
+            };
+            tokens.push(Token::Literal(literal));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut word = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_alphanumeric() || next == '_' || next == '.' {
+                    word.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(match word.to_uppercase().as_str() {
+                "AND" => Token::Keyword(Keyword::And),
+                "IS" => Token::Keyword(Keyword::Is),
+                "NOT" => Token::Keyword(Keyword::Not),
+                "NULL" => Token::Keyword(Keyword::Null),
+                "TRUE" => Token::Literal(Literal::Boolean(true)),
+                "FALSE" => Token::Literal(Literal::Boolean(false)),
+                _ => Token::Identifier(word),
+            });
+        } else {
+            let mut operator = String::new();
+            while let Some(&next) = chars.peek() {
+                if "=<>!".contains(next) {
+                    operator.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let comparison = match operator.as_str() {
+                "=" => Comparison::Eq,
+                "<>" | "!=" => Comparison::Ne,
+                "<" => Comparison::Lt,
+                "<=" => Comparison::Le,
+                ">" => Comparison::Gt,
+                ">=" => Comparison::Ge,
+                "" => bail!("Unexpected character '{}' in predicate.", c),
+                other => bail!("Unknown operator '{}' in predicate.", other),
+            };
+            tokens.push(Token::Operator(comparison));
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parquet::{
+        file::statistics::Statistics,
+        schema::{parser::parse_message_type, types::SchemaDescriptor},
+    };
+
+    use super::{BoundCondition, Predicate, Value};
+
+    fn bind(predicate: &str) -> Vec<BoundCondition> {
+        let schema = parse_message_type(
+            "message schema {
+                OPTIONAL INT64 a;
+                OPTIONAL BYTE_ARRAY b (UTF8);
+                REQUIRED BOOLEAN c;
+                OPTIONAL INT32 d (UINT_32);
+            }",
+        )
+        .unwrap();
+        let schema = SchemaDescriptor::new(Arc::new(schema));
+        predicate
+            .parse::<Predicate>()
+            .unwrap()
+            .bind(&schema)
+            .unwrap()
+    }
+
+    fn bind_err(predicate: &str) -> String {
+        let schema = parse_message_type("message schema { OPTIONAL INT64 a; }").unwrap();
+        let schema = SchemaDescriptor::new(Arc::new(schema));
+        match predicate.parse::<Predicate>() {
+            Ok(predicate) => predicate.bind(&schema).unwrap_err().to_string(),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn evaluate_conjunction() {
+        let conditions = bind("a >= 10 AND a < 20.5 AND b <> 'it''s' AND c = true");
+        assert_eq!(4, conditions.len());
+        assert!(conditions[0].matches(Some(Value::Integer(10))));
+        assert!(!conditions[0].matches(Some(Value::Integer(9))));
+        assert!(!conditions[0].matches(None));
+        assert!(conditions[1].matches(Some(Value::Integer(20))));
+        assert!(!conditions[1].matches(Some(Value::Integer(21))));
+        assert!(!conditions[2].matches(Some(Value::Bytes(b"it's"))));
+        assert!(conditions[2].matches(Some(Value::Bytes(b"its"))));
+        assert!(conditions[3].matches(Some(Value::Boolean(true))));
+    }
+
+    #[test]
+    fn null_tests() {
+        let conditions = bind("a is null and b IS NOT NULL and d IS NULL");
+        assert!(conditions[0].matches(None));
+        assert!(!conditions[0].matches(Some(Value::Integer(0))));
+        assert!(conditions[1].matches(Some(Value::Bytes(b""))));
+        assert!(!conditions[1].matches(None));
+    }
+
+    #[test]
+    fn invalid_predicates() {
+        assert_eq!("Unknown column 'x'.", bind_err("x = 1"));
+        assert_eq!(
+            "Column 'a' of type INT64 NONE can not be compared with text 'x'.",
+            bind_err("a = 'x'")
+        );
+        assert_eq!("Expected AND, found column 'b'.", bind_err("a = 1 b = 2"));
+        assert_eq!("Expected a literal, found end of input.", bind_err("a ="));
+        assert_eq!("Expected NULL, found integer 1.", bind_err("a IS 1"));
+        assert_eq!("Unknown operator '=>' in predicate.", bind_err("a => 1"));
+        assert_eq!("Expected a column name, found AND.", bind_err("AND a = 1"));
+    }
+
+    #[test]
+    fn no_comparisons_on_unsigned_columns() {
+        let schema = parse_message_type("message schema { OPTIONAL INT32 d (UINT_32); }").unwrap();
+        let schema = SchemaDescriptor::new(Arc::new(schema));
+        let predicate: Predicate = "d > 1".parse().unwrap();
+        assert!(predicate.bind(&schema).is_err());
+    }
+
+    #[test]
+    fn no_comparisons_on_encoded_columns() {
+        let schema = parse_message_type(
+            "message schema {
+                OPTIONAL INT32 small (DECIMAL(9,2));
+                OPTIONAL INT64 medium (DECIMAL(18,2));
+                OPTIONAL FIXED_LEN_BYTE_ARRAY (16) large (DECIMAL(38,2));
+                OPTIONAL BYTE_ARRAY text (DECIMAL(10,2));
+                OPTIONAL INT32 day (DATE);
+                OPTIONAL INT32 millis (TIME_MILLIS);
+                OPTIONAL INT64 micros (TIME_MICROS);
+                OPTIONAL INT64 ts_millis (TIMESTAMP_MILLIS);
+                OPTIONAL INT64 ts_micros (TIMESTAMP_MICROS);
+            }",
+        )
+        .unwrap();
+        let schema = SchemaDescriptor::new(Arc::new(schema));
+        let bind_err = |predicate: &str| {
+            let predicate: Predicate = predicate.parse().unwrap();
+            predicate.bind(&schema).unwrap_err().to_string()
+        };
+        assert_eq!(
+            "Column 'small' of type INT32 DECIMAL can not be compared with number 1.5.",
+            bind_err("small > 1.5")
+        );
+        assert_eq!(
+            "Column 'medium' of type INT64 DECIMAL can not be compared with integer 150.",
+            bind_err("medium > 150")
+        );
+        assert_eq!(
+            "Column 'large' of type FIXED_LEN_BYTE_ARRAY DECIMAL can not be compared with text \
+            '1.5'.",
+            bind_err("large = '1.5'")
+        );
+        assert_eq!(
+            "Column 'text' of type BYTE_ARRAY DECIMAL can not be compared with text '1.5'.",
+            bind_err("text = '1.5'")
+        );
+        assert_eq!(
+            "Column 'day' of type INT32 DATE can not be compared with integer 18000.",
+            bind_err("day >= 18000")
+        );
+        assert!(bind_err("day = '2020-09-16'").starts_with("Column 'day'"));
+        assert!(bind_err("millis < 1000").starts_with("Column 'millis'"));
+        assert!(bind_err("micros < 1000").starts_with("Column 'micros'"));
+        assert!(bind_err("ts_millis < 1000").starts_with("Column 'ts_millis'"));
+        assert!(bind_err("ts_micros < 1000").starts_with("Column 'ts_micros'"));
+
+        // Testing for NULL does not depend on the representation.
+        let predicate: Predicate = "small IS NULL AND large IS NOT NULL AND day IS NULL"
+            .parse()
+            .unwrap();
+        assert_eq!(3, predicate.bind(&schema).unwrap().len());
+    }
+
+    #[test]
+    fn exclude_row_groups_by_statistics() {
+        let stats = Statistics::int64(Some(10), Some(20), None, 0, false);
+        let excludes = |predicate: &str| bind(predicate)[0].excludes(Some(&stats), 100);
+        assert!(excludes("a < 10"));
+        assert!(!excludes("a <= 10"));
+        assert!(excludes("a > 20"));
+        assert!(!excludes("a >= 20"));
+        assert!(excludes("a = 21"));
+        assert!(!excludes("a = 15"));
+        assert!(!excludes("a <> 15"));
+        assert!(!excludes("a IS NULL"));
+        assert!(!excludes("a IS NOT NULL"));
+
+        let constant = Statistics::int64(Some(5), Some(5), None, 0, false);
+        assert!(bind("a <> 5")[0].excludes(Some(&constant), 100));
+
+        let only_nulls = Statistics::int64(None, None, None, 100, false);
+        assert!(bind("a = 5")[0].excludes(Some(&only_nulls), 100));
+        assert!(bind("a IS NOT NULL")[0].excludes(Some(&only_nulls), 100));
+
+        // Missing statistics or statistics of other types never exclude anything.
+        assert!(!bind("a < 10")[0].excludes(None, 100));
+        let text = Statistics::byte_array(Some("x".into()), Some("y".into()), None, 0, false);
+        assert!(!bind("b = 'a'")[0].excludes(Some(&text), 100));
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
+
+use odbc_api::{
+    buffers::AnyColumnView,
+    sys::{Date, Timestamp},
+};
+use serde_json::{json, Value};
+
+/// Number of bits of the hash used to select a HyperLogLog register.
+const HLL_PRECISION: u32 = 12;
+/// Number of HyperLogLog registers per column. One byte each, so a sketch takes 4 KiB.
+const HLL_NUM_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// Statistics about the values of a single column, computed while streaming through the result
+/// set. Memory usage does not depend on the number of rows.
+pub struct ColumnProfile {
+    name: String,
+    num_values: u64,
+    num_nulls: u64,
+    /// Smallest and largest value. NaN has no place in the order of floats, so it is counted as a
+    /// value, but never reported as minimum or maximum.
+    min_max: Option<(Scalar, Scalar)>,
+    /// Maximum length in bytes. Only tracked for text columns.
+    max_len: Option<usize>,
+    distinct: HyperLogLog,
+}
+
+impl ColumnProfile {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            num_values: 0,
+            num_nulls: 0,
+            min_max: None,
+            max_len: None,
+            distinct: HyperLogLog::new(),
+        }
+    }
+
+    /// Account for the values of the column in the current batch.
+    ///
+    /// # Parameters
+    ///
+    /// * `view`: Values of the column in the current batch.
+    /// * `selection`: If `Some`, only rows marked with `true` are taken into account.
+    pub fn observe(&mut self, view: AnyColumnView, selection: Option<&[bool]>) {
+        match view {
+            AnyColumnView::NullableBit(it) => {
+                self.observe_fixed(it, selection, |bit| Scalar::Int(bit.as_bool() as i64))
+            }
+            AnyColumnView::Bit(bits) => {
+                let it = bits.iter().map(Some);
+                self.observe_fixed(it, selection, |bit| Scalar::Int(bit.as_bool() as i64))
+            }
+            AnyColumnView::NullableDate(it) => {
+                self.observe_fixed(it, selection, |&date| Scalar::Date(date))
+            }
+            AnyColumnView::NullableTimestamp(it) => {
+                self.observe_fixed(it, selection, |&ts| Scalar::Timestamp(ts))
+            }
+            AnyColumnView::NullableI32(it) => {
+                self.observe_fixed(it, selection, |&i| Scalar::Int(i as i64))
+            }
+            AnyColumnView::NullableI64(it) => {
+                self.observe_fixed(it, selection, |&i| Scalar::Int(i))
+            }
+            AnyColumnView::NullableF32(it) => {
+                self.observe_fixed(it, selection, |&f| Scalar::Float(f as f64))
+            }
+            AnyColumnView::NullableF64(it) => {
+                self.observe_fixed(it, selection, |&f| Scalar::Float(f))
+            }
+            AnyColumnView::Text(it) => {
+                for (index, item) in it.enumerate() {
+                    if selection.is_some_and(|s| !s[index]) {
+                        continue;
+                    }
+                    self.num_values += 1;
+                    if let Some(text) = item {
+                        let bytes = text.to_bytes();
+                        self.max_len = Some(self.max_len.unwrap_or(0).max(bytes.len()));
+                        self.distinct.insert(bytes);
+                    } else {
+                        self.num_nulls += 1;
+                    }
+                }
+            }
+            // We never bind any other buffer type.
+            _ => (),
+        }
+    }
+
+    fn observe_fixed<'a, T: 'a>(
+        &mut self,
+        it: impl Iterator<Item = Option<&'a T>>,
+        selection: Option<&[bool]>,
+        to_scalar: impl Fn(&T) -> Scalar,
+    ) {
+        for (index, item) in it.enumerate() {
+            if selection.is_some_and(|s| !s[index]) {
+                continue;
+            }
+            self.num_values += 1;
+            if let Some(value) = item {
+                let scalar = to_scalar(value);
+                scalar.hash_into(&mut self.distinct);
+                if scalar.is_nan() {
+                    continue;
+                }
+                self.min_max = match self.min_max.take() {
+                    None => Some((scalar, scalar)),
+                    Some((min, max)) => Some((min.min(scalar), max.max(scalar))),
+                };
+            } else {
+                self.num_nulls += 1;
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "num_values": self.num_values,
+            "num_nulls": self.num_nulls,
+            "min": self.min_max.map(|(min, _)| min.to_string()),
+            "max": self.min_max.map(|(_, max)| max.to_string()),
+            "max_len": self.max_len,
+            "approx_distinct": self.distinct.estimate(),
+        })
+    }
+}
+
+/// Print profiles as a human readable table.
+pub fn print_table(profiles: &[ColumnProfile]) {
+    let headers = ["Column", "Nulls", "Min", "Max", "Max length", "~Distinct"];
+    let rows: Vec<[String; 6]> = profiles
+        .iter()
+        .map(|p| {
+            let opt = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+            [
+                p.name.clone(),
+                p.num_nulls.to_string(),
+                opt(p.min_max.map(|(min, _)| min.to_string())),
+                opt(p.min_max.map(|(_, max)| max.to_string())),
+                opt(p.max_len.map(|len| len.to_string())),
+                p.distinct.estimate().to_string(),
+            ]
+        })
+        .collect();
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let print_row = |cells: &[&str]| {
+        let line: Vec<_> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join(" | ").trim_end());
+    };
+    print_row(&headers);
+    let separator: Vec<_> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    println!("{}", separator.join("-|-"));
+    for row in &rows {
+        let cells: Vec<_> = row.iter().map(String::as_str).collect();
+        print_row(&cells);
+    }
+}
+
+/// A non NULL value of a column with a natural order, used to track minimum and maximum.
+#[derive(Clone, Copy)]
+enum Scalar {
+    Int(i64),
+    Float(f64),
+    Date(Date),
+    Timestamp(Timestamp),
+}
+
+impl Scalar {
+    fn hash_into(&self, hll: &mut HyperLogLog) {
+        match self {
+            Scalar::Int(i) => hll.insert(i),
+            Scalar::Float(f) => hll.insert(f.to_bits()),
+            Scalar::Date(d) => hll.insert(d),
+            Scalar::Timestamp(ts) => hll.insert(ts),
+        }
+    }
+
+    fn is_nan(&self) -> bool {
+        matches!(self, Scalar::Float(f) if f.is_nan())
+    }
+
+    /// Tuple used to compare two values. Values within one column always share the same variant.
+    fn key(&self) -> (f64, [i64; 7]) {
+        match *self {
+            Scalar::Int(i) => (0., [i, 0, 0, 0, 0, 0, 0]),
+            Scalar::Float(f) => (f, [0; 7]),
+            Scalar::Date(d) => (
+                0.,
+                [d.year as i64, d.month as i64, d.day as i64, 0, 0, 0, 0],
+            ),
+            Scalar::Timestamp(ts) => (
+                0.,
+                [
+                    ts.year as i64,
+                    ts.month as i64,
+                    ts.day as i64,
+                    ts.hour as i64,
+                    ts.minute as i64,
+                    ts.second as i64,
+                    ts.fraction as i64,
+                ],
+            ),
+        }
+    }
+
+    fn min(self, other: Self) -> Self {
+        if other.key() < self.key() {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn max(self, other: Self) -> Self {
+        if other.key() > self.key() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl Display for Scalar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scalar::Int(i) => write!(f, "{}", i),
+            Scalar::Float(v) => write!(f, "{}", v),
+            Scalar::Date(d) => write!(f, "{:04}-{:02}-{:02}", d.year, d.month, d.day),
+            Scalar::Timestamp(ts) => write!(
+                f,
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09}",
+                ts.year, ts.month, ts.day, ts.hour, ts.minute, ts.second, ts.fraction
+            ),
+        }
+    }
+}
+
+/// Approximates the number of distinct values with bounded memory.
+///
+/// See: Flajolet et al. "HyperLogLog: the analysis of a near-optimal cardinality estimation
+/// algorithm". With 4096 registers the standard error is about 1.6%.
+struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; HLL_NUM_REGISTERS],
+        }
+    }
+
+    fn insert(&mut self, value: impl Hash) {
+        // Default hasher is created with fixed keys, so results are deterministic.
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        // Position of the first set bit in the remaining bits. Make sure we terminate, even if all
+        // remaining bits are zero.
+        let remaining = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = remaining.leading_zeros() as u8 + 1;
+        if self.registers[index] < rank {
+            self.registers[index] = rank;
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = HLL_NUM_REGISTERS as f64;
+        let alpha = 0.7213 / (1. + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let num_zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        let estimate = if raw <= 2.5 * m && num_zeros != 0 {
+            // Small range correction: Linear counting
+            m * (m / num_zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use odbc_api::buffers::{BufferDescription, BufferKind};
+
+    use super::{ColumnProfile, HyperLogLog};
+    use crate::fake::{FakeColumn, FakeResultSet};
+
+    fn observe(column: FakeColumn, kind: BufferKind) -> ColumnProfile {
+        let buffers = [(
+            1,
+            BufferDescription {
+                nullable: true,
+                kind,
+            },
+        )];
+        let batch = FakeResultSet::new(vec![column]).batch(&buffers);
+        let mut profile = ColumnProfile::new("a".to_owned());
+        profile.observe(batch.column(0), None);
+        profile
+    }
+
+    #[test]
+    fn distinct_estimate_within_expected_error() {
+        for &cardinality in &[0u64, 10, 1_000, 10_000, 100_000] {
+            let mut hll = HyperLogLog::new();
+            // Every value twice, duplicates must not count.
+            for value in (0..cardinality).chain(0..cardinality) {
+                hll.insert(value);
+            }
+            let estimate = hll.estimate() as f64;
+            // Three times the standard error of 1.6%.
+            let tolerance = (cardinality as f64 * 0.05).max(1.);
+            assert!(
+                (estimate - cardinality as f64).abs() <= tolerance,
+                "Estimated {} distinct values, expected {}",
+                estimate,
+                cardinality
+            );
+        }
+    }
+
+    #[test]
+    fn min_max_skip_nulls_and_nan() {
+        let profile = observe(
+            FakeColumn::f64("a", &[None, Some(f64::NAN), Some(2.5), None, Some(-1.)]),
+            BufferKind::F64,
+        );
+        let json = profile.to_json();
+        assert_eq!(5, json["num_values"]);
+        assert_eq!(2, json["num_nulls"]);
+        assert_eq!("-1", json["min"]);
+        assert_eq!("2.5", json["max"]);
+        assert_eq!(3, json["approx_distinct"]);
+    }
+
+    #[test]
+    fn no_min_max_without_comparable_values() {
+        let json = observe(
+            FakeColumn::f64("a", &[None, Some(f64::NAN)]),
+            BufferKind::F64,
+        )
+        .to_json();
+        assert!(json["min"].is_null());
+        assert!(json["max"].is_null());
+        assert_eq!(1, json["num_nulls"]);
+    }
+
+    #[test]
+    fn min_max_of_integers() {
+        let json = observe(
+            FakeColumn::i64("a", &[None, Some(3), Some(-7)]),
+            BufferKind::I64,
+        )
+        .to_json();
+        assert_eq!("-7", json["min"]);
+        assert_eq!("3", json["max"]);
+    }
+}
@@ -1,315 +1,1626 @@
 use std::{
-    convert::TryInto,
-    fs::File,
+    fmt,
+    fs::{self, File},
+    io::{self, Seek, SeekFrom, Write},
+    ops::Range,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, Context, Error};
 use log::{debug, info, warn};
 use odbc_api::{
     buffers::{AnyColumnView, BufferDescription, BufferKind, ColumnarRowSet},
-    ColumnDescription, Cursor, DataType, Environment, IntoParameter, Nullability,
+    parameter::WithDataType,
+    sys::Timestamp,
+    Connection, Cursor, DataType, Environment, IntoParameter, Nullability, ParameterCollection,
 };
 use parquet::{
-    basic::{LogicalType, Repetition, Type as PhysicalType},
-    column::writer::ColumnWriter,
-    errors::ParquetError,
+    basic::{Compression, LogicalType, Repetition, Type as PhysicalType},
+    column::writer::{ColumnWriter, ColumnWriterImpl},
+    data_type::Int64Type,
     file::{
+        metadata::KeyValue,
         properties::WriterProperties,
-        writer::{FileWriter, RowGroupWriter, SerializedFileWriter},
+        writer::{
+            FileWriter, InMemoryWriteableCursor, RowGroupWriter, SerializedFileWriter, TryClone,
+        },
     },
-    schema::types::{Type, TypePtr},
+    schema::types::{PrimitiveTypeBuilder, Type, TypePtr},
 };
 
-use crate::{open_connection, parquet_buffer::ParquetBuffer, QueryOpt};
+use crate::{
+    append::{temporary_path, ExistingFile},
+    budget::{self, available_memory, BufferBudget},
+    column_mapping::{column_name, ColumnMapping, DescribeColumns},
+    deadline::Deadline,
+    dedupe::Deduplicator,
+    estimate::{count_query, Estimate},
+    explain::{self, mask_method_text, ColumnExplanation, ExplainFormat},
+    fetch::{check_num_rows_fetched, Accumulator, Batches},
+    field_id::check_unique,
+    fraction::{scale_digits, warn_if_implausible, FractionUnit},
+    hook::{FileHook, Upload},
+    lineage::lineage_metadata,
+    mask::MaskMethod,
+    metrics::{timed, Metrics, Stage},
+    null_default::{normalize_decimal, FromSentinel, Sentinel},
+    open_connection,
+    output_path::{refuse_existing, OutputPath},
+    parquet_buffer::{ConversionErrorPolicy, ParquetBuffer},
+    profile::{print_table, ColumnProfile},
+    sampling::Sampler,
+    strict::{column_losses, LossPolicy, LossyRule},
+    summary::Summary,
+    user_metadata, QueryOpt,
+};
 
-/// Execute a query and writes the result to parquet.
-pub fn query(environment: &Environment, opt: &QueryOpt) -> Result<(), Error> {
+/// Execute a query and writes the result to parquet. Returns `true` if the export completed with
+/// warnings, i.e. unsupported columns have been left out or `--max-duration` cut it short.
+pub fn query(environment: &Environment, opt: &QueryOpt) -> Result<bool, Error> {
     let QueryOpt {
         connect_opts,
-        output,
         parameters,
         query,
-        batch_size,
+        sample_rate,
+        sample_seed,
+        field_ids,
+        estimate,
+        no_describe_param,
+        max_duration,
+        output,
         batches_per_file,
+        file_size_threshold,
+        suffix_length,
+        always_suffix,
+        overwrite,
+        append,
+        no_write,
+        output_base64,
+        explain_mapping,
+        metadata,
+        ..
     } = opt;
+    // Connecting and executing the query count towards the time window, too.
+    let deadline = max_duration.map(Deadline::new);
+
+    // Fail before executing a potentially expensive query.
+    check_unique(field_ids)?;
+    user_metadata::check_unique(metadata)?;
+    // Every numbered file of a split output is checked up front, so a split export does not fail
+    // halfway. Files are checked again once they are started, in case they appeared meanwhile.
+    let writes_file = !*no_write
+        && !*output_base64
+        && !output.is_stdout()
+        && !matches!(explain_mapping, Some(ExplainFormat::Json));
+    if writes_file && !*overwrite && !*append {
+        let splitting = *batches_per_file != 0 || file_size_threshold.is_some();
+        if !splitting || !*always_suffix {
+            refuse_existing(&output.single(*suffix_length))?;
+        }
+        if splitting {
+            output.refuse_existing_numbered(*suffix_length)?;
+        }
+    }
+
+    let sampler = sample_rate.map(|rate| {
+        let seed = sample_seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+        });
+        info!("Sampling rows with seed {}.", seed);
+        Sampler::new(rate, seed)
+    });
 
+    let odbc_conn = open_connection(environment, connect_opts)?;
+
+    let parameter_types = if *no_describe_param || parameters.is_empty() {
+        Vec::new()
+    } else {
+        describe_parameters(&odbc_conn, query, parameters.len())
+    };
     // Convert the input strings into parameters suitable to for use with ODBC.
     let params: Vec<_> = parameters
         .iter()
-        .map(|param| param.into_parameter())
+        .enumerate()
+        .map(|(index, param)| {
+            let text = DataType::Varchar {
+                length: param.len(),
+            };
+            let data_type = parameter_types
+                .get(index)
+                .copied()
+                .flatten()
+                .unwrap_or(text);
+            debug!("Binding parameter {} as {:?}.", index + 1, data_type);
+            WithDataType {
+                value: param.as_str().into_parameter(),
+                data_type,
+            }
+        })
         .collect();
 
-    let odbc_conn = open_connection(&environment, connect_opts)?;
-
-    if let Some(cursor) = odbc_conn.execute(query, params.as_slice())? {
-        cursor_to_parquet(cursor, output, *batch_size, *batches_per_file)?;
+    // Counted before executing the query itself, since many drivers allow only one open cursor per
+    // connection.
+    let num_rows = if *estimate {
+        count_rows(&odbc_conn, query, params.as_slice())?
     } else {
-        eprintln!(
-            "Query came back empty (not even a schema has been returned). No file has been created"
-        );
+        None
+    };
+
+    let execute = || {
+        let cursor = odbc_conn.execute(query, params.as_slice());
+        // Diagnostics of preparing and executing the statement are logged by odbc-api. Report
+        // how often each of them has been repeated, before the first batch is fetched.
+        log::logger().flush();
+        cursor
+    };
+    // odbc-api reports statements without any result column, like `UPDATE`, as no cursor.
+    let cursor = execute()?.ok_or(NoResultSet)?;
+    cursor_to_parquet(cursor, execute, opt, sampler, num_rows, deadline)
+}
+
+/// The statement has been executed, but did not produce a result set with any columns. E.g.
+/// because it only modifies data.
+#[derive(Debug)]
+pub struct NoResultSet;
+
+impl fmt::Display for NoResultSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            "Statement produced no result set. Did you mean to use a different tool or add a \
+            SELECT? No file has been created.",
+        )
     }
-    Ok(())
 }
 
-fn cursor_to_parquet(
-    cursor: impl Cursor,
-    path: &Path,
-    batch_size: u32,
-    batches_per_file: u32,
-) -> Result<(), Error> {
+impl std::error::Error for NoResultSet {}
+
+/// The result set is empty, so no file has been left behind due to `--no-empty-file`. Reported
+/// after the export is otherwise complete, e.g. the summary has been written.
+#[derive(Debug)]
+pub struct EmptyResultSet;
+
+impl fmt::Display for EmptyResultSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Result set is empty. No file has been written due to --no-empty-file.")
+    }
+}
+
+impl std::error::Error for EmptyResultSet {}
+
+/// Returns `true` if unsupported columns have been left out, or rows due to `--max-duration`.
+///
+/// # Parameters
+///
+/// * `execute_again`: Used to obtain a new cursor, should the first one turn out to be unusable
+///   for block cursors.
+/// * `num_rows`: Number of rows in the result set, if counted for `--estimate`.
+/// * `deadline`: Stop fetching before this deadline is missed. Set by `--max-duration`.
+fn cursor_to_parquet<C: Cursor>(
+    cursor: C,
+    execute_again: impl FnOnce() -> Result<Option<C>, odbc_api::Error>,
+    opt: &QueryOpt,
+    mut sampler: Option<Sampler>,
+    num_rows: Option<u64>,
+    mut deadline: Option<Deadline>,
+) -> Result<bool, Error> {
+    let QueryOpt {
+        output: path,
+        batch_size,
+        batch_memory,
+        batches_per_file,
+        file_size_threshold,
+        suffix_length,
+        masks,
+        on_conversion_error,
+        round_decimals,
+        profile,
+        no_write,
+        summary_file,
+        metrics_file,
+        on_file_complete,
+        hook_timeout,
+        hook_failure_aborts,
+        upload_command,
+        remove_after_upload,
+        output_base64,
+        max_inline_size,
+        always_suffix,
+        no_empty_file,
+        dedupe_on,
+        dedupe_max_keys,
+        row_group_memory_limit,
+        buffer_memory_limit,
+        rows_per_row_group,
+        row_group_per_batch,
+        explain_mapping,
+        strict,
+        estimate,
+        yes,
+        timestamp_fraction_unit,
+        reproducible,
+        append,
+        no_atomic,
+        overwrite,
+        no_query_metadata,
+        metadata,
+        column_compression_default,
+        ..
+    } = opt;
+    let loss_policy = LossPolicy::new(*strict);
+
+    let hook = on_file_complete.as_ref().map(|command| FileHook {
+        command: command.clone(),
+        timeout: *hook_timeout,
+        failure_aborts: *hook_failure_aborts,
+    });
+    let upload = upload_command.as_ref().map(|command| Upload {
+        command: command.clone(),
+        remove_after_upload: *remove_after_upload,
+    });
+
+    let schema = make_schema(&cursor, opt)?;
+    if let Some(ExplainFormat::Json) = explain_mapping {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&explain::to_json(&schema.explanations))?
+        );
+        return Ok(!schema.skipped_columns.is_empty());
+    }
+    // Fail before fetching anything, if the rows do not fit into the existing file.
+    let existing = if *append {
+        ExistingFile::open(&path.single(*suffix_length))?
+    } else {
+        None
+    };
+    if let Some(existing) = &existing {
+        existing.check_schema(&schema.parquet)?;
+    }
+    let num_rows_prior = append.then(|| existing.as_ref().map_or(0, ExistingFile::num_rows));
+    let mappings: Vec<_> = schema
+        .explanations
+        .iter()
+        .filter(|explanation| explanation.buffer.is_some())
+        .map(|explanation| explanation.mapping.clone())
+        .collect();
+    let batch_size = budget::batch_size(*batch_size, *batch_memory, &mappings)?;
     info!("Batch size set to {}", batch_size);
+    // Fail before allocating the buffers, rather than running out of memory.
+    BufferBudget::new(&mappings, batch_size)
+        .check(buffer_memory_limit.or_else(available_memory))?;
+    if *estimate {
+        print!("{}", Estimate::new(&mappings, num_rows));
+        if !*yes && !confirm("Start the export?")? {
+            eprintln!("Export cancelled.");
+            return Ok(!schema.skipped_columns.is_empty());
+        }
+    }
+    let Schema {
+        parquet: parquet_schema,
+        buffers: buffer_description,
+        buffer_names,
+        sentinels,
+        sources,
+        ..
+    } = &schema;
+    let mut deduplicator = if dedupe_on.is_empty() {
+        None
+    } else {
+        Some(Deduplicator::new(
+            dedupe_on,
+            buffer_names,
+            buffer_description,
+            *dedupe_max_keys,
+            loss_policy,
+        )?)
+    };
+    // Measured from here on, so neither the query nor waiting for confirmation is included.
+    let mut metrics = metrics_file.as_ref().map(|_| Metrics::new());
+    let mut batches = Batches::bind(cursor, execute_again, buffer_description, batch_size)?;
 
-    let (parquet_schema, buffer_description) = make_schema(&cursor)?;
-    let mut odbc_buffer =
-        ColumnarRowSet::with_column_indices(batch_size, buffer_description.iter().copied());
-    let mut row_set_cursor = cursor.bind_buffer(&mut odbc_buffer)?;
+    // Timestamp columns and their precision, whose fractions are checked in the first batch.
+    let mut fraction_checks: Vec<(usize, i16)> = if *timestamp_fraction_unit == FractionUnit::Auto {
+        timestamp_precisions(&schema)
+    } else {
+        Vec::new()
+    };
 
-    let mut pb = ParquetBuffer::new(batch_size as usize);
+    let mut pb = ParquetBuffer::new(
+        batch_size as usize,
+        *on_conversion_error,
+        loss_policy,
+        *timestamp_fraction_unit,
+        *round_decimals,
+    );
     let mut num_batch = 0;
+    // Only used if sampling or deduplicating. `true` for each row of the current batch, which is
+    // to be written.
+    let mut selection = Vec::new();
+    // Estimated size of each row in the current batch, once written to parquet.
+    let mut row_sizes = Vec::new();
+    // Only used if a batch is split into multiple row groups. `true` for each row of the current
+    // row group.
+    let mut row_group_selection = Vec::new();
+    let max_rows_per_row_group = if *row_group_per_batch {
+        usize::MAX
+    } else {
+        rows_per_row_group.unwrap_or(batch_size) as usize
+    };
+    // Collects the rows of batches, which do not fill a row group on their own. Allocated once
+    // first needed, since full batches are written directly from the fetch buffer.
+    let mut accumulator: Option<Accumulator> = None;
+    // Number of rows and estimated bytes held by the accumulator.
+    let mut accumulated = (0, 0);
 
-    let mut writer =
-        ParquetWriter::new(path, batch_size, parquet_schema.clone(), batches_per_file)?;
-
-    while let Some(buffer) = row_set_cursor.fetch()? {
-        let mut row_group_writer = writer.next_row_group(num_batch)?;
-        let mut col_index = 0;
-        num_batch += 1;
-        let num_rows = buffer.num_rows();
-        info!("Fetched batch {} with {} rows.", num_batch, num_rows);
-        while let Some(mut column_writer) = row_group_writer.next_column()? {
-            pb.set_num_rows_fetched(num_rows);
-            let odbc_column = buffer.column(col_index);
-            match (&mut column_writer, odbc_column) {
-                (ColumnWriter::BoolColumnWriter(cw), AnyColumnView::NullableBit(it)) => {
-                    pb.write_optional(cw, it)?;
-                }
-                (ColumnWriter::Int32ColumnWriter(cw), AnyColumnView::NullableDate(it)) => {
-                    pb.write_optional(cw, it)?;
-                }
-                (ColumnWriter::Int32ColumnWriter(cw), AnyColumnView::NullableI32(it)) => {
-                    pb.write_optional(cw, it)?;
+    let mut key_value_metadata = if *no_query_metadata {
+        Vec::new()
+    } else {
+        lineage_metadata(opt)
+    };
+    // Record which columns have been masked, so it can be audited without knowing the command
+    // line which produced the file.
+    if !masks.is_empty() {
+        let mut masked_columns: Vec<_> = masks.iter().map(|m| m.column.as_str()).collect();
+        // Independent of the order of the `--mask` options.
+        if *reproducible {
+            masked_columns.sort_unstable();
+        }
+        key_value_metadata.push(KeyValue::new(
+            "odbc2parquet.masked_columns".to_owned(),
+            masked_columns.join(","),
+        ));
+    }
+    user_metadata::merge(&mut key_value_metadata, metadata);
+    let key_value_metadata = if key_value_metadata.is_empty() {
+        None
+    } else {
+        Some(key_value_metadata)
+    };
+
+    let mut writer = if *no_write {
+        None
+    } else {
+        Some(ParquetWriter::new(
+            path,
+            batch_size,
+            parquet_schema.clone(),
+            *batches_per_file,
+            *file_size_threshold,
+            *suffix_length,
+            *always_suffix,
+            *no_empty_file,
+            key_value_metadata,
+            *column_compression_default,
+            *reproducible,
+            existing,
+            !*no_atomic,
+            *overwrite,
+            hook.as_ref(),
+            upload.as_ref(),
+            if *output_base64 {
+                Some(*max_inline_size)
+            } else {
+                None
+            },
+        )?)
+    };
+
+    let mut summary = Summary {
+        num_rows_prior,
+        single_row_fetch: batches.is_single_row(),
+        skipped_columns: schema.skipped_columns.clone(),
+        ..Summary::default()
+    };
+    if *profile {
+        // Profile the columns of the result set, rather than the ones derived from them.
+        summary.profiles = buffer_names
+            .iter()
+            .cloned()
+            .map(ColumnProfile::new)
+            .collect();
+    }
+
+    // Number of NULLs replaced due to `--null-default` for each parquet column.
+    let mut nulls_substituted = vec![0u64; sources.len()];
+
+    // Kept apart from the closing of the writer, so we can react to a full disk in one place.
+    let mut write_batches = || -> Result<(), Error> {
+        loop {
+            if deadline
+                .as_mut()
+                .is_some_and(|deadline| !deadline.next_batch())
+            {
+                summary.time_boxed = true;
+                break;
+            }
+            let buffer = match timed(&mut metrics, Stage::Fetch, || batches.fetch())? {
+                Some(buffer) => buffer,
+                None => break,
+            };
+            num_batch += 1;
+            let num_rows_fetched = buffer.num_rows();
+            check_num_rows_fetched(num_rows_fetched, batch_size as usize)?;
+            info!(
+                "Fetched batch {} with {} rows.",
+                num_batch, num_rows_fetched
+            );
+            for (buffer_index, precision) in fraction_checks.drain(..) {
+                if let AnyColumnView::NullableTimestamp(it) = buffer.column(buffer_index) {
+                    warn_if_implausible(&buffer_names[buffer_index], precision, it);
                 }
-                (ColumnWriter::Int64ColumnWriter(cw), AnyColumnView::NullableTimestamp(it)) => {
-                    pb.write_timestamp(cw, it, &*parquet_schema.get_fields()[col_index])?;
+            }
+            // Decide which rows to write, before spending any effort on converting them.
+            let mut num_rows = num_rows_fetched;
+            if let Some(sampler) = sampler.as_mut() {
+                num_rows = sampler.select(num_rows_fetched, &mut selection);
+            } else if deduplicator.is_some() {
+                selection.clear();
+                selection.resize(num_rows_fetched, true);
+            }
+            // Sampled out rows do not count as seen, so they do not cause later rows to be dropped.
+            if let Some(deduplicator) = deduplicator.as_mut() {
+                num_rows = deduplicator.select(buffer, &mut selection, num_batch)?;
+            }
+            let selection = if sampler.is_some() || deduplicator.is_some() {
+                Some(selection.as_slice())
+            } else {
+                None
+            };
+            summary.num_rows_fetched += num_rows_fetched as u64;
+            if num_rows == 0 {
+                // Do not write empty row groups
+                continue;
+            }
+            for (col_index, profile) in summary.profiles.iter_mut().enumerate() {
+                profile.observe(buffer.column(col_index), selection);
+            }
+            let writer = if let Some(writer) = writer.as_mut() {
+                writer
+            } else {
+                continue;
+            };
+            summary.num_rows_written += num_rows as u64;
+            // The parquet writer holds each row group in memory until it is closed, so large
+            // batches may need to be split into several row groups.
+            estimate_row_sizes(buffer, sources, &mut row_sizes);
+            let row_groups = split_into_row_groups(
+                &row_sizes,
+                selection,
+                *row_group_memory_limit,
+                max_rows_per_row_group,
+                accumulated,
+            );
+            let num_row_groups = row_groups.len();
+            for (index, row_group) in row_groups.into_iter().enumerate() {
+                let selection = if num_row_groups == 1 {
+                    selection
+                } else {
+                    row_group_selection.clear();
+                    row_group_selection.extend((0..num_rows_fetched).map(|row| {
+                        row_group.rows.contains(&row) && selection.is_none_or(|s| s[row])
+                    }));
+                    Some(row_group_selection.as_slice())
+                };
+                // Only the last row group of a batch may be continued by the next one.
+                let complete =
+                    index + 1 != num_row_groups || row_group.num_rows == max_rows_per_row_group;
+                if *row_group_per_batch || (accumulated.0 == 0 && complete) {
+                    // Write directly from the fetched buffer, rather than copying the rows.
+                    let starts_batch = index == 0 || !*row_group_per_batch;
+                    let mut row_group_writer =
+                        writer.next_row_group(row_group.num_rows, starts_batch)?;
+                    timed(&mut metrics, Stage::Convert, || {
+                        write_row_group(
+                            row_group_writer.as_mut(),
+                            buffer,
+                            selection,
+                            &schema,
+                            &mut pb,
+                            &mut nulls_substituted,
+                            num_batch,
+                        )
+                    })?;
+                    timed(&mut metrics, Stage::Write, || {
+                        writer.close_row_group(row_group_writer)
+                    })?;
+                    summary
+                        .row_groups
+                        .push((row_group.num_rows as u64, row_group.num_bytes));
+                    continue;
                 }
-                (ColumnWriter::Int64ColumnWriter(cw), AnyColumnView::NullableI64(it)) => {
-                    pb.write_optional(cw, it)?;
+                let accumulator = accumulator.get_or_insert_with(|| {
+                    Accumulator::new(buffer_description, max_rows_per_row_group)
+                });
+                accumulator.push(buffer, selection);
+                accumulated = (row_group.num_rows, row_group.num_bytes);
+                if complete {
+                    write_accumulated(
+                        writer,
+                        accumulator,
+                        &schema,
+                        &mut pb,
+                        &mut nulls_substituted,
+                        num_batch,
+                        &mut metrics,
+                    )?;
+                    summary
+                        .row_groups
+                        .push((accumulated.0 as u64, accumulated.1));
+                    accumulated = (0, 0);
                 }
-                (ColumnWriter::FloatColumnWriter(cw), AnyColumnView::NullableF32(it)) => {
-                    pb.write_optional(cw, it)?;
+            }
+        }
+        // Rows of the last batches, which did not fill a row group.
+        if let (Some(writer), Some(accumulator)) = (writer.as_mut(), accumulator.as_mut()) {
+            if accumulated.0 != 0 {
+                write_accumulated(
+                    writer,
+                    accumulator,
+                    &schema,
+                    &mut pb,
+                    &mut nulls_substituted,
+                    num_batch,
+                    &mut metrics,
+                )?;
+                summary
+                    .row_groups
+                    .push((accumulated.0 as u64, accumulated.1));
+            }
+        }
+        Ok(())
+    };
+    if let Err(error) = write_batches() {
+        return Err(match writer {
+            Some(writer) => writer.handle_write_error(error),
+            None => error,
+        });
+    }
+
+    if let Some(writer) = writer {
+        summary.uploaded_files = timed(&mut metrics, Stage::Write, || writer.close())?;
+    }
+
+    // Make replaced NULLs visible, since these values are not part of the source data.
+    let fields = parquet_schema.get_fields().iter().zip(sources);
+    for ((field, source), &num) in fields.zip(&nulls_substituted) {
+        if sentinels[source.buffer_index].is_some() {
+            info!("Replaced {} NULLs in column '{}'.", num, field.name());
+            summary
+                .nulls_substituted
+                .push((field.name().to_owned(), num));
+        }
+    }
+    if summary.time_boxed {
+        // Only known if the rows have been counted for `--estimate`.
+        summary.num_rows_remaining =
+            num_rows.map(|num| num.saturating_sub(summary.num_rows_fetched));
+    }
+    if let Some(deduplicator) = &deduplicator {
+        info!("Dropped {} duplicate rows.", deduplicator.num_dropped());
+        summary.num_duplicates_dropped = Some(deduplicator.num_dropped());
+    }
+    if *profile {
+        print_table(&summary.profiles);
+    }
+
+    if let Some(summary_file) = summary_file {
+        summary.write_json(summary_file)?;
+    }
+    if let (Some(metrics), Some(metrics_file)) = (&metrics, metrics_file) {
+        metrics.write_json(metrics_file, summary.num_rows_fetched)?;
+    }
+
+    if let Some(sampler) = sampler {
+        info!(
+            "Sampled {} of {} rows.",
+            sampler.num_rows_selected(),
+            sampler.num_rows_seen()
+        );
+    }
+
+    // Repeated at the end, so it is not lost among the log output of the export.
+    let skipped_columns = &schema.skipped_columns;
+    if !skipped_columns.is_empty() {
+        let names: Vec<_> = skipped_columns
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        warn!(
+            "Export completed, but {} unsupported columns have been left out: {}",
+            names.len(),
+            names.join(", ")
+        );
+    }
+
+    if summary.time_boxed {
+        let remaining = summary
+            .num_rows_remaining
+            .map(|num| format!(" {} rows of the result set are left.", num))
+            .unwrap_or_default();
+        warn!(
+            "Export stopped early due to --max-duration, after fetching {} rows. The output is \
+            complete, but lacks the remaining rows.{}",
+            summary.num_rows_fetched, remaining
+        );
+    }
+
+    // Same condition under which the writer removed the file.
+    if *no_empty_file && summary.num_rows_written == 0 && summary.num_rows_prior.unwrap_or(0) == 0 {
+        return Err(EmptyResultSet.into());
+    }
+
+    Ok(!skipped_columns.is_empty() || summary.time_boxed)
+}
+
+/// Index and precision of each buffer bound to a timestamp column.
+fn timestamp_precisions(schema: &Schema) -> Vec<(usize, i16)> {
+    schema
+        .buffers
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, description))| matches!(description.kind, BufferKind::Timestamp))
+        .filter_map(|(buffer_index, (col_index, _))| {
+            let explanation = schema
+                .explanations
+                .iter()
+                .find(|explanation| explanation.mapping.index == *col_index)?;
+            match explanation.mapping.data_type {
+                DataType::Timestamp { precision } => Some((buffer_index, precision)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// SQL type the driver expects for each parameter placeholder in `query`. `None` for parameters
+/// which are bound as `VARCHAR`, because the driver can not describe them or expects text anyway.
+fn describe_parameters(
+    conn: &Connection,
+    query: &str,
+    num_parameters: usize,
+) -> Vec<Option<DataType>> {
+    let prepared = match conn.prepare(query) {
+        Ok(prepared) => prepared,
+        Err(error) => {
+            debug!(
+                "Binding parameters as VARCHAR, since the query could not be prepared: {}",
+                error
+            );
+            return vec![None; num_parameters];
+        }
+    };
+    (1..=num_parameters)
+        .map(|number| match prepared.describe_param(number as u16) {
+            Ok(description) => match description.data_type {
+                DataType::Unknown
+                | DataType::Char { .. }
+                | DataType::Varchar { .. }
+                | DataType::WVarchar { .. }
+                | DataType::Other { .. } => None,
+                data_type => Some(data_type),
+            },
+            Err(error) => {
+                debug!("Driver could not describe parameter {}: {}", number, error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Number of rows returned by `query`, or `None` if it can not be counted safely.
+fn count_rows(
+    conn: &Connection,
+    query: &str,
+    params: impl ParameterCollection,
+) -> Result<Option<u64>, Error> {
+    let count_query = match count_query(query) {
+        Some(count_query) => count_query,
+        None => {
+            warn!("Not counting rows for --estimate, since the query can not be wrapped safely.");
+            return Ok(None);
+        }
+    };
+    debug!("Counting rows with: {}", count_query);
+    let cursor = match conn.execute(&count_query, params)? {
+        Some(cursor) => cursor,
+        None => return Ok(None),
+    };
+    let buffer = ColumnarRowSet::new(
+        1,
+        std::iter::once(BufferDescription {
+            kind: BufferKind::I64,
+            nullable: true,
+        }),
+    );
+    let mut row_set_cursor = cursor.bind_buffer(buffer)?;
+    let num_rows = match row_set_cursor.fetch()? {
+        Some(batch) => match batch.column(0) {
+            AnyColumnView::NullableI64(mut it) => it.next().flatten().copied(),
+            _ => unreachable!("Buffer is bound as nullable I64."),
+        },
+        None => None,
+    };
+    Ok(num_rows.map(|n| n.max(0) as u64))
+}
+
+/// Ask the user on the terminal. Anything but `y` or `yes` counts as no.
+fn confirm(question: &str) -> Result<bool, Error> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Write the selected rows of `batch` into a row group.
+fn write_row_group(
+    row_group_writer: &mut dyn RowGroupWriter,
+    batch: &ColumnarRowSet,
+    selection: Option<&[bool]>,
+    schema: &Schema,
+    pb: &mut ParquetBuffer,
+    nulls_substituted: &mut [u64],
+    num_batch: u32,
+) -> Result<(), Error> {
+    let Schema {
+        parquet: parquet_schema,
+        sentinels,
+        sources,
+        ..
+    } = schema;
+    let num_rows = selection.map_or(batch.num_rows(), |s| s.iter().filter(|&&row| row).count());
+    let mut col_index = 0;
+    while let Some(mut column_writer) = row_group_writer.next_column()? {
+        pb.set_num_rows_fetched(num_rows);
+        let field = &parquet_schema.get_fields()[col_index];
+        pb.set_required(field.get_basic_info().repetition() == Repetition::REQUIRED);
+        let source = &sources[col_index];
+        let odbc_column = batch.column(source.buffer_index);
+        let sentinel = sentinels[source.buffer_index].as_ref();
+        let num_substituted = &mut nulls_substituted[col_index];
+        let result = match (&mut column_writer, odbc_column) {
+            (ColumnWriter::BoolColumnWriter(cw), AnyColumnView::NullableBit(it)) => {
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_optional(cw, it)
+            }
+            (ColumnWriter::BoolColumnWriter(cw), AnyColumnView::Bit(bits)) => {
+                pb.write_bits(cw, bits, selection)
+            }
+            (ColumnWriter::Int32ColumnWriter(cw), AnyColumnView::NullableDate(it)) => {
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_date(cw, it)
+            }
+            (ColumnWriter::Int32ColumnWriter(cw), AnyColumnView::NullableTimestamp(it)) => {
+                // Only bound to an INT32 column, if split with `--split-timestamp`.
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_timestamp_date(cw, it)
+            }
+            (ColumnWriter::Int32ColumnWriter(cw), AnyColumnView::Text(it)) => {
+                // Only bound to an INT32 column, if it is a time with at most three digits.
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_time(cw, it)
+            }
+            (ColumnWriter::Int32ColumnWriter(cw), AnyColumnView::NullableI32(it)) => {
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_optional(cw, it)
+            }
+            (ColumnWriter::Int64ColumnWriter(cw), AnyColumnView::NullableTimestamp(it)) => {
+                match pb.fraction_unit {
+                    FractionUnit::Digits(digits) => {
+                        // Normalized before NULLs are substituted, since the sentinel is in
+                        // nanoseconds.
+                        let timestamps = scale_digits(digits, selected(it, selection))?;
+                        let it = timestamps.iter().map(Option::as_ref);
+                        let it = substituted(it, sentinel, num_substituted);
+                        write_timestamps(pb, cw, it, source, field)
+                    }
+                    // Already in nanoseconds, so there is no need to copy them.
+                    FractionUnit::Nanoseconds | FractionUnit::Auto => {
+                        let it = substituted(selected(it, selection), sentinel, num_substituted);
+                        write_timestamps(pb, cw, it, source, field)
+                    }
                 }
-                (ColumnWriter::DoubleColumnWriter(cw), AnyColumnView::NullableF64(it)) => {
-                    pb.write_optional(cw, it)?;
+            }
+            (ColumnWriter::Int64ColumnWriter(cw), AnyColumnView::Text(it)) => {
+                // Only bound to an INT64 column, if it is a time with more than three digits.
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_time(cw, it)
+            }
+            (ColumnWriter::Int64ColumnWriter(cw), AnyColumnView::NullableI64(it)) => {
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_optional(cw, it)
+            }
+            (ColumnWriter::FloatColumnWriter(cw), AnyColumnView::NullableF32(it)) => {
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_optional(cw, it)
+            }
+            (ColumnWriter::DoubleColumnWriter(cw), AnyColumnView::NullableF64(it)) => {
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                pb.write_optional(cw, it)
+            }
+            (ColumnWriter::ByteArrayColumnWriter(cw), AnyColumnView::Text(it)) => {
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                // Masked columns are always bound as text, so this is the only place there
+                // we need to check for them.
+                match &source.transform {
+                    Transform::Mask(mask) => pb.write_masked(cw, it, mask, source.statistics),
+                    Transform::Hex => pb.write_binary(cw, it, None),
+                    _ => pb.write_optional(cw, it),
                 }
-                (ColumnWriter::ByteArrayColumnWriter(cw), AnyColumnView::Text(it)) => {
-                    pb.write_optional(cw, it)?;
+            }
+            (ColumnWriter::FixedLenByteArrayColumnWriter(cw), AnyColumnView::Text(it)) => {
+                let it = substituted(selected(it, selection), sentinel, num_substituted);
+                match source.transform {
+                    Transform::Guid => pb.write_guid(cw, it),
+                    Transform::Hex => {
+                        let length = match field.as_ref() {
+                            Type::PrimitiveType { type_length, .. } => *type_length,
+                            Type::GroupType { .. } => unreachable!("Columns are primitive types."),
+                        };
+                        pb.write_binary(cw, it, Some(length))
+                    }
+                    _ => pb.write_decimal(cw, it, field),
                 }
-                (ColumnWriter::FixedLenByteArrayColumnWriter(cw), AnyColumnView::Text(it)) => {
-                    pb.write_decimal(cw, it, &*parquet_schema.get_fields()[col_index])?;
+            }
+            // ColumnWriter::Int96ColumnWriter(_) => {}
+            _ => panic!(
+                "Invalid ColumnWriter type. This is not supposed to happen. Please \
+                open a Bug at https://github.com/pacman82/odbc2parquet/issues."
+            ),
+        };
+        result.with_context(|| {
+            format!(
+                "Failed to convert column '{}' of batch {}.",
+                parquet_schema.get_fields()[col_index].name(),
+                num_batch
+            )
+        })?;
+        row_group_writer.close_column(column_writer)?;
+        col_index += 1;
+    }
+    Ok(())
+}
+
+/// Estimate the number of bytes each row of `batch` takes in the row group, once written. Only the
+/// values themselves are accounted for.
+fn estimate_row_sizes(batch: &ColumnarRowSet, sources: &[ColumnSource], sizes: &mut Vec<u64>) {
+    sizes.clear();
+    sizes.resize(batch.num_rows(), 0);
+    for source in sources {
+        let fixed_size = match batch.column(source.buffer_index) {
+            AnyColumnView::Text(it) => {
+                for (size, value) in sizes.iter_mut().zip(it) {
+                    *size += match (value, &source.transform) {
+                        (None, _) | (Some(_), Transform::Mask(MaskMethod::Null)) => 0,
+                        // Hex encoded digest
+                        (Some(_), Transform::Mask(MaskMethod::Sha256)) => 64,
+                        (Some(_), Transform::Mask(MaskMethod::Fixed(text))) => text.len() as u64,
+                        (Some(text), _) => text.to_bytes().len() as u64,
+                    }
                 }
-                // ColumnWriter::Int96ColumnWriter(_) => {}
-                _ => panic!(
-                    "Invalid ColumnWriter type. This is not supposed to happen. Please \
-                    open a Bug at https://github.com/pacman82/odbc2parquet/issues."
-                ),
+                continue;
             }
-            row_group_writer.close_column(column_writer)?;
-            col_index += 1;
+            AnyColumnView::NullableBit(_) | AnyColumnView::Bit(_) => 1,
+            AnyColumnView::NullableI32(_)
+            | AnyColumnView::NullableF32(_)
+            | AnyColumnView::NullableDate(_) => 4,
+            AnyColumnView::NullableTimestamp(_) if source.transform == Transform::Date => 4,
+            _ => 8,
+        };
+        for size in sizes.iter_mut() {
+            *size += fixed_size;
         }
-        writer.close_row_group(row_group_writer)?;
     }
+}
 
-    writer.close()?;
+/// Consecutive rows of a batch, written into the same row group.
+struct RowGroupRows {
+    rows: Range<usize>,
+    /// Number of selected rows in `rows`.
+    num_rows: usize,
+    /// Estimated size of the selected rows in bytes.
+    num_bytes: u64,
+}
 
-    Ok(())
+/// Split the rows of a batch into row groups, so that the estimated size of each stays within
+/// `memory_limit` and none holds more than `max_rows`. Each row group holds at least one selected
+/// row, even if that row alone exceeds the limit.
+///
+/// `accumulated` is the number of rows and bytes of earlier batches, which are written into the
+/// same row group as the first rows of this batch. They are accounted for in the first row group
+/// returned, which may consist of them alone.
+fn split_into_row_groups(
+    row_sizes: &[u64],
+    selection: Option<&[bool]>,
+    memory_limit: Option<u64>,
+    max_rows: usize,
+    accumulated: (usize, u64),
+) -> Vec<RowGroupRows> {
+    let limit = memory_limit.unwrap_or(u64::MAX);
+    let mut row_groups = Vec::new();
+    let mut current = RowGroupRows {
+        rows: 0..0,
+        num_rows: accumulated.0,
+        num_bytes: accumulated.1,
+    };
+    for (row, &size) in row_sizes.iter().enumerate() {
+        if selection.is_some_and(|s| !s[row]) {
+            continue;
+        }
+        if current.num_rows != 0
+            && (current.num_bytes.saturating_add(size) > limit || current.num_rows == max_rows)
+        {
+            let start = current.rows.end;
+            row_groups.push(std::mem::replace(
+                &mut current,
+                RowGroupRows {
+                    rows: start..start,
+                    num_rows: 0,
+                    num_bytes: 0,
+                },
+            ));
+        }
+        current.rows.end = row + 1;
+        current.num_rows += 1;
+        current.num_bytes += size;
+    }
+    if current.num_rows != 0 {
+        row_groups.push(current);
+    }
+    row_groups
 }
 
-fn make_schema(cursor: &impl Cursor) -> Result<(TypePtr, Vec<(u16, BufferDescription)>), Error> {
-    let num_cols = cursor.num_result_cols()?;
+/// Writes the rows collected by the accumulator as one row group.
+fn write_accumulated(
+    writer: &mut ParquetWriter,
+    accumulator: &mut Accumulator,
+    schema: &Schema,
+    pb: &mut ParquetBuffer,
+    nulls_substituted: &mut [u64],
+    num_batch: u32,
+    metrics: &mut Option<Metrics>,
+) -> Result<(), Error> {
+    let batch = accumulator.take();
+    let mut row_group_writer = writer.next_row_group(batch.num_rows(), true)?;
+    timed(metrics, Stage::Convert, || {
+        write_row_group(
+            row_group_writer.as_mut(),
+            batch,
+            None,
+            schema,
+            pb,
+            nulls_substituted,
+            num_batch,
+        )
+    })?;
+    timed(metrics, Stage::Write, || {
+        writer.close_row_group(row_group_writer)
+    })
+}
 
-    let mut odbc_buffer_desc = Vec::new();
-    let mut fields = Vec::new();
+/// Replaces NULLs with `sentinel`, if specified. Increments `num_substituted` for each replaced
+/// NULL.
+fn substituted<'a, T>(
+    it: impl Iterator<Item = Option<&'a T>> + 'a,
+    sentinel: Option<&'a Sentinel>,
+    num_substituted: &'a mut u64,
+) -> impl Iterator<Item = Option<&'a T>> + 'a
+where
+    T: FromSentinel + ?Sized + 'a,
+{
+    let sentinel = sentinel
+        .map(|s| T::from_sentinel(s).expect("Sentinel must match the kind of the column buffer."));
+    it.map(move |item| match (item, sentinel) {
+        (None, Some(value)) => {
+            *num_substituted += 1;
+            Some(value)
+        }
+        (item, _) => item,
+    })
+}
 
-    for index in 1..(num_cols + 1) {
-        let mut cd = ColumnDescription::default();
-        // Reserving helps with drivers not reporting column name size correctly.
-        cd.name.reserve(128);
-        cursor.describe_col(index as u16, &mut cd)?;
+/// Writes timestamps either as time of day, or with the precision of the parquet column.
+fn write_timestamps<'a>(
+    pb: &mut ParquetBuffer,
+    cw: &mut ColumnWriterImpl<Int64Type>,
+    it: impl Iterator<Item = Option<&'a Timestamp>>,
+    source: &ColumnSource,
+    field: &Type,
+) -> Result<(), Error> {
+    if source.transform == Transform::TimeOfDay {
+        pb.write_timestamp_time(cw, it)
+    } else {
+        pb.write_timestamp(cw, it, field)
+    }
+}
 
-        debug!("ODBC column description for column {}: {:?}", index, cd);
+/// Only yields the items of rows selected for output. `None` selects every row.
+fn selected<'s, I>(it: I, selection: Option<&'s [bool]>) -> impl Iterator<Item = I::Item> + 's
+where
+    I: Iterator + 's,
+{
+    it.enumerate()
+        .filter(move |(row_index, _)| selection.is_none_or(|s| s[*row_index]))
+        .map(|(_, item)| item)
+}
 
-        let name = cd.name_to_string()?;
-        // Give a generated name, should we fail to retrieve one from the ODBC data source.
-        let name = if name.is_empty() {
-            format!("Column{}", index)
-        } else {
-            name
-        };
+/// Parquet schema and the ODBC buffers to bind in order to fill it.
+struct Schema {
+    parquet: TypePtr,
+    /// One description for each bound column of the result set.
+    buffers: Vec<(u16, BufferDescription)>,
+    /// Name of the result set column bound to each buffer.
+    buffer_names: Vec<String>,
+    /// Value to write instead of NULL for each buffer, if specified with `--null-default`.
+    sentinels: Vec<Option<Sentinel>>,
+    /// One entry for each field of the parquet schema. Describes how its values are obtained from
+    /// the buffers.
+    sources: Vec<ColumnSource>,
+    /// One entry for each column of the result set, including ignored ones. Skipped columns are
+    /// not part of it.
+    explanations: Vec<ColumnExplanation>,
+    /// Name of each column left out due to `--skip-unsupported-columns` and the reason.
+    skipped_columns: Vec<(String, String)>,
+}
 
-        let ptb = |physical_type| Type::primitive_type_builder(&name, physical_type);
+/// Describes how the values of a parquet column are obtained from the ODBC buffers.
+struct ColumnSource {
+    /// Index of the ODBC buffer holding the values of the column.
+    buffer_index: usize,
+    transform: Transform,
+    /// Record minimum and maximum of the values in the file footer.
+    statistics: bool,
+}
 
-        let (field_builder, buffer_kind) = match cd.data_type {
-            DataType::Double => (ptb(PhysicalType::DOUBLE), BufferKind::F64),
-            DataType::Float | DataType::Real => (ptb(PhysicalType::FLOAT), BufferKind::F32),
-            DataType::SmallInt => (
-                ptb(PhysicalType::INT32).with_logical_type(LogicalType::INT_16),
-                BufferKind::I32,
-            ),
-            DataType::Integer => (
-                ptb(PhysicalType::INT32).with_logical_type(LogicalType::INT_32),
-                BufferKind::I32,
-            ),
-            DataType::Date => (
-                ptb(PhysicalType::INT32).with_logical_type(LogicalType::DATE),
-                BufferKind::Date,
-            ),
-            DataType::Decimal {
-                scale: 0,
-                precision: p @ 0..=9,
-            }
-            | DataType::Numeric {
-                scale: 0,
-                precision: p @ 0..=9,
-            } => (
-                ptb(PhysicalType::INT32)
-                    .with_logical_type(LogicalType::DECIMAL)
-                    .with_precision(p as i32)
-                    .with_scale(0),
-                BufferKind::I32,
-            ),
-            DataType::Decimal {
-                scale: 0,
-                precision: p @ 0..=18,
-            }
-            | DataType::Numeric {
-                scale: 0,
-                precision: p @ 0..=18,
-            } => (
-                ptb(PhysicalType::INT64)
-                    .with_logical_type(LogicalType::DECIMAL)
-                    .with_precision(p as i32)
-                    .with_scale(0),
-                BufferKind::I64,
-            ),
-            DataType::Numeric { scale, precision } | DataType::Decimal { scale, precision } => {
-                // Length of the two's complement.
-                let num_binary_digits = precision as f64 * 10f64.log2();
-                // Plus one bit for the sign (+/-)
-                let length_in_bits = num_binary_digits + 1.0;
-                let length_in_bytes = (length_in_bits / 8.0).ceil() as i32;
-                (
-                    ptb(PhysicalType::FIXED_LEN_BYTE_ARRAY)
-                        .with_length(dbg!(length_in_bytes))
-                        .with_logical_type(LogicalType::DECIMAL)
-                        .with_precision(precision.try_into().unwrap())
-                        .with_scale(scale.try_into().unwrap()),
-                    BufferKind::Text {
-                        max_str_len: cd.data_type.column_size(),
-                    },
-                )
+/// Applied to the values of an ODBC buffer before they are written to a parquet column.
+#[derive(PartialEq, Eq)]
+enum Transform {
+    /// Write the values as they are.
+    Identity,
+    /// Write the masked representation of the text in the buffer.
+    Mask(MaskMethod),
+    /// Only write the date part of a timestamp.
+    Date,
+    /// Only write the time of day of a timestamp.
+    TimeOfDay,
+    /// Parse the GUID text in the buffer into 16 bytes.
+    Guid,
+    /// Decode the hexadecimal text in the buffer, which holds binary data.
+    Hex,
+}
+
+fn make_schema(cursor: &impl DescribeColumns, opt: &QueryOpt) -> Result<Schema, Error> {
+    let QueryOpt {
+        masks,
+        split_timestamps,
+        null_defaults,
+        on_conversion_error,
+        force_statistics,
+        field_ids,
+        auto_field_ids,
+        skip_unsupported_columns,
+        strict,
+        time_precisions,
+        nullable_all,
+        guid_as_bytes,
+        time_as_text,
+        timestamp_precision,
+        ..
+    } = opt;
+    let loss_policy = LossPolicy::new(*strict);
+    let num_cols = cursor.num_result_cols()?;
+
+    let mut odbc_buffer_desc = Vec::new();
+    let mut fields = Vec::new();
+    let mut buffer_names = Vec::new();
+    let mut sources = Vec::new();
+    // Remember which masks we applied, so we can tell the user about the ones we did not.
+    let mut mask_applied = vec![false; masks.len()];
+    let mut split_applied = vec![false; split_timestamps.len()];
+    let mut sentinels = Vec::new();
+    let mut null_default_applied = vec![false; null_defaults.len()];
+    let mut explanations = Vec::new();
+    let mut skipped_columns = Vec::new();
+    let mut field_id_applied = vec![false; field_ids.len()];
+    let mut time_precision_applied = vec![false; time_precisions.len()];
+    // Assigns the id specified on the command line to the parquet field with the given name and
+    // position.
+    let mut field_id = |name: &str, position: usize| -> Option<i32> {
+        if *auto_field_ids {
+            return Some(position as i32 + 1);
+        }
+        let i = field_ids.iter().position(|f| f.column == name)?;
+        field_id_applied[i] = true;
+        Some(field_ids[i].id)
+    };
+
+    for index in 1..(num_cols + 1) {
+        let index = index as u16;
+        let mapping = ColumnMapping::new(cursor, index).and_then(|mapping| {
+            // Independent of its original type, a masked column is fetched as text and written as
+            // UTF-8.
+            let mapping = if masks.iter().any(|m| m.column == mapping.name)
+                || (*time_as_text && mapping.time_precision.is_some())
+            {
+                mapping.into_text(cursor)?
+            } else if *guid_as_bytes {
+                mapping.with_guid_as_bytes()
+            } else {
+                mapping
+            };
+            let mapping = match timestamp_precision {
+                Some(precision) => mapping.with_timestamp_precision(*precision),
+                None => mapping,
+            };
+            // Fail early, should the column not be representable in parquet.
+            mapping.field_builder().build()?;
+            Ok(mapping)
+        });
+        let mapping = match mapping {
+            Ok(mapping) => mapping,
+            Err(error) if *skip_unsupported_columns => {
+                let name = column_name(cursor, index);
+                warn!(
+                    "Skipping unsupported column '{}' with index {}: {:#}",
+                    name, index, error
+                );
+                // The column is not written at all, so there is nothing left to mask.
+                if let Some(i) = masks.iter().position(|m| m.column == name) {
+                    mask_applied[i] = true;
+                }
+                skipped_columns.push((name, format!("{:#}", error)));
+                continue;
             }
-            DataType::Timestamp { precision: 0..=3 } => (
-                ptb(PhysicalType::INT64).with_logical_type(LogicalType::TIMESTAMP_MILLIS),
-                BufferKind::Timestamp,
-            ),
-            DataType::Timestamp { .. } => (
-                ptb(PhysicalType::INT64).with_logical_type(LogicalType::TIMESTAMP_MICROS),
-                BufferKind::Timestamp,
-            ),
-            DataType::Bigint => (
-                ptb(PhysicalType::INT64).with_logical_type(LogicalType::INT_64),
-                BufferKind::I64,
-            ),
-            DataType::Bit => (ptb(PhysicalType::BOOLEAN), BufferKind::Bit),
-            DataType::Tinyint => (
-                ptb(PhysicalType::INT32).with_logical_type(LogicalType::INT_8),
-                BufferKind::I32,
-            ),
-            DataType::Char { .. }
-            | DataType::Varchar { .. }
-            | DataType::WVarchar { .. }
-            | DataType::Unknown
-            | DataType::Time { .. }
-            | DataType::Other { .. } => {
-                let max_str_len = if let Some(len) = cd.data_type.utf8_len() {
-                    len
-                } else {
-                    cursor.col_display_size(index.try_into().unwrap())? as usize
-                };
-                (
-                    ptb(PhysicalType::BYTE_ARRAY).with_logical_type(LogicalType::UTF8),
-                    BufferKind::Text { max_str_len },
-                )
+            Err(error) => {
+                return Err(error.context(format!(
+                    "Unsupported column with index {}. Use --skip-unsupported-columns to leave it \
+                    out of the export.",
+                    index
+                )))
+            }
+        };
+        let name = mapping.name.clone();
+        let mask_index = masks.iter().position(|m| m.column == name);
+        let mask = mask_index.map(|i| masks[i].method.clone());
+        let time_precision_index = time_precisions.iter().position(|t| t.column == name);
+        let mapping = match time_precision_index {
+            Some(i) => {
+                time_precision_applied[i] = true;
+                mapping.with_time_precision(time_precisions[i].digits)?
             }
+            None => mapping,
         };
+        debug!("{}", mapping);
+        let location = format!("column '{}'", name);
+        for (rule, message) in column_losses(&mapping) {
+            loss_policy.report(rule, &location, &message)?;
+        }
+        let mut explanation = ColumnExplanation::new(mapping.clone());
+        if let Some(mask) = &mask {
+            explanation.add_override("mask", mask_method_text(mask));
+        }
+        if let Some(i) = time_precision_index {
+            explanation.add_override("time_precision", time_precisions[i].digits);
+        }
+        if let Some(precision) = timestamp_precision {
+            if matches!(mapping.buffer_kind, BufferKind::Timestamp)
+                && !split_timestamps.contains(&name)
+            {
+                explanation.add_override("timestamp_precision", precision.unit());
+            }
+        }
 
+        let data_type = mapping.data_type;
+        let buffer_kind = mapping.buffer_kind;
+        // Bit columns without NULLs are bound without indicators, so they can be written in bulk.
+        // All other buffers are nullable, so a driver reporting the wrong nullability can not
+        // cause NULLs to silently turn into default values.
+        let nullable = *nullable_all
+            || !(matches!(buffer_kind, BufferKind::Bit)
+                && matches!(mapping.nullability, Nullability::NoNulls));
         let buffer_description = BufferDescription {
             kind: buffer_kind,
-            nullable: true,
+            nullable,
         };
 
-        debug!(
-            "ODBC buffer description for column {}: {:?}",
-            index, buffer_description
-        );
+        // Values of these columns are validated during conversion, all others are passed through.
+        let fallible_conversion = mask.is_none()
+            && (mapping.time_precision.is_some()
+                || mapping.is_guid_bytes()
+                || mapping.is_binary()
+                || matches!(
+                    data_type,
+                    DataType::Date
+                        | DataType::Timestamp { .. }
+                        | DataType::Numeric { .. }
+                        | DataType::Decimal { .. }
+                ));
+        let null_on_error =
+            fallible_conversion && *on_conversion_error == ConversionErrorPolicy::Null;
+        if null_on_error {
+            explanation.add_override("on_conversion_error", "null");
+        }
 
-        let repetition = match cd.nullability {
-            Nullability::Nullable | Nullability::Unknown => Repetition::OPTIONAL,
-            Nullability::NoNulls => Repetition::REQUIRED,
+        let null_default_index = null_defaults.iter().position(|d| d.column == name);
+        let sentinel = if let Some(i) = null_default_index {
+            if mask.is_some() || null_on_error {
+                bail!(
+                    "Column '{}' specified in --null-default must neither be masked, nor written \
+                    as NULL due to --on-conversion-error.",
+                    name
+                );
+            }
+            let value = &null_defaults[i].value;
+            explanation.add_override("null_default", value.as_str());
+            let sentinel = match data_type {
+                // Decimals are bound as text, but converted to numbers by us.
+                DataType::Numeric { scale, .. } | DataType::Decimal { scale, .. }
+                    if matches!(buffer_kind, BufferKind::Text { .. }) =>
+                {
+                    normalize_decimal(value, scale as usize)
+                        .and_then(|value| Sentinel::parse(&value, buffer_kind))
+                }
+                _ => Sentinel::parse(value, buffer_kind),
+            }
+            .with_context(|| {
+                format!(
+                    "Invalid value '{}' specified in --null-default for column '{}'.",
+                    value, name
+                )
+            })?;
+            Some(sentinel)
+        } else {
+            None
+        };
+
+        let repetition = match (&mapping.nullability, &mask) {
+            // Masking with NULL requires the column to be nullable, even if the source is not.
+            (_, Some(MaskMethod::Null)) => Repetition::OPTIONAL,
+            // So does replacing invalid values with NULL.
+            _ if null_on_error => Repetition::OPTIONAL,
+            // NULLs are replaced, so there are none left to write.
+            _ if sentinel.is_some() => Repetition::REQUIRED,
+            _ if *nullable_all => Repetition::OPTIONAL,
+            (Nullability::Nullable, _) | (Nullability::Unknown, _) => Repetition::OPTIONAL,
+            (Nullability::NoNulls, _) => Repetition::REQUIRED,
         };
 
         if matches!(buffer_kind, BufferKind::Text { max_str_len: 0 }) {
-            warn!(
-                "Ignoring column '{}' with index {}. Driver reported a display length of 0. \
-              This can happen for types without a fixed size limit. If you feel this should be \
-              supported open an issue (or PR) at \
-              <https://github.com/pacman82/odbc2parquet/issues>.",
-                name, index
-            );
+            loss_policy.report(
+                LossyRule::IgnoredColumn,
+                &format!("column '{}' with index {}", name, index),
+                "Ignoring column, since the driver reported a display length of 0. This can \
+                happen for types without a fixed size limit. If you feel this should be supported \
+                open an issue (or PR) at <https://github.com/pacman82/odbc2parquet/issues>.",
+            )?;
+            explanation
+                .warnings
+                .push("Ignored, since the driver reported a display length of 0.".to_owned());
         } else {
-            let field_builder = field_builder.with_repetition(repetition);
-            fields.push(Arc::new(field_builder.build()?));
-            odbc_buffer_desc.push((index as u16, buffer_description));
+            let buffer_index = odbc_buffer_desc.len();
+            let first_field = fields.len();
+            let split_index = split_timestamps.iter().position(|column| *column == name);
+            if let Some(i) = split_index {
+                if !matches!(buffer_kind, BufferKind::Timestamp) || mask.is_some() {
+                    bail!(
+                        "Column '{}' specified in --split-timestamp must be an unmasked timestamp.",
+                        name
+                    );
+                }
+                split_applied[i] = true;
+                explanation.add_override("split_timestamp", true);
+                let date_name = format!("{}_date", name);
+                let time_name = format!("{}_time", name);
+                debug!(
+                    "Column '{}' is split into '{}' (INT32 DATE) and '{}' (INT64 TIME_MICROS).",
+                    name, date_name, time_name
+                );
+                let date = Type::primitive_type_builder(&date_name, PhysicalType::INT32)
+                    .with_logical_type(LogicalType::DATE)
+                    .with_repetition(repetition);
+                let date = with_field_id(date, field_id(&date_name, fields.len()));
+                fields.push(Arc::new(date.build()?));
+                sources.push(ColumnSource {
+                    buffer_index,
+                    transform: Transform::Date,
+                    statistics: true,
+                });
+                let time = Type::primitive_type_builder(&time_name, PhysicalType::INT64)
+                    .with_logical_type(LogicalType::TIME_MICROS)
+                    .with_repetition(repetition);
+                let time = with_field_id(time, field_id(&time_name, fields.len()));
+                fields.push(Arc::new(time.build()?));
+                sources.push(ColumnSource {
+                    buffer_index,
+                    transform: Transform::TimeOfDay,
+                    statistics: true,
+                });
+            } else {
+                let field_builder = mapping.field_builder().with_repetition(repetition);
+                let field_builder = with_field_id(field_builder, field_id(&name, fields.len()));
+                fields.push(Arc::new(field_builder.build()?));
+                // Minimum and maximum end up in the footer, which is also read by tools never
+                // looking at the values themselves, e.g. catalogs or query planners.
+                let statistics = mask.is_none() || *force_statistics;
+                let transform = match mask {
+                    Some(mask) => Transform::Mask(mask),
+                    None if mapping.is_guid_bytes() => Transform::Guid,
+                    None if mapping.is_binary() => Transform::Hex,
+                    None => Transform::Identity,
+                };
+                if !statistics {
+                    info!(
+                        "Not writing statistics for masked column '{}'. Use --force-statistics to \
+                        write them anyway.",
+                        name
+                    );
+                }
+                sources.push(ColumnSource {
+                    buffer_index,
+                    transform,
+                    statistics,
+                });
+            }
+            explanation.buffer = Some(buffer_description);
+            explanation.fields = fields[first_field..].to_vec();
+            odbc_buffer_desc.push((index, buffer_description));
+            buffer_names.push(name);
+            sentinels.push(sentinel);
+            if let Some(i) = mask_index {
+                mask_applied[i] = true;
+            }
+            if let Some(i) = null_default_index {
+                null_default_applied[i] = true;
+            }
         }
+        explanations.push(explanation);
     }
 
+    // A typo in a mask must not cause sensitive data to be written in plain text.
+    if let Some(i) = mask_applied.iter().position(|&applied| !applied) {
+        bail!(
+            "Column '{}' specified in --mask is not part of the result set.",
+            masks[i].column
+        );
+    }
+    if let Some(i) = split_applied.iter().position(|&applied| !applied) {
+        bail!(
+            "Column '{}' specified in --split-timestamp is not part of the result set.",
+            split_timestamps[i]
+        );
+    }
+    if let Some(i) = null_default_applied.iter().position(|&applied| !applied) {
+        bail!(
+            "Column '{}' specified in --null-default is not part of the result set.",
+            null_defaults[i].column
+        );
+    }
+
+    if let Some(i) = time_precision_applied.iter().position(|&applied| !applied) {
+        bail!(
+            "Column '{}' specified in --time-precision is not part of the result set.",
+            time_precisions[i].column
+        );
+    }
+
+    if let Some(i) = field_id_applied.iter().position(|&applied| !applied) {
+        bail!(
+            "Column '{}' specified in --field-id is not part of the parquet schema.",
+            field_ids[i].column
+        );
+    }
+
+    check_field_names(&fields)?;
     let schema = Type::group_type_builder("schema")
         .with_fields(&mut fields)
         .build()?;
 
-    Ok((Arc::new(schema), odbc_buffer_desc))
+    Ok(Schema {
+        parquet: Arc::new(schema),
+        buffers: odbc_buffer_desc,
+        buffer_names,
+        sentinels,
+        sources,
+        explanations,
+        skipped_columns,
+    })
+}
+
+/// The parquet crate accepts any field name, but control characters break many readers, and the
+/// tools displaying a schema. Reports all offending fields at once, so they can be renamed in one
+/// go.
+fn check_field_names(fields: &[TypePtr]) -> Result<(), Error> {
+    let offenders: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .filter_map(|(position, field)| {
+            let name = field.name();
+            let (offset, character) = name.char_indices().find(|(_, c)| c.is_control())?;
+            Some(format!(
+                "field {} '{}' contains {:?} at byte {}",
+                position + 1,
+                name.escape_debug(),
+                character,
+                offset
+            ))
+        })
+        .collect();
+    if !offenders.is_empty() {
+        bail!(
+            "Column names must not contain control characters: {}. Rename the columns in the \
+            query, e.g. `SELECT a AS b`.",
+            offenders.join(", ")
+        )
+    }
+    Ok(())
+}
+
+fn with_field_id(builder: PrimitiveTypeBuilder<'_>, id: Option<i32>) -> PrimitiveTypeBuilder<'_> {
+    match id {
+        Some(id) => builder.with_id(id),
+        None => builder,
+    }
 }
 
+/// Written into the footer with `--reproducible`, instead of the version of the parquet library.
+const REPRODUCIBLE_CREATED_BY: &str = "odbc2parquet";
+
 /// Wraps parquet SerializedFileWriter. Handles splitting into new files after maximum amount of
 /// batches is reached.
 struct ParquetWriter<'p> {
-    path: &'p Path,
+    path: &'p OutputPath,
     schema: Arc<Type>,
     properties: Arc<WriterProperties>,
-    writer: SerializedFileWriter<File>,
+    writer: SerializedFileWriter<Sink>,
     batches_per_file: u32,
+    /// Start a new file, once the current one has grown to this many bytes.
+    file_size_threshold: Option<u64>,
+    /// Minimum number of digits in the suffix of each file.
+    suffix_length: usize,
+    /// Keep the suffix of the first file, even if only one file has been written.
+    always_suffix: bool,
+    /// Remove the output file again, if it does not contain any rows.
+    no_empty_file: bool,
+    /// Final path of the file currently written to.
+    current_path: PathBuf,
+    /// `true` if each file is written under a temporary name, and only renamed to its final path
+    /// once complete. Always the case if appending to an existing output.
+    temporary: bool,
+    /// Replace files of a split output, which exist already.
+    overwrite: bool,
+    /// Number of files started so far, including the current one.
+    num_files: u32,
+    /// Number of batches written to the current file.
+    num_batches_in_file: u32,
+    /// Number of rows in completed row groups of the current file.
+    num_rows_in_file: u64,
+    /// Number of rows in the row group currently written.
+    num_rows_in_row_group: u64,
+    /// Number of rows and bytes in files which have already been completed.
+    num_rows_completed: u64,
+    num_bytes_completed: u64,
+    /// Set by the sink, if a write failed because the disk is full.
+    out_of_space: Arc<AtomicBool>,
+    /// Executed for each file, once it is completed.
+    hook: Option<&'p FileHook>,
+    /// Hands over each file, once it is completed.
+    upload: Option<&'p Upload>,
+    /// Files handed over to `upload` so far, and whether they have been removed locally.
+    uploaded: Vec<(PathBuf, bool)>,
+    /// Only set if writing to memory, instead of a file.
+    inline: Option<InlineOutput>,
 }
 
 impl<'p> ParquetWriter<'p> {
+    /// # Parameters
+    ///
+    /// * `max_inline_size`: If `Some`, the output is written to memory rather than to `path`. It
+    ///   is printed base64 encoded to standard out once closed, as long as it does not exceed this
+    ///   size in bytes. Incompatible with `batches_per_file` and `file_size_threshold`.
+    /// * `file_size_threshold`: Start a new file with the next batch, once the current one holds
+    ///   at least this many bytes.
+    /// * `suffix_length`: Numbers in the suffixes of split files are zero padded to this many
+    ///   digits.
+    /// * `always_suffix`: Only relevant if splitting into multiple files. If `false` and all rows
+    ///   fit into the first file, it is renamed to `path` at the end.
+    /// * `no_empty_file`: Remove the output file at the end, if no rows have been written to it.
+    /// * `reproducible`: Pin the `created_by` field of the footer, so it does not change with the
+    ///   version of odbc2parquet or the parquet library.
+    /// * `append`: Output of a previous run, whose row groups are copied ahead of the new ones.
+    ///   Incompatible with `batches_per_file`, `file_size_threshold` and `max_inline_size`.
+    /// * `atomic`: Write each file under a temporary name and rename it once it is complete, so
+    ///   no truncated file is left behind at its final path if the export fails.
+    /// * `overwrite`: Replace files which exist already. Otherwise starting a further file of a
+    ///   split output fails, if there is a file with its name. The first one is checked up front.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        path: &'p Path,
+        path: &'p OutputPath,
         batch_size: u32,
         schema: Arc<Type>,
         batches_per_file: u32,
+        file_size_threshold: Option<u64>,
+        suffix_length: usize,
+        always_suffix: bool,
+        no_empty_file: bool,
+        key_value_metadata: Option<Vec<KeyValue>>,
+        compression: Compression,
+        reproducible: bool,
+        append: Option<ExistingFile>,
+        atomic: bool,
+        overwrite: bool,
+        hook: Option<&'p FileHook>,
+        upload: Option<&'p Upload>,
+        max_inline_size: Option<u64>,
     ) -> Result<Self, Error> {
         // Write properties
         // Seems to also work fine without setting the batch size explicitly, but what the heck. Just to
         // be on the safe side.
-        let wpb = WriterProperties::builder().set_write_batch_size(batch_size as usize);
+        let key_value_metadata = match &append {
+            Some(existing) => existing.merge_key_value_metadata(key_value_metadata),
+            None => key_value_metadata,
+        };
+        let mut wpb = WriterProperties::builder()
+            .set_write_batch_size(batch_size as usize)
+            .set_key_value_metadata(key_value_metadata)
+            .set_compression(compression);
+        if reproducible {
+            wpb = wpb.set_created_by(REPRODUCIBLE_CREATED_BY.to_owned());
+        }
         let properties = Arc::new(wpb.build());
-        let file = if batches_per_file == 0 {
-            File::create(path)?
+        // We do not know yet, whether there is going to be a second file, so we start with the
+        // suffix and decide on the final name once we close the writer.
+        let current_path = if batches_per_file == 0 && file_size_threshold.is_none() {
+            path.single(suffix_length)
         } else {
-            File::create(Self::path_with_suffix(path, "_1")?)?
+            path.numbered(1, suffix_length)?
         };
-        let writer = SerializedFileWriter::new(file, schema.clone(), properties.clone())?;
+        let temporary =
+            append.is_some() || (atomic && max_inline_size.is_none() && !path.is_stdout());
+        let writing_path = Self::writing_path(&current_path, temporary);
+        let out_of_space = Arc::new(AtomicBool::new(false));
+        let (sink, inline) = if let Some(max_size) = max_inline_size {
+            let buffer = InMemoryWriteableCursor::default();
+            (
+                Sink::Memory(buffer.clone()),
+                Some(InlineOutput { buffer, max_size }),
+            )
+        } else if path.is_stdout() {
+            (Sink::Stdout(Arc::new(AtomicU64::new(0))), None)
+        } else {
+            (
+                Sink::file(File::create(&writing_path)?, out_of_space.clone()),
+                None,
+            )
+        };
+        let mut writer = SerializedFileWriter::new(sink, schema.clone(), properties.clone())
+            .map_err(|error| {
+                // Even the magic bytes at the start of the file did not fit.
+                if out_of_space.load(Ordering::Relaxed) {
+                    let _ = fs::remove_file(&writing_path);
+                    Error::from(error).context(format!(
+                        "Ran out of disk space writing '{}'. The incomplete file has been removed.",
+                        writing_path.display()
+                    ))
+                } else {
+                    error.into()
+                }
+            })?;
+        let mut num_rows_in_file = 0;
+        if let Some(existing) = &append {
+            if let Err(error) = existing.copy_row_groups(&mut writer) {
+                drop(writer);
+                let _ = fs::remove_file(&writing_path);
+                return Err(error);
+            }
+            num_rows_in_file = existing.num_rows();
+        }
 
         Ok(Self {
             path,
@@ -317,6 +1628,24 @@ impl<'p> ParquetWriter<'p> {
             properties,
             writer,
             batches_per_file,
+            file_size_threshold,
+            suffix_length,
+            always_suffix,
+            no_empty_file,
+            current_path,
+            temporary,
+            overwrite,
+            num_files: 1,
+            num_batches_in_file: 0,
+            num_rows_in_file,
+            num_rows_in_row_group: 0,
+            num_rows_completed: 0,
+            num_bytes_completed: 0,
+            out_of_space,
+            hook,
+            upload,
+            uploaded: Vec::new(),
+            inline,
         })
     }
 
@@ -325,39 +1654,1242 @@ impl<'p> ParquetWriter<'p> {
     ///
     /// # Parameters
     ///
-    /// * `num_batch`: Zero based num batch index
-    pub fn next_row_group(&mut self, num_batch: u32) -> Result<Box<dyn RowGroupWriter>, Error> {
+    /// * `num_rows`: Number of rows which are going to be written into the row group.
+    /// * `starts_batch`: `true` if the row group holds the first rows of a batch. A batch may be
+    ///   split into several row groups, but never across files.
+    pub fn next_row_group(
+        &mut self,
+        num_rows: usize,
+        starts_batch: bool,
+    ) -> Result<Box<dyn RowGroupWriter>, Error> {
         // Check if we need to write the next batch into a new file
-        if num_batch != 0 && self.batches_per_file != 0 && num_batch % self.batches_per_file == 0 {
+        if starts_batch && self.file_full()? {
+            let path = self.path.numbered(self.num_files + 1, self.suffix_length)?;
+            if !self.overwrite {
+                refuse_existing(&path)?;
+            }
             self.writer.close()?;
-            let suffix = format!("_{}", (num_batch / self.batches_per_file) + 1);
-            let path = Self::path_with_suffix(self.path, &suffix)?;
-            let file = File::create(path)?;
+            // From here on errors concern the new file, not the completed one.
+            let completed = std::mem::replace(&mut self.current_path, path);
+            let completed_writing_path = Self::writing_path(&completed, self.temporary);
+            let num_rows_completed = std::mem::replace(&mut self.num_rows_in_file, 0);
+            self.num_rows_completed += num_rows_completed;
+            self.num_bytes_completed += completed_writing_path.metadata()?.len();
+            self.num_files += 1;
+            self.num_batches_in_file = 0;
+            let file = Sink::file(
+                File::create(Self::writing_path(&self.current_path, self.temporary))?,
+                self.out_of_space.clone(),
+            );
+            // Replacing the writer also closes the handle to the previous file, before we rename
+            // it or tell anyone about it.
             self.writer =
                 SerializedFileWriter::new(file, self.schema.clone(), self.properties.clone())?;
+            if self.temporary {
+                fs::rename(&completed_writing_path, &completed)?;
+            }
+            self.file_complete(&completed, num_rows_completed)?;
+        }
+        if starts_batch {
+            self.num_batches_in_file += 1;
         }
+        self.num_rows_in_row_group = num_rows as u64;
         Ok(self.writer.next_row_group()?)
     }
 
-    fn close_row_group(
-        &mut self,
-        row_group_writer: Box<dyn RowGroupWriter>,
-    ) -> Result<(), ParquetError> {
-        self.writer.close_row_group(row_group_writer)
+    /// `true` if the next batch is to be written into a new file. Never for an empty file, so no
+    /// file is started without any rows.
+    fn file_full(&self) -> Result<bool, Error> {
+        if self.num_batches_in_file == 0 {
+            return Ok(false);
+        }
+        if self.batches_per_file != 0 && self.num_batches_in_file == self.batches_per_file {
+            return Ok(true);
+        }
+        // Completed row groups have already been written to the file, only the footer is missing.
+        Ok(match self.file_size_threshold {
+            Some(threshold) => {
+                let path = Self::writing_path(&self.current_path, self.temporary);
+                path.metadata()?.len() >= threshold
+            }
+            None => false,
+        })
+    }
+
+    fn close_row_group(&mut self, row_group_writer: Box<dyn RowGroupWriter>) -> Result<(), Error> {
+        self.writer.close_row_group(row_group_writer)?;
+        self.num_rows_in_file += self.num_rows_in_row_group;
+        if let Some(inline) = &self.inline {
+            inline.check_size()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the paths of all files handed to the upload command, and whether they have been
+    /// removed locally.
+    pub fn close(mut self) -> Result<Vec<(PathBuf, bool)>, Error> {
+        if let Err(error) = self.writer.close() {
+            return Err(self.handle_write_error(error.into()));
+        }
+        let Self {
+            path,
+            writer,
+            batches_per_file,
+            file_size_threshold,
+            suffix_length,
+            always_suffix,
+            no_empty_file,
+            mut current_path,
+            temporary,
+            num_files,
+            num_rows_in_file,
+            num_rows_completed,
+            hook,
+            upload,
+            mut uploaded,
+            inline,
+            ..
+        } = self;
+        // Make sure the file handle is closed, before executing the hook. Also releases the last
+        // reference to the in memory buffer, besides our own.
+        drop(writer);
+        let writing_path = Self::writing_path(&current_path, temporary);
+        if no_empty_file && num_rows_completed + num_rows_in_file == 0 {
+            info!(
+                "Result set is empty. Removing '{}'.",
+                current_path.display()
+            );
+            fs::remove_file(&writing_path)?;
+            return Ok(uploaded);
+        }
+        let splitting = batches_per_file != 0 || file_size_threshold.is_some();
+        if splitting && num_files == 1 && !always_suffix {
+            current_path = path.single(suffix_length);
+        }
+        if writing_path != current_path {
+            fs::rename(&writing_path, &current_path)?;
+        }
+        if let Some(inline) = inline {
+            inline.print_base64()?;
+        }
+        if path.is_stdout() {
+            io::stdout().flush()?;
+            return Ok(uploaded);
+        }
+        complete_file(&current_path, num_rows_in_file, hook, upload, &mut uploaded)?;
+        Ok(uploaded)
+    }
+
+    /// Call this, if writing failed. Cleans up and adds context for errors due to a full disk.
+    /// Other errors are returned unchanged.
+    pub fn handle_write_error(self, error: Error) -> Error {
+        let writing_path = Self::writing_path(&self.current_path, self.temporary);
+        if !self.out_of_space.load(Ordering::Relaxed) {
+            // Nothing is left at the final path of the incomplete file. When appending, the output
+            // is left as it has been before the export.
+            if self.temporary {
+                drop(self.writer);
+                let _ = fs::remove_file(&writing_path);
+            }
+            return error;
+        }
+        let num_bytes_in_file = writing_path
+            .metadata()
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        // We can not finish the file without space for its footer, so we remove the incomplete
+        // file. Completed files stay.
+        drop(self.writer);
+        if let Err(remove_error) = fs::remove_file(&writing_path) {
+            warn!(
+                "Could not remove incomplete file '{}': {}",
+                writing_path.display(),
+                remove_error
+            );
+        }
+        let num_bytes = self.num_bytes_completed + num_bytes_in_file;
+        let num_rows = self.num_rows_completed + self.num_rows_in_file;
+        let estimate = num_bytes
+            .checked_div(num_rows)
+            .map(|bytes_per_row| {
+                format!(
+                    " Data written so far took about {} bytes per row.",
+                    bytes_per_row
+                )
+            })
+            .unwrap_or_default();
+        error.context(format!(
+            "Ran out of disk space writing '{}'. {} bytes have been written, {} rows of which \
+            are in completed files. The incomplete file has been removed.{}",
+            writing_path.display(),
+            num_bytes,
+            self.num_rows_completed,
+            estimate
+        ))
+    }
+
+    fn file_complete(&mut self, path: &Path, num_rows: u64) -> Result<(), Error> {
+        complete_file(path, num_rows, self.hook, self.upload, &mut self.uploaded)
+    }
+
+    /// Path the file with the final path `path` is written to, e.g. `out.par.tmp` if `temporary`.
+    fn writing_path(path: &Path, temporary: bool) -> PathBuf {
+        if temporary {
+            temporary_path(path)
+        } else {
+            path.to_owned()
+        }
+    }
+}
+
+/// Uploads a completed file and runs the hook for it. The hook still sees the local file, even if
+/// it is removed after the upload.
+fn complete_file(
+    path: &Path,
+    num_rows: u64,
+    hook: Option<&FileHook>,
+    upload: Option<&Upload>,
+    uploaded: &mut Vec<(PathBuf, bool)>,
+) -> Result<(), Error> {
+    if let Some(upload) = upload {
+        upload.upload(path)?;
+        uploaded.push((path.to_owned(), upload.remove_after_upload));
+    }
+    if let Some(hook) = hook {
+        hook.on_file_complete(path, num_rows)?;
+    }
+    if let Some(upload) = upload {
+        upload.remove_local(path)?;
+    }
+    Ok(())
+}
+
+/// Parquet output kept in memory, to be printed base64 encoded to standard out.
+struct InlineOutput {
+    buffer: InMemoryWriteableCursor,
+    /// Maximum size of the output in bytes.
+    max_size: u64,
+}
+
+impl InlineOutput {
+    /// Fail early, rather than accumulating more and more memory.
+    fn check_size(&self) -> Result<(), Error> {
+        // All writers share the position of the cursor, which is located at its end.
+        let size = self.buffer.try_clone()?.stream_position()?;
+        if size > self.max_size {
+            bail!(
+                "Output exceeds maximum inline size of {} bytes. Write the result into a file \
+                instead of using `--output-base64`, or increase `--max-inline-size`.",
+                self.max_size
+            );
+        }
+        Ok(())
+    }
+
+    fn print_base64(self) -> Result<(), Error> {
+        // The footer is written last, so we need to check once more.
+        self.check_size()?;
+        let bytes = self
+            .buffer
+            .into_inner()
+            .expect("Buffer must not be referenced by any writer anymore.");
+        println!("{}", base64::encode(bytes));
+        Ok(())
+    }
+}
+
+/// Destination of the parquet output. Either a file, a buffer in memory or standard out.
+enum Sink {
+    File {
+        file: File,
+        /// Shared between all clones of the file. Set if a write failed due to a full disk, since
+        /// the parquet errors do not tell us the kind of IO error.
+        out_of_space: Arc<AtomicBool>,
+    },
+    Memory(InMemoryWriteableCursor),
+    /// Number of bytes written so far, shared between all clones. Standard out can not seek, but
+    /// the parquet writer only asks for the current position.
+    Stdout(Arc<AtomicU64>),
+}
+
+impl Sink {
+    fn file(file: File, out_of_space: Arc<AtomicBool>) -> Self {
+        Sink::File { file, out_of_space }
+    }
+
+    fn check<T>(out_of_space: &AtomicBool, result: io::Result<T>) -> io::Result<T> {
+        if let Err(error) = &result {
+            if matches!(
+                error.kind(),
+                io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+            ) {
+                out_of_space.store(true, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::File { file, out_of_space } => Self::check(out_of_space, file.write(buf)),
+            Sink::Memory(buffer) => buffer.write(buf),
+            Sink::Stdout(position) => {
+                let num_bytes = io::stdout().lock().write(buf)?;
+                position.fetch_add(num_bytes as u64, Ordering::Relaxed);
+                Ok(num_bytes)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File { file, out_of_space } => Self::check(out_of_space, file.flush()),
+            Sink::Memory(buffer) => buffer.flush(),
+            Sink::Stdout(_) => io::stdout().flush(),
+        }
+    }
+}
+
+impl Seek for Sink {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Sink::File { file, .. } => file.seek(pos),
+            Sink::Memory(buffer) => buffer.seek(pos),
+            Sink::Stdout(position) => match pos {
+                SeekFrom::Current(0) => Ok(position.load(Ordering::Relaxed)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Standard out does not support seeking.",
+                )),
+            },
+        }
+    }
+}
+
+impl TryClone for Sink {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Sink::File { file, out_of_space } => {
+                Sink::file(file.try_clone()?, out_of_space.clone())
+            }
+            Sink::Memory(buffer) => Sink::Memory(buffer.try_clone()?),
+            Sink::Stdout(position) => Sink::Stdout(position.clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Error;
+    use num_bigint::BigInt;
+    use odbc_api::sys::SqlDataType;
+    use parquet::{
+        basic::{LogicalType, Type as PhysicalType},
+        column::reader::{ColumnReader, ColumnReaderImpl},
+        data_type::DataType,
+        file::{
+            properties::WriterProperties,
+            reader::{FileReader, RowGroupReader},
+            serialized_reader::{SerializedFileReader, SliceableCursor},
+            statistics::Statistics,
+            writer::{FileWriter, InMemoryWriteableCursor, SerializedFileWriter},
+        },
+    };
+    use structopt::StructOpt;
+
+    use super::{make_schema, split_into_row_groups, write_row_group};
+    use crate::{
+        fake::{FakeColumn, FakeResultSet},
+        fetch::check_num_rows_fetched,
+        parquet_buffer::ParquetBuffer,
+        strict::LossPolicy,
+        QueryOpt,
+    };
+
+    /// Maps and converts the columns like `odbc2parquet query` with `args` would. Returns the
+    /// physical values written for each parquet column. NULLs are returned as `null`, decimals
+    /// stored as `FIXED_LEN_BYTE_ARRAY` as their unscaled value.
+    fn export(columns: Vec<FakeColumn>, args: &[&str]) -> Result<Vec<Vec<String>>, Error> {
+        let reader = write(columns, args)?;
+        let row_group = reader.get_row_group(0)?;
+        (0..row_group.num_columns())
+            .map(|i| read_column(&*row_group, i))
+            .collect()
+    }
+
+    /// Number of NULLs recorded in the statistics of each column chunk written by `export`.
+    /// `None` if the column chunk has no statistics.
+    fn null_counts(columns: Vec<FakeColumn>, args: &[&str]) -> Result<Vec<Option<u64>>, Error> {
+        let reader = write(columns, args)?;
+        let metadata = reader.metadata().row_group(0);
+        Ok(metadata
+            .columns()
+            .iter()
+            .map(|column| column.statistics().map(Statistics::null_count))
+            .collect())
+    }
+
+    /// Writes the result set into a parquet file in memory, consisting of a single row group.
+    fn write(
+        columns: Vec<FakeColumn>,
+        args: &[&str],
+    ) -> Result<SerializedFileReader<SliceableCursor>, Error> {
+        let args = ["query", "--connection-string", "fake"]
+            .iter()
+            .chain(args)
+            .chain(&["out.par", "SELECT"]);
+        let opt = QueryOpt::from_iter_safe(args)?;
+        let result_set = FakeResultSet::new(columns);
+        let schema = make_schema(&result_set, &opt)?;
+        let batch = result_set.batch(&schema.buffers);
+
+        let sink = InMemoryWriteableCursor::default();
+        let properties = Arc::new(WriterProperties::builder().build());
+        let mut writer =
+            SerializedFileWriter::new(sink.clone(), schema.parquet.clone(), properties)?;
+        let mut row_group_writer = writer.next_row_group()?;
+        let mut pb = ParquetBuffer::new(
+            batch.num_rows(),
+            opt.on_conversion_error,
+            LossPolicy::new(opt.strict),
+            opt.timestamp_fraction_unit,
+            opt.round_decimals,
+        );
+        let mut nulls_substituted = vec![0; schema.sources.len()];
+        write_row_group(
+            &mut *row_group_writer,
+            &batch,
+            None,
+            &schema,
+            &mut pb,
+            &mut nulls_substituted,
+            1,
+        )?;
+        writer.close_row_group(row_group_writer)?;
+        writer.close()?;
+
+        Ok(SerializedFileReader::new(SliceableCursor::new(
+            sink.data(),
+        ))?)
+    }
+
+    fn read_column(row_group: &dyn RowGroupReader, index: usize) -> Result<Vec<String>, Error> {
+        let metadata = row_group.metadata();
+        let num_rows = metadata.num_rows() as usize;
+        let nullable = metadata.column(index).column_descr().max_def_level() > 0;
+        let values = match row_group.get_column_reader(index)? {
+            ColumnReader::BoolColumnReader(r) => read(r, num_rows, nullable, |v| v.to_string()),
+            ColumnReader::Int32ColumnReader(r) => read(r, num_rows, nullable, |v| v.to_string()),
+            ColumnReader::Int64ColumnReader(r) => read(r, num_rows, nullable, |v| v.to_string()),
+            ColumnReader::FloatColumnReader(r) => read(r, num_rows, nullable, |v| v.to_string()),
+            ColumnReader::DoubleColumnReader(r) => read(r, num_rows, nullable, |v| v.to_string()),
+            ColumnReader::ByteArrayColumnReader(r) => read(r, num_rows, nullable, |v| {
+                String::from_utf8(v.data().to_vec()).unwrap()
+            }),
+            ColumnReader::FixedLenByteArrayColumnReader(r) => read(r, num_rows, nullable, |v| {
+                BigInt::from_signed_bytes_be(v.data()).to_string()
+            }),
+            ColumnReader::Int96ColumnReader(_) => unreachable!("INT96 is never written."),
+        };
+        values
     }
 
-    pub fn close(&mut self) -> Result<(), ParquetError> {
-        self.writer.close()
+    fn read<T: DataType>(
+        mut reader: ColumnReaderImpl<T>,
+        num_rows: usize,
+        nullable: bool,
+        format: impl Fn(&T::T) -> String,
+    ) -> Result<Vec<String>, Error> {
+        let mut values = vec![T::T::default(); num_rows];
+        let mut def_levels = vec![1; num_rows];
+        let def_levels_out = if nullable {
+            Some(def_levels.as_mut_slice())
+        } else {
+            None
+        };
+        reader.read_batch(num_rows, def_levels_out, None, &mut values)?;
+        let mut values = values.iter();
+        let column = def_levels
+            .iter()
+            .map(|&level| {
+                if level == 0 {
+                    "null".to_owned()
+                } else {
+                    format(values.next().unwrap())
+                }
+            })
+            .collect();
+        Ok(column)
     }
 
-    fn path_with_suffix(path: &Path, suffix: &str) -> Result<PathBuf, Error> {
-        let mut stem = path
-            .file_stem()
-            .ok_or_else(|| format_err!("Output needs To have a file stem."))?
-            .to_owned();
-        stem.push(suffix);
-        let mut path_with_suffix = path.with_file_name(stem);
-        path_with_suffix = path_with_suffix.with_extension("par");
-        Ok(path_with_suffix)
+    #[test]
+    fn nulls_in_every_type() {
+        let columns = export(
+            vec![
+                FakeColumn::text("a", &[Some("Hello"), None]),
+                FakeColumn::i32("b", &[None, Some(42)]),
+                FakeColumn::i64("c", &[Some(-1), None]),
+                FakeColumn::f32("d", &[None, Some(1.5)]),
+                FakeColumn::f64("e", &[Some(2.5), None]),
+                FakeColumn::bit("f", &[None, Some(true)]),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            columns,
+            vec![
+                ["Hello", "null"],
+                ["null", "42"],
+                ["-1", "null"],
+                ["null", "1.5"],
+                ["2.5", "null"],
+                ["null", "true"],
+            ]
+        );
+    }
+
+    #[test]
+    fn bit_without_nulls() {
+        let columns = export(
+            vec![FakeColumn::bit("a", &[Some(true), Some(false)]).not_null()],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(columns, vec![["true", "false"]]);
+    }
+
+    #[test]
+    fn null_in_required_column() {
+        let columns = || vec![FakeColumn::i32("a", &[Some(1), None, Some(3)]).not_null()];
+
+        let error = export(columns(), &[]).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to convert column 'a' of batch 1.: Row 1 is NULL, but the column is REQUIRED, \
+            since the ODBC driver reported it as not nullable. Use --nullable-all, if the driver \
+            does not report nullability reliably."
+        );
+        let columns = export(columns(), &["--nullable-all"]).unwrap();
+        assert_eq!(columns, vec![["1", "null", "3"]]);
+    }
+
+    #[test]
+    fn nullable_all() {
+        let columns = vec![
+            FakeColumn::i32("a", &[Some(1)]).not_null(),
+            FakeColumn::bit("b", &[Some(true)]).not_null(),
+        ];
+        let reader = write(columns, &["--nullable-all"]).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        assert!((0..2).all(|i| schema.column(i).max_def_level() == 1));
+    }
+
+    #[test]
+    fn dates() {
+        let columns = export(
+            vec![FakeColumn::date(
+                "a",
+                &[Some("2020-09-16"), Some("1969-12-31"), None],
+            )],
+            &[],
+        )
+        .unwrap();
+        // Days since epoch
+        assert_eq!(columns, vec![["18521", "-1", "null"]]);
+    }
+
+    #[test]
+    fn invalid_date() {
+        let columns = || vec![FakeColumn::date("a", &[Some("2021-02-30")])];
+
+        let error = export(columns(), &[]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 0.: Invalid date \
+            2021-02-30.",
+            format!("{:#}", error)
+        );
+
+        let columns = export(columns(), &["--on-conversion-error", "null"]).unwrap();
+        assert_eq!(columns, vec![["null"]]);
+    }
+
+    #[test]
+    fn zero_dates() {
+        // Some drivers, e.g. for MySQL, return `0000-00-00` instead of failing.
+        let dates = || {
+            vec![FakeColumn::date(
+                "a",
+                &[Some("2021-02-28"), Some("0000-00-00")],
+            )]
+        };
+        let timestamps = || {
+            vec![FakeColumn::timestamp(
+                "a",
+                0,
+                &[Some("2021-02-28 00:00:00"), Some("0000-00-00 00:00:00")],
+            )]
+        };
+
+        let error = export(dates(), &[]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 1.: Invalid date \
+            0000-00-00.",
+            format!("{:#}", error)
+        );
+        let error = export(timestamps(), &[]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 1.: Invalid \
+            timestamp 0000-00-00 00:00:00.000000000.",
+            format!("{:#}", error)
+        );
+        assert!(export(timestamps(), &["--split-timestamp", "a"]).is_err());
+
+        let args = ["--on-conversion-error", "null"];
+        assert_eq!(export(dates(), &args).unwrap(), vec![["18686", "null"]]);
+        assert_eq!(
+            export(timestamps(), &args).unwrap(),
+            vec![["1614470400000", "null"]]
+        );
+    }
+
+    #[test]
+    fn decimals() {
+        let columns = export(
+            vec![
+                FakeColumn::decimal("a", 10, 2, &[Some("0.12"), Some("-123.45"), None]),
+                FakeColumn::decimal("b", 5, 0, &[Some("42"), Some("-42"), None]),
+                FakeColumn::decimal("c", 15, 0, &[Some("123456789012345"), None, Some("0")]),
+            ],
+            &[],
+        )
+        .unwrap();
+        // Unscaled values
+        assert_eq!(
+            columns,
+            vec![
+                ["12", "-12345", "null"],
+                ["42", "-42", "null"],
+                ["123456789012345", "null", "0"],
+            ]
+        );
+    }
+
+    #[test]
+    fn decimals_with_fewer_fractional_digits() {
+        let columns = export(
+            vec![FakeColumn::decimal(
+                "a",
+                10,
+                2,
+                &[Some("1.5"), Some("-3"), Some(".25"), None],
+            )],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(columns, vec![["150", "-300", "25", "null"]]);
+    }
+
+    #[test]
+    fn decimals_with_more_fractional_digits() {
+        let columns = || {
+            vec![FakeColumn::decimal(
+                "a",
+                10,
+                2,
+                &[Some("1.2345"), Some("-0.125"), Some("9.996"), Some("1.23")],
+            )]
+        };
+
+        let error = export(columns(), &[]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 0.: Decimal '1.2345' \
+            has 4 digits after the decimal point, but the scale of the column is 2. The ODBC \
+            driver returned more digits than it declared. Use --round-decimals to round them.",
+            format!("{:#}", error)
+        );
+
+        let columns = export(columns(), &["--round-decimals"]).unwrap();
+        assert_eq!(columns, vec![["123", "-13", "1000", "123"]]);
+    }
+
+    #[test]
+    fn decimals_with_too_many_integer_digits() {
+        // The text buffer is as long as the precision, yet a value without decimal point can
+        // still exceed it. Values filling the buffer completely are not placed in the last row,
+        // since odbc-api writes a terminating zero one byte past them.
+        let export_err = |precision, value: &str| {
+            let column = FakeColumn::decimal("a", precision, 2, &[Some(value), Some("1.00")]);
+            format!("{:#}", export(vec![column], &[]).unwrap_err())
+        };
+
+        // Fits into an i128, but not into the five bytes of a DECIMAL(10,2).
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 0.: Decimal \
+            '9999999999' does not fit into 5 bytes. The ODBC driver returned more digits than the \
+            precision of the column allows.",
+            export_err(10, "9999999999")
+        );
+        // Overflows an i128.
+        assert!(export_err(38, &"9".repeat(38)).contains("does not fit into 16 bytes"));
+        // Big integer path for precisions beyond 38 digits.
+        assert!(export_err(40, &format!("-{}", "9".repeat(39))).contains("does not fit into 17"));
+    }
+
+    #[test]
+    fn more_rows_fetched_than_bound() {
+        // The row set of a fake result set can not hold more rows than it has been allocated
+        // with, so the numbers a misbehaving driver would report are checked directly.
+        let result_set = FakeResultSet::new(vec![FakeColumn::i32("a", &[Some(1), Some(2)])]);
+        let opt = QueryOpt::from_iter_safe(&["query", "--dsn", "fake", "out.par", "SELECT"]);
+        let schema = make_schema(&result_set, &opt.unwrap()).unwrap();
+        let batch = result_set.batch(&schema.buffers);
+        check_num_rows_fetched(batch.num_rows(), 2).unwrap();
+        assert_eq!(
+            "The ODBC driver reported 3 rows fetched, but the row set has only room for 2. This is \
+            a bug in the ODBC driver.",
+            check_num_rows_fetched(3, 2).unwrap_err().to_string()
+        );
+        // Fetching one row at a time binds a row set of a single row.
+        assert!(check_num_rows_fetched(2, 1).is_err());
+    }
+
+    #[test]
+    fn time_is_written_with_its_precision() {
+        let columns = vec![
+            FakeColumn::time("t0", 0, &[Some("00:05:34"), None]),
+            FakeColumn::time("t3", 3, &[Some("14:05:32.123"), None]),
+            FakeColumn::time("t6", 6, &[Some("14:05:32.123456"), None]),
+            // Truncated to microseconds
+            FakeColumn::time("t7", 7, &[Some("03:54:12.1234567"), None]),
+        ];
+        let reader = write(columns, &[]).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        let types: Vec<_> = (0..4)
+            .map(|i| {
+                (
+                    schema.column(i).physical_type(),
+                    schema.column(i).logical_type(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            types,
+            [
+                (PhysicalType::INT32, LogicalType::TIME_MILLIS),
+                (PhysicalType::INT32, LogicalType::TIME_MILLIS),
+                (PhysicalType::INT64, LogicalType::TIME_MICROS),
+                (PhysicalType::INT64, LogicalType::TIME_MICROS),
+            ]
+        );
+        let row_group = reader.get_row_group(0).unwrap();
+        let columns: Vec<_> = (0..4)
+            .map(|i| read_column(&*row_group, i).unwrap())
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                ["334000", "null"],
+                ["50732123", "null"],
+                ["50732123456", "null"],
+                ["14052123456", "null"],
+            ]
+        );
+    }
+
+    #[test]
+    fn sql_server_time_type() {
+        // SQL Server reports `TIME(3)` as `SQL_SS_TIME2`, rather than the standard type.
+        let column = FakeColumn::text("a", &[Some("14:05:32.123")])
+            .with_data_type(odbc_api::DataType::Other {
+                data_type: SqlDataType(-154),
+                column_size: 12,
+                decimal_digits: 3,
+            })
+            .with_display_size(16);
+        assert_eq!(export(vec![column], &[]).unwrap(), vec![["50732123"]]);
+    }
+
+    #[test]
+    fn guid_as_bytes() {
+        let columns = || {
+            vec![FakeColumn::text(
+                "a",
+                &[
+                    Some("6F9619FF-8B86-D011-B42D-00C04FC964FF"),
+                    Some("{00000000-0000-0000-0000-000000000001}"),
+                    Some("6F9619FF-8B86-D011-B42D"),
+                ],
+            )
+            .with_data_type(odbc_api::DataType::Other {
+                data_type: SqlDataType::EXT_GUID,
+                column_size: 36,
+                decimal_digits: 0,
+            })
+            // Enclosed in braces by some drivers.
+            .with_display_size(38)]
+        };
+
+        let error = export(columns(), &["--guid-as-bytes"]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 2.: \
+            '6F9619FF-8B86-D011-B42D' is not a valid GUID.",
+            format!("{:#}", error)
+        );
+
+        let reader = write(
+            columns(),
+            &["--guid-as-bytes", "--on-conversion-error", "null"],
+        )
+        .unwrap();
+        let column = reader.metadata().file_metadata().schema_descr().column(0);
+        assert_eq!(PhysicalType::FIXED_LEN_BYTE_ARRAY, column.physical_type());
+        assert_eq!(16, column.type_length());
+        // Bytes in the order of the text, read as a big endian number.
+        let first = BigInt::from_signed_bytes_be(&[
+            0x6F, 0x96, 0x19, 0xFF, 0x8B, 0x86, 0xD0, 0x11, 0xB4, 0x2D, 0x00, 0xC0, 0x4F, 0xC9,
+            0x64, 0xFF,
+        ]);
+        assert_eq!(
+            export(
+                columns(),
+                &["--guid-as-bytes", "--on-conversion-error", "null"]
+            )
+            .unwrap(),
+            vec![[first.to_string().as_str(), "1", "null"]]
+        );
+    }
+
+    #[test]
+    fn binary() {
+        // Drivers return binary data as hexadecimal digits, if fetched as text.
+        let binary = |name, data_type, column_size, values: &[Option<&str>]| {
+            FakeColumn::text(name, values)
+                .with_data_type(odbc_api::DataType::Other {
+                    data_type,
+                    column_size,
+                    decimal_digits: 0,
+                })
+                .with_display_size(2 * column_size as isize)
+        };
+        // MD5 hash of `odbc2parquet`.
+        let md5 = "79CEB926AEC1D4116837647D77A70BFF";
+        let columns = vec![
+            binary("md5", SqlDataType::EXT_BINARY, 16, &[Some(md5), None]),
+            binary(
+                "text",
+                SqlDataType::EXT_VAR_BINARY,
+                256,
+                &[Some("6F6462633270"), Some("00")],
+            ),
+        ];
+
+        let reader = write(columns, &[]).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        assert_eq!(
+            PhysicalType::FIXED_LEN_BYTE_ARRAY,
+            schema.column(0).physical_type()
+        );
+        assert_eq!(16, schema.column(0).type_length());
+        assert_eq!(LogicalType::NONE, schema.column(0).logical_type());
+        assert_eq!(PhysicalType::BYTE_ARRAY, schema.column(1).physical_type());
+        assert_eq!(LogicalType::NONE, schema.column(1).logical_type());
+
+        let row_group = reader.get_row_group(0).unwrap();
+        let md5: Vec<u8> = (0..md5.len())
+            .step_by(2)
+            .map(|pos| u8::from_str_radix(&md5[pos..(pos + 2)], 16).unwrap())
+            .collect();
+        let md5 = BigInt::from_signed_bytes_be(&md5);
+        assert_eq!(
+            vec![md5.to_string(), "null".to_owned()],
+            read_column(&*row_group, 0).unwrap()
+        );
+        assert_eq!(vec!["odbc2p", "\0"], read_column(&*row_group, 1).unwrap());
+    }
+
+    #[test]
+    fn binary_with_wrong_length() {
+        let column = FakeColumn::text("a", &[Some("0102")])
+            .with_data_type(odbc_api::DataType::Other {
+                data_type: SqlDataType::EXT_BINARY,
+                column_size: 4,
+                decimal_digits: 0,
+            })
+            .with_display_size(8);
+        let error = export(vec![column], &[]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 0.: Binary value of 2 \
+            bytes does not match the fixed length 4 of the column.",
+            format!("{:#}", error)
+        );
+    }
+
+    #[test]
+    fn time_as_text() {
+        let columns = || vec![FakeColumn::time("a", 7, &[Some("14:05:32.1234567"), None])];
+
+        assert_eq!(
+            export(columns(), &["--time-as-text"]).unwrap(),
+            vec![["14:05:32.1234567", "null"]]
+        );
+        let reader = write(columns(), &["--time-as-text"]).unwrap();
+        let column = reader.metadata().file_metadata().schema_descr().column(0);
+        assert_eq!(LogicalType::UTF8, column.logical_type());
+    }
+
+    #[test]
+    fn override_time_precision() {
+        let columns = || {
+            vec![
+                // Driver claims three digits, but the values have six.
+                FakeColumn::time("a", 3, &[Some("14:05:32.123456"), None]),
+                FakeColumn::time("b", 6, &[Some("14:05:32.123456"), None]),
+            ]
+        };
+        assert_eq!(
+            export(
+                columns(),
+                &["--time-precision", "a=6", "--time-precision", "b=3"]
+            )
+            .unwrap(),
+            vec![["50732123456", "null"], ["50732123", "null"]]
+        );
+        // Cutting off the microseconds violates `--strict`.
+        let time = FakeColumn::time("b", 6, &[Some("14:05:32.123456"), None]);
+        assert!(export(vec![time], &["--strict", "--time-precision", "b=3"]).is_err());
+
+        let not_a_time = FakeColumn::i32("c", &[Some(1)]);
+        assert!(export(vec![not_a_time], &["--time-precision", "c=6"]).is_err());
+        let time = FakeColumn::time("b", 6, &[None]);
+        assert!(export(vec![time], &["--time-precision", "c=6"]).is_err());
+    }
+
+    #[test]
+    fn invalid_time() {
+        let columns = || {
+            vec![FakeColumn::time(
+                "a",
+                3,
+                &[Some("25:00:00"), Some("01:00:00")],
+            )]
+        };
+        let error = export(columns(), &[]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 0.: Invalid time of \
+            day '25:00:00'.",
+            format!("{:#}", error)
+        );
+        assert_eq!(
+            export(columns(), &["--on-conversion-error", "null"]).unwrap(),
+            vec![["null", "3600000"]]
+        );
+    }
+
+    #[test]
+    fn empty_column_name() {
+        let reader = write(vec![FakeColumn::i32("", &[Some(1)])], &[]).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        assert_eq!("Column1", schema.column(0).name());
+    }
+
+    #[test]
+    fn column_names_with_control_characters() {
+        let columns = vec![
+            FakeColumn::i32("a\0b", &[Some(1)]),
+            FakeColumn::i32("c", &[Some(1)]),
+            FakeColumn::i32("d\n", &[Some(1)]),
+        ];
+        let message = export(columns, &[]).unwrap_err().to_string();
+        assert!(
+            message.contains("field 1 'a\\0b' contains '\\0' at byte 1, field 3 'd\\n'"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn unknown_type_falls_back_to_text() {
+        let columns = export(
+            vec![
+                FakeColumn::text("a", &[Some("POINT (1 2)")])
+                    .with_data_type(odbc_api::DataType::Unknown)
+                    .with_display_size(20),
+                // Driver reports no upper bound for the length, so the column is ignored.
+                FakeColumn::text("b", &[Some("Hello")])
+                    .with_data_type(odbc_api::DataType::Unknown)
+                    .with_display_size(0),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(columns, vec![["POINT (1 2)"]]);
+    }
+
+    #[test]
+    fn timestamps() {
+        let columns = export(
+            vec![
+                FakeColumn::timestamp("a", 3, &[Some("2020-09-16 03:54:12.123"), None]),
+                FakeColumn::timestamp("b", 6, &[None, Some("1969-12-31 23:59:59.999999")]),
+            ],
+            &[],
+        )
+        .unwrap();
+        // Milliseconds and microseconds since epoch
+        assert_eq!(columns, vec![["1600228452123", "null"], ["null", "-1"]]);
+    }
+
+    #[test]
+    fn timestamps_far_from_epoch() {
+        // Beyond the range of nanoseconds in 64 Bit, yet valid `DATETIME2` values, e.g. sentinel
+        // dates.
+        let values = [
+            Some("0001-01-01 00:00:00"),
+            Some("1800-06-15 00:00:00"),
+            Some("9999-12-31 23:59:59.999999"),
+        ];
+        let columns = export(
+            vec![
+                FakeColumn::timestamp("a", 3, &values),
+                FakeColumn::timestamp("b", 6, &values),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            columns,
+            vec![
+                ["-62135596800000", "-5350406400000", "253402300799999"],
+                [
+                    "-62135596800000000",
+                    "-5350406400000000",
+                    "253402300799999999"
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn timestamp_fraction_digits() {
+        // A driver reporting `.123` seconds as 123, rather than 123 million nanoseconds.
+        let column = || FakeColumn::timestamp("a", 3, &[Some("2020-09-16 03:54:12.000000123")]);
+        let columns = export(vec![column()], &["--timestamp-fraction-unit", "digits:3"]).unwrap();
+        assert_eq!(columns, vec![["1600228452123"]]);
+
+        let columns = export(vec![column()], &["--timestamp-fraction-unit", "digits:2"]);
+        assert!(columns.is_err());
+    }
+
+    #[test]
+    fn timestamp_precision() {
+        let columns = || {
+            vec![FakeColumn::timestamp(
+                "a",
+                7,
+                &[Some("2020-09-16 03:54:12.1234567"), None],
+            )]
+        };
+        let logical_type = |args: &[&str]| {
+            let reader = write(columns(), args).unwrap();
+            let schema = reader.metadata().file_metadata().schema_descr();
+            schema.column(0).logical_type()
+        };
+
+        assert_eq!(
+            export(columns(), &["--timestamp-precision", "nanoseconds"]).unwrap(),
+            vec![["1600228452123456700", "null"]]
+        );
+        assert_eq!(
+            LogicalType::NONE,
+            logical_type(&["--timestamp-precision", "nanoseconds"])
+        );
+        assert_eq!(
+            export(columns(), &["--timestamp-precision", "milliseconds"]).unwrap(),
+            vec![["1600228452123", "null"]]
+        );
+        assert_eq!(
+            LogicalType::TIMESTAMP_MILLIS,
+            logical_type(&["--timestamp-precision", "milliseconds"])
+        );
+        assert_eq!(LogicalType::TIMESTAMP_MICROS, logical_type(&[]));
+    }
+
+    #[test]
+    fn timestamp_beyond_nanosecond_range() {
+        let columns = vec![FakeColumn::timestamp(
+            "a",
+            7,
+            &[Some("2262-04-12 00:00:00")],
+        )];
+        let error = export(columns, &["--timestamp-precision", "nanoseconds"]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 0.: Timestamp \
+            2262-04-12 00:00:00 is out of the range representable in nanoseconds, which is \
+            1677-09-21 to 2262-04-11.",
+            format!("{:#}", error)
+        );
+    }
+
+    #[test]
+    fn split_timestamp() {
+        let columns = export(
+            vec![FakeColumn::timestamp(
+                "a",
+                6,
+                &[Some("2020-09-16 03:54:12.5")],
+            )],
+            &["--split-timestamp", "a"],
+        )
+        .unwrap();
+        // Days since epoch and microseconds since midnight
+        assert_eq!(columns, vec![["18521"], ["14052500000"]]);
+    }
+
+    #[test]
+    fn strict_timestamp_precision() {
+        let columns = || {
+            vec![FakeColumn::timestamp(
+                "a",
+                6,
+                &[Some("2020-09-16 03:54:12.1234567")],
+            )]
+        };
+
+        assert!(export(columns(), &[]).is_ok());
+
+        let error = export(columns(), &["--strict"]).unwrap_err();
+        assert_eq!(
+            "Failed to convert column 'a' of batch 1.: Invalid value in row 0.: Fraction \
+            123456700 violates rule 'timestamp-precision': Fractional seconds are truncated, if \
+            written with a precision of microseconds. Aborting due to --strict.",
+            format!("{:#}", error)
+        );
+    }
+
+    #[test]
+    fn null_default() {
+        let columns = export(
+            vec![FakeColumn::i32("a", &[Some(1), None])],
+            &["--null-default", "a=-1"],
+        )
+        .unwrap();
+        assert_eq!(columns, vec![["1", "-1"]]);
+    }
+
+    #[test]
+    fn masked_column() {
+        let columns = export(
+            vec![FakeColumn::i32("a", &[Some(42), None])],
+            &["--mask", "a=fixed:x"],
+        )
+        .unwrap();
+        assert_eq!(columns, vec![["x", "null"]]);
+    }
+
+    #[test]
+    fn null_counts_of_every_type() {
+        let columns = vec![
+            FakeColumn::text("text", &[Some("a"), None, None]),
+            FakeColumn::i32("i32", &[None, Some(1), Some(2)]),
+            FakeColumn::i64("i64", &[None, None, None]),
+            FakeColumn::f32("f32", &[Some(1.0), None, Some(2.0)]),
+            FakeColumn::f64("f64", &[None, Some(1.0), None]),
+            FakeColumn::decimal("decimal", 10, 2, &[Some("1.00"), None, None]),
+            FakeColumn::decimal("decimal_int", 9, 0, &[None, Some("1"), None]),
+            FakeColumn::decimal("decimal_big", 40, 2, &[None, None, Some("1.00")]),
+            FakeColumn::date("date", &[None, Some("2021-01-01"), None]),
+            FakeColumn::time("time", 7, &[Some("12:00:00.0000000"), None, None]),
+            FakeColumn::timestamp(
+                "timestamp_millis",
+                3,
+                &[None, None, Some("2021-01-01 00:00:00")],
+            ),
+            FakeColumn::timestamp(
+                "timestamp_micros",
+                6,
+                &[Some("2021-01-01 00:00:00"), None, None],
+            ),
+            FakeColumn::bit("bit", &[None, Some(true), Some(false)]),
+            FakeColumn::bit("required_bit", &[Some(true), Some(true), Some(false)]).not_null(),
+            FakeColumn::i32("required_i32", &[Some(1), Some(2), Some(3)]).not_null(),
+        ];
+        assert_eq!(
+            null_counts(columns, &[]).unwrap(),
+            [2, 1, 3, 1, 2, 2, 2, 2, 2, 2, 2, 2, 1, 0, 0]
+                .iter()
+                .map(|&n| Some(n))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn null_counts_of_derived_columns() {
+        let columns = vec![
+            FakeColumn::text("masked", &[Some("a"), None, None]),
+            FakeColumn::text("masked_to_null", &[Some("a"), Some("b"), None]),
+            FakeColumn::timestamp("split", 6, &[None, Some("2021-01-01 00:00:00"), None]),
+            FakeColumn::i32("defaulted", &[None, Some(1), None]),
+        ];
+        let args = [
+            "--mask",
+            "masked=sha256",
+            "--mask",
+            "masked_to_null=null",
+            "--split-timestamp",
+            "split",
+            "--null-default",
+            "defaulted=0",
+        ];
+        assert_eq!(
+            null_counts(columns, &args).unwrap(),
+            [2, 3, 2, 2, 0].iter().map(|&n| Some(n)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn null_counts_with_null_on_conversion_error() {
+        let columns = vec![FakeColumn::date(
+            "date",
+            &[Some("2021-02-30"), None, Some("2021-01-01")],
+        )];
+        let args = ["--on-conversion-error", "null"];
+        assert_eq!(null_counts(columns, &args).unwrap(), [Some(2)]);
+    }
+
+    #[test]
+    fn split_rows_of_batch_into_row_groups() {
+        // Number of rows in each row group and the range of the batch it covers.
+        let split = |selection: Option<&[bool]>, limit, max_rows, accumulated| {
+            split_into_row_groups(&[10; 5], selection, limit, max_rows, accumulated)
+                .into_iter()
+                .map(|row_group| (row_group.num_rows, row_group.rows))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(split(None, None, usize::MAX, (0, 0)), [(5, 0..5)]);
+        assert_eq!(
+            split(None, Some(25), usize::MAX, (0, 0)),
+            [(2, 0..2), (2, 2..4), (1, 4..5)]
+        );
+        assert_eq!(split(None, None, 3, (0, 0)), [(3, 0..3), (2, 3..5)]);
+        // Accumulated rows count towards the first row group, which may hold no rows of this batch.
+        assert_eq!(
+            split(None, None, 3, (2, 20)),
+            [(3, 0..1), (3, 1..4), (1, 4..5)]
+        );
+        assert_eq!(
+            split(None, None, 3, (3, 30)),
+            [(3, 0..0), (3, 0..3), (2, 3..5)]
+        );
+        // Rows not selected are neither counted nor do they start a row group.
+        let selection = [false, true, false, true, true];
+        assert_eq!(
+            split(Some(&selection), None, 2, (0, 0)),
+            [(2, 0..4), (1, 4..5)]
+        );
     }
 }
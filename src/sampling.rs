@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+/// Fraction of rows to keep in the output. Guaranteed to be in `(0, 1]`.
+#[derive(Debug, Clone, Copy)]
+pub struct SampleRate(f64);
+
+impl FromStr for SampleRate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rate: f64 = s.parse()?;
+        // Also rejects NaN
+        if !(rate > 0. && rate <= 1.) {
+            bail!(
+                "Sample rate must be larger than 0 and at most 1. E.g. `0.01` for one percent. \
+                Found: {}",
+                s
+            )
+        }
+        Ok(SampleRate(rate))
+    }
+}
+
+/// Decides independently for each row, whether it is part of the sample or not (Bernoulli
+/// sampling). The same seed always yields the same decisions, so a sample can be reproduced as long
+/// as the data source returns the rows in the same order.
+pub struct Sampler {
+    rate: f64,
+    /// State of the SplitMix64 pseudo random number generator. We do not need anything
+    /// cryptographically secure here, just something fast with a reasonable distribution.
+    state: u64,
+    num_rows_seen: u64,
+    num_rows_selected: u64,
+}
+
+impl Sampler {
+    pub fn new(rate: SampleRate, seed: u64) -> Self {
+        Self {
+            rate: rate.0,
+            state: seed,
+            num_rows_seen: 0,
+            num_rows_selected: 0,
+        }
+    }
+
+    /// Decide for the next `num_rows` rows, whether they are part of the sample. `selection` is
+    /// overwritten with one entry per row, `true` for each row which should be written. Returns
+    /// the number of selected rows.
+    pub fn select(&mut self, num_rows: usize, selection: &mut Vec<bool>) -> usize {
+        selection.clear();
+        let mut num_selected = 0;
+        for _ in 0..num_rows {
+            let keep = self.next_f64() < self.rate;
+            if keep {
+                num_selected += 1;
+            }
+            selection.push(keep);
+        }
+        self.num_rows_seen += num_rows as u64;
+        self.num_rows_selected += num_selected as u64;
+        num_selected
+    }
+
+    /// Total number of rows the sampler decided upon so far.
+    pub fn num_rows_seen(&self) -> u64 {
+        self.num_rows_seen
+    }
+
+    /// Total number of rows which are part of the sample so far.
+    pub fn num_rows_selected(&self) -> u64 {
+        self.num_rows_selected
+    }
+
+    /// Uniformly distributed in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        // Use the upper 53 Bits, which is the precision of the mantissa of an `f64`.
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,136 @@
+use std::convert::TryFrom;
+
+use anyhow::{format_err, Error};
+
+/// Parses counts, like a number of rows or batches, given on the command line. Accepts plain
+/// numbers with optional underscores as separators (`1_000_000`), as well as the suffixes `k`,
+/// `M`, `G` (powers of 1000) and `Ki`, `Mi`, `Gi` (powers of 1024). E.g. `100k`.
+pub fn parse_count<T: TryFrom<u64>>(text: &str) -> Result<T, Error> {
+    parse(text, "")
+}
+
+/// Parses sizes in bytes given on the command line. Same as [`parse_count`], but the suffix may
+/// be followed by a `B`. E.g. `64MiB`.
+pub fn parse_byte_size<T: TryFrom<u64>>(text: &str) -> Result<T, Error> {
+    parse(text, "B")
+}
+
+fn parse<T: TryFrom<u64>>(text: &str, unit: &str) -> Result<T, Error> {
+    let invalid = || {
+        format_err!(
+            "'{}' is not a valid number. Expected digits, optionally separated by underscores and \
+            followed by one of the suffixes k, M, G (powers of 1000) or Ki, Mi, Gi (powers of \
+            1024). E.g. `100_000` or `64Mi{}`.",
+            text,
+            unit
+        )
+    };
+    let end_of_digits = text
+        .find(|c: char| !c.is_ascii_digit() && c != '_')
+        .unwrap_or(text.len());
+    let (digits, suffix) = text.split_at(end_of_digits);
+    if digits.is_empty() || digits.starts_with('_') {
+        return Err(invalid());
+    }
+    let suffix = suffix.strip_suffix(unit).unwrap_or(suffix);
+    let multiplier: u64 = match suffix {
+        "" => 1,
+        "k" => 1_000,
+        "M" => 1_000_000,
+        "G" => 1_000_000_000,
+        "Ki" => 1 << 10,
+        "Mi" => 1 << 20,
+        "Gi" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    let too_large = || format_err!("'{}' exceeds the largest supported value.", text);
+    // Only digits are left, so parsing can only fail due to overflow.
+    let number: u64 = digits.replace('_', "").parse().map_err(|_| too_large())?;
+    let value = number.checked_mul(multiplier).ok_or_else(too_large)?;
+    T::try_from(value).map_err(|_| too_large())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_byte_size, parse_count};
+
+    fn count(text: &str) -> Option<u64> {
+        parse_count(text).ok()
+    }
+
+    #[test]
+    fn plain_numbers() {
+        assert_eq!(Some(0), count("0"));
+        assert_eq!(Some(123), count("123"));
+        assert_eq!(Some(u64::MAX), count("18446744073709551615"));
+        assert_eq!(None, count(""));
+        assert_eq!(None, count("-1"));
+        assert_eq!(None, count("1.5"));
+    }
+
+    #[test]
+    fn suffixes() {
+        assert_eq!(Some(2_000), count("2k"));
+        assert_eq!(Some(2_000_000), count("2M"));
+        assert_eq!(Some(2_000_000_000), count("2G"));
+        assert_eq!(Some(2 * 1024), count("2Ki"));
+        assert_eq!(Some(2 * 1024 * 1024), count("2Mi"));
+        assert_eq!(Some(2 * 1024 * 1024 * 1024), count("2Gi"));
+        // Suffixes are case sensitive, and `B` is only allowed for byte sizes.
+        assert_eq!(None, count("2K"));
+        assert_eq!(None, count("2m"));
+        assert_eq!(None, count("2MiB"));
+        assert_eq!(None, count("k"));
+    }
+
+    #[test]
+    fn byte_sizes() {
+        assert_eq!(64 << 20, parse_byte_size::<u64>("64MiB").unwrap());
+        assert_eq!(64 << 20, parse_byte_size::<u64>("64Mi").unwrap());
+        assert_eq!(1_000, parse_byte_size::<u64>("1kB").unwrap());
+        assert_eq!(512, parse_byte_size::<u64>("512B").unwrap());
+        assert_eq!(512, parse_byte_size::<u64>("512").unwrap());
+        assert!(parse_byte_size::<u64>("1BB").is_err());
+    }
+
+    #[test]
+    fn underscores() {
+        assert_eq!(Some(1_000_000), count("1_000_000"));
+        assert_eq!(Some(1_000_000), count("1_000k"));
+        assert_eq!(Some(1_000), count("1__000"));
+        assert_eq!(None, count("_1000"));
+        assert_eq!(None, count("_"));
+    }
+
+    #[test]
+    fn overflow() {
+        for (suffix, multiplier) in [
+            ("k", 1_000),
+            ("M", 1_000_000),
+            ("G", 1_000_000_000),
+            ("Ki", 1 << 10),
+            ("Mi", 1 << 20),
+            ("Gi", 1 << 30),
+        ] {
+            let largest = u64::MAX / multiplier;
+            assert_eq!(
+                Some(largest * multiplier),
+                count(&format!("{}{}", largest, suffix))
+            );
+            let error = parse_count::<u64>(&format!("{}{}", largest + 1, suffix)).unwrap_err();
+            assert_eq!(
+                format!(
+                    "'{}{}' exceeds the largest supported value.",
+                    largest + 1,
+                    suffix
+                ),
+                error.to_string()
+            );
+        }
+        assert_eq!(None, count("18446744073709551616"));
+        // The value must fit into the target type, too.
+        assert_eq!(u32::MAX, parse_count::<u32>("4294967295").unwrap());
+        assert!(parse_count::<u32>("4294967296").is_err());
+        assert!(parse_count::<u32>("4Gi").is_err());
+    }
+}
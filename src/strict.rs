@@ -0,0 +1,138 @@
+use std::fmt;
+
+use anyhow::{format_err, Error};
+use log::warn;
+use odbc_api::DataType;
+use parquet::basic::Type as PhysicalType;
+
+use crate::{column_mapping::ColumnMapping, timestamp::TimestampPrecision};
+
+/// Ways in which an export may lose information. By default each is logged as a warning, with
+/// `--strict` each aborts the export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LossyRule {
+    /// SQL FLOAT may have double precision, but is written as 32 Bit float.
+    FloatPrecision,
+    /// Fractional seconds beyond the precision of the parquet column are cut off.
+    TimestampPrecision,
+    /// A type without parquet equivalent is written as text.
+    FallbackToText,
+    /// A column is left out of the export.
+    IgnoredColumn,
+    /// Deduplication switched to a bloom filter, which may drop rows with unique keys.
+    ApproximateDeduplication,
+}
+
+impl fmt::Display for LossyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LossyRule::FloatPrecision => "float-precision",
+            LossyRule::TimestampPrecision => "timestamp-precision",
+            LossyRule::FallbackToText => "fallback-to-text",
+            LossyRule::IgnoredColumn => "ignored-column",
+            LossyRule::ApproximateDeduplication => "approximate-deduplication",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Decides whether losing information is a warning or an error. Every place which may lose
+/// information reports it here, so `--strict` covers it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LossPolicy {
+    strict: bool,
+}
+
+impl LossPolicy {
+    pub fn new(strict: bool) -> Self {
+        LossPolicy { strict }
+    }
+
+    /// `true` if any loss of information aborts the export. Used to skip checks on individual
+    /// values, which would be too costly or too noisy otherwise.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Warn about the loss of information, or fail if strict.
+    ///
+    /// # Parameters
+    ///
+    /// * `location`: Column (and batch) affected. E.g. `column 'price'`.
+    /// * `message`: What is lost.
+    pub fn report(&self, rule: LossyRule, location: &str, message: &str) -> Result<(), Error> {
+        if self.strict {
+            Err(violation(rule, location, message))
+        } else {
+            warn!("{}: {} [{}]", capitalize(location), message, rule);
+            Ok(())
+        }
+    }
+}
+
+/// Error for a loss of information, which is not acceptable due to `--strict`.
+pub fn violation(rule: LossyRule, location: &str, message: &str) -> Error {
+    format_err!(
+        "{} violates rule '{}': {} Aborting due to --strict.",
+        capitalize(location),
+        rule,
+        message
+    )
+}
+
+/// Losses following from the mapping of a column alone, independent of its values.
+pub fn column_losses(mapping: &ColumnMapping) -> Vec<(LossyRule, String)> {
+    let mut losses = Vec::new();
+    // Unmasked time columns are written as TIME_MILLIS or TIME_MICROS.
+    if let Some(precision) = mapping.time_precision {
+        if precision > 6 {
+            losses.push((
+                LossyRule::TimestampPrecision,
+                format!(
+                    "Time has {} fractional digits, but is written with microsecond precision. \
+                    Fractional seconds are truncated.",
+                    precision
+                ),
+            ));
+        }
+        return losses;
+    }
+    match (mapping.data_type, mapping.physical_type) {
+        (DataType::Float, PhysicalType::FLOAT) => losses.push((
+            LossyRule::FloatPrecision,
+            "SQL FLOAT may have double precision, but is written as 32 Bit FLOAT. Values may be \
+            rounded."
+                .to_owned(),
+        )),
+        (DataType::Timestamp { precision }, PhysicalType::INT64)
+            if precision > TimestampPrecision::of_column(mapping.logical_type).digits() =>
+        {
+            losses.push((
+                LossyRule::TimestampPrecision,
+                format!(
+                    "Timestamp has {} fractional digits, but is written with a precision of {}. \
+                    Fractional seconds are truncated.",
+                    precision,
+                    TimestampPrecision::of_column(mapping.logical_type).unit()
+                ),
+            ))
+        }
+        // Binary columns and GUIDs written as bytes with `--guid-as-bytes`.
+        (DataType::Other { .. }, _) if mapping.is_binary() || mapping.is_guid_bytes() => (),
+        (DataType::Time { .. }, _) | (DataType::Unknown, _) | (DataType::Other { .. }, _) => losses
+            .push((
+                LossyRule::FallbackToText,
+                "Type has no parquet equivalent. Falling back to text.".to_owned(),
+            )),
+        _ => (),
+    }
+    losses
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
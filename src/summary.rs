@@ -0,0 +1,110 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Error;
+use serde_json::{json, Map, Value};
+
+use crate::profile::ColumnProfile;
+
+/// Facts about a finished export. Written as JSON to the path specified with `--summary-file`.
+#[derive(Default)]
+pub struct Summary {
+    /// Number of rows fetched from the data source.
+    pub num_rows_fetched: u64,
+    /// Number of rows written to the output. May differ from the number of fetched rows, e.g. due
+    /// to sampling.
+    pub num_rows_written: u64,
+    /// Number of rows the output held before the export. `None` unless `--append` is specified.
+    pub num_rows_prior: Option<u64>,
+    /// `true` if fetching stopped early due to `--max-duration`.
+    pub time_boxed: bool,
+    /// Number of rows of the result set which have not been fetched due to `--max-duration`. Only
+    /// known if the rows have been counted for `--estimate`.
+    pub num_rows_remaining: Option<u64>,
+    /// Per column statistics. Empty unless `--profile` is specified.
+    pub profiles: Vec<ColumnProfile>,
+    /// Name and number of replaced NULLs for each column specified in `--null-default`.
+    pub nulls_substituted: Vec<(String, u64)>,
+    /// Number of rows and estimated size in bytes of each written row group.
+    pub row_groups: Vec<(u64, u64)>,
+    /// Number of rows dropped due to `--dedupe-on`. `None` if not deduplicating.
+    pub num_duplicates_dropped: Option<u64>,
+    /// `true` if the driver did not support block cursors and rows have been fetched one at a
+    /// time.
+    pub single_row_fetch: bool,
+    /// Name of each column left out due to `--skip-unsupported-columns` and the reason.
+    pub skipped_columns: Vec<(String, String)>,
+    /// Output files handed to `--upload-command`, and whether they have been removed locally. All
+    /// of them have been uploaded successfully, since the export fails otherwise.
+    pub uploaded_files: Vec<(PathBuf, bool)>,
+}
+
+impl Summary {
+    pub fn to_json(&self) -> Value {
+        let mut summary = json!({
+            "num_rows_fetched": self.num_rows_fetched,
+            "num_rows_written": self.num_rows_written,
+        });
+        if let Some(num) = self.num_rows_prior {
+            summary["num_rows_prior"] = num.into();
+        }
+        if self.time_boxed {
+            summary["time_boxed"] = true.into();
+            summary["num_rows_remaining"] = json!(self.num_rows_remaining);
+        }
+        if !self.profiles.is_empty() {
+            summary["columns"] = self.profiles.iter().map(ColumnProfile::to_json).collect();
+        }
+        if !self.nulls_substituted.is_empty() {
+            summary["nulls_substituted"] = self
+                .nulls_substituted
+                .iter()
+                .map(|(column, num)| (column.clone(), Value::from(*num)))
+                .collect::<Map<_, _>>()
+                .into();
+        }
+        if !self.row_groups.is_empty() {
+            summary["row_groups"] = self
+                .row_groups
+                .iter()
+                .map(|&(num_rows, num_bytes)| {
+                    json!({ "num_rows": num_rows, "estimated_bytes": num_bytes })
+                })
+                .collect();
+        }
+        if let Some(num) = self.num_duplicates_dropped {
+            summary["num_duplicates_dropped"] = num.into();
+        }
+        if self.single_row_fetch {
+            summary["single_row_fetch"] = true.into();
+        }
+        if !self.skipped_columns.is_empty() {
+            summary["skipped_columns"] = self
+                .skipped_columns
+                .iter()
+                .map(|(name, reason)| json!({ "name": name, "reason": reason }))
+                .collect();
+        }
+        if !self.uploaded_files.is_empty() {
+            summary["uploaded_files"] = self
+                .uploaded_files
+                .iter()
+                .map(|(path, removed)| {
+                    json!({
+                        "path": path.display().to_string(),
+                        "uploaded": true,
+                        "removed": removed,
+                    })
+                })
+                .collect();
+        }
+        summary
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, serde_json::to_string_pretty(&self.to_json())?)?;
+        Ok(())
+    }
+}
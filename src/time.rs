@@ -0,0 +1,120 @@
+use std::{ffi::CStr, str::FromStr};
+
+use anyhow::{bail, Error};
+
+/// Number of fractional second digits of a `TIME` column, overriding the one reported by the
+/// driver. Parsed from command line arguments of the form `column=digits`.
+#[derive(Debug, Clone)]
+pub struct TimePrecision {
+    /// Name of the column in the result set.
+    pub column: String,
+    pub digits: i16,
+}
+
+impl FromStr for TimePrecision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, digits) = match s.find('=') {
+            Some(pos) => (&s[..pos], &s[(pos + 1)..]),
+            None => bail!(
+                "Time precision '{}' must be of the form `column=digits`. E.g. `start=3`.",
+                s
+            ),
+        };
+        if column.is_empty() {
+            bail!("Time precision '{}' does not specify a column name.", s)
+        }
+        let digits = match digits.parse::<i16>() {
+            Ok(digits @ 0..=9) => digits,
+            _ => bail!(
+                "Time precision of column '{}' must be between 0 and 9. Found: '{}'",
+                column,
+                digits
+            ),
+        };
+        Ok(TimePrecision {
+            column: column.to_owned(),
+            digits,
+        })
+    }
+}
+
+/// Nanoseconds since midnight of a time of day in the text representation `hh:mm:ss[.fffffffff]`.
+/// This is how drivers return `TIME` columns bound as text.
+pub fn nanos_since_midnight(text: &CStr) -> Result<i64, Error> {
+    let invalid = || Error::msg(format!("Invalid time of day '{}'.", text.to_string_lossy()));
+    let text = text.to_str().map_err(|_| invalid())?;
+    let (hms, fraction) = match text.find('.') {
+        Some(pos) => (&text[..pos], &text[(pos + 1)..]),
+        None => (text, ""),
+    };
+    let mut parts = hms.split(':');
+    let mut next = |max: i64| -> Result<i64, Error> {
+        let part = parts.next().ok_or_else(invalid)?;
+        // Checked for digits first, since `parse` would also accept a sign, like in `+1`.
+        if part.len() != 2 || !part.bytes().all(|digit| digit.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        match part.parse::<i64>() {
+            Ok(value) if (0..max).contains(&value) => Ok(value),
+            _ => Err(invalid()),
+        }
+    };
+    let (hour, minute, second) = (next(24)?, next(60)?, next(60)?);
+    if parts.next().is_some()
+        || fraction.len() > 9
+        || !fraction.bytes().all(|digit| digit.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    // Pad the fraction to nine digits, so it represents nanoseconds.
+    let nanos = fraction
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0, |nanos, digit| nanos * 10 + (digit - b'0') as i64);
+    Ok(((hour * 60 + minute) * 60 + second) * 1_000_000_000 + nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::{nanos_since_midnight, TimePrecision};
+
+    fn nanos(text: &str) -> Option<i64> {
+        nanos_since_midnight(&CString::new(text).unwrap()).ok()
+    }
+
+    #[test]
+    fn parse_time_of_day() {
+        assert_eq!(Some(334_000_000_000), nanos("00:05:34"));
+        assert_eq!(Some(50_732_123_000_000), nanos("14:05:32.123"));
+        assert_eq!(Some(14_052_000_000_100), nanos("03:54:12.0000001"));
+        assert_eq!(Some(86_399_999_999_999), nanos("23:59:59.999999999"));
+        assert_eq!(None, nanos("24:00:00"));
+        assert_eq!(None, nanos("12:5:00"));
+        assert_eq!(None, nanos("12:05"));
+        assert_eq!(None, nanos("12:05:00.1234567890"));
+        assert_eq!(None, nanos("12:05:00.12a"));
+        assert_eq!(None, nanos(""));
+        // Missing fraction separator
+        assert_eq!(None, nanos("12:05:00123"));
+        assert_eq!(None, nanos("12:05:00:123"));
+        // Out of range or signed components
+        assert_eq!(None, nanos("12:60:00"));
+        assert_eq!(None, nanos("12:05:60"));
+        assert_eq!(None, nanos("+1:05:00"));
+        assert_eq!(None, nanos("12:-5:00"));
+    }
+
+    #[test]
+    fn parse_time_precision() {
+        let precision: TimePrecision = "start=3".parse().unwrap();
+        assert_eq!(("start", 3), (precision.column.as_str(), precision.digits));
+        assert!("start=10".parse::<TimePrecision>().is_err());
+        assert!("=3".parse::<TimePrecision>().is_err());
+        assert!("start".parse::<TimePrecision>().is_err());
+    }
+}
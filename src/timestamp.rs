@@ -0,0 +1,228 @@
+use std::str::FromStr;
+
+use anyhow::{bail, format_err, Error};
+use odbc_api::sys::Timestamp;
+use parquet::basic::LogicalType;
+
+/// Precision timestamps are written with, if given with `--timestamp-precision`. Otherwise it is
+/// derived from the number of fractional digits reported by the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampPrecision {
+    Milliseconds,
+    Microseconds,
+    /// Parquet 3 lacks a logical type for nanoseconds, so these are written as plain INT64.
+    Nanoseconds,
+}
+
+impl FromStr for TimestampPrecision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "milliseconds" => Ok(TimestampPrecision::Milliseconds),
+            "microseconds" => Ok(TimestampPrecision::Microseconds),
+            "nanoseconds" => Ok(TimestampPrecision::Nanoseconds),
+            other => bail!(
+                "Unknown timestamp precision '{}'. Supported are `milliseconds`, `microseconds` \
+                and `nanoseconds`.",
+                other
+            ),
+        }
+    }
+}
+
+impl TimestampPrecision {
+    /// Precision of an INT64 timestamp column with `logical_type`.
+    pub fn of_column(logical_type: LogicalType) -> Self {
+        match logical_type {
+            LogicalType::TIMESTAMP_MILLIS => TimestampPrecision::Milliseconds,
+            LogicalType::TIMESTAMP_MICROS => TimestampPrecision::Microseconds,
+            _ => TimestampPrecision::Nanoseconds,
+        }
+    }
+
+    pub fn logical_type(self) -> LogicalType {
+        match self {
+            TimestampPrecision::Milliseconds => LogicalType::TIMESTAMP_MILLIS,
+            TimestampPrecision::Microseconds => LogicalType::TIMESTAMP_MICROS,
+            TimestampPrecision::Nanoseconds => LogicalType::NONE,
+        }
+    }
+
+    /// Number of fractional second digits preserved.
+    pub fn digits(self) -> i16 {
+        match self {
+            TimestampPrecision::Milliseconds => 3,
+            TimestampPrecision::Microseconds => 6,
+            TimestampPrecision::Nanoseconds => 9,
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            TimestampPrecision::Milliseconds => "milliseconds",
+            TimestampPrecision::Microseconds => "microseconds",
+            TimestampPrecision::Nanoseconds => "nanoseconds",
+        }
+    }
+}
+
+/// Milliseconds since the unix epoch. Identical to `NaiveDateTime::timestamp_millis` of chrono.
+pub fn timestamp_millis(ts: &Timestamp) -> Result<i64, Error> {
+    let (seconds, nanos) = seconds_and_nanos(ts)?;
+    Ok(seconds * 1_000 + (nanos / 1_000_000) as i64)
+}
+
+/// Microseconds since the unix epoch. Identical to `NaiveDateTime::timestamp` multiplied by a
+/// million plus `NaiveDateTime::timestamp_subsec_micros` of chrono.
+pub fn timestamp_micros(ts: &Timestamp) -> Result<i64, Error> {
+    let (seconds, nanos) = seconds_and_nanos(ts)?;
+    Ok(seconds * 1_000_000 + (nanos / 1_000) as i64)
+}
+
+/// Nanoseconds since the unix epoch. Only timestamps between 1677-09-21 and 2262-04-11 fit into
+/// 64 Bit.
+pub fn timestamp_nanos(ts: &Timestamp) -> Result<i64, Error> {
+    let (seconds, nanos) = seconds_and_nanos(ts)?;
+    seconds
+        .checked_mul(1_000_000_000)
+        .and_then(|nanos_of_seconds| nanos_of_seconds.checked_add(nanos as i64))
+        .ok_or_else(|| {
+            format_err!(
+                "Timestamp {:04}-{:02}-{:02} {:02}:{:02}:{:02} is out of the range representable \
+                in nanoseconds, which is 1677-09-21 to 2262-04-11.",
+                ts.year,
+                ts.month,
+                ts.day,
+                ts.hour,
+                ts.minute,
+                ts.second
+            )
+        })
+}
+
+/// Seconds since the unix epoch and the nanoseconds within that second. We do the arithmetic
+/// ourselves, rather than constructing a chrono `NaiveDateTime` for every value, since the latter
+/// dominates the time spent on exports with many timestamps.
+fn seconds_and_nanos(ts: &Timestamp) -> Result<(i64, u32), Error> {
+    // Same ranges chrono accepts. A fraction of more than a second represents a leap second.
+    let is_valid = (1..=12).contains(&ts.month)
+        && ts.day >= 1
+        // Only look at the calendar, if the day may be beyond the end of the month.
+        && (ts.day <= 28 || ts.day <= days_in_month(ts.year as i64, ts.month))
+        && ts.hour < 24
+        && ts.minute < 60
+        && ts.second < 60
+        && ts.fraction < 2_000_000_000;
+    if !is_valid {
+        bail!(
+            "Invalid timestamp {:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09}.",
+            ts.year,
+            ts.month,
+            ts.day,
+            ts.hour,
+            ts.minute,
+            ts.second,
+            ts.fraction
+        )
+    }
+    let days = days_from_civil(ts.year as i64, ts.month as i64, ts.day as i64);
+    let seconds = days * 86_400 + ts.hour as i64 * 3_600 + ts.minute as i64 * 60 + ts.second as i64;
+    Ok((seconds, ts.fraction))
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar. See
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Shift years by a multiple of 400 (the length of an era), so they are positive for every
+    // year an ODBC timestamp can hold. Saves us from rounding towards negative infinity.
+    const YEAR_OFFSET: i64 = 400 * 82;
+    const DAYS_OFFSET: i64 = 146_097 * 82;
+    // Years start in March, so the leap day is the last day of the year.
+    let year = year + YEAR_OFFSET - (month <= 2) as i64;
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 719468 is the number of days from 0000-03-01 to 1970-01-01.
+    era * 146_097 + day_of_era - 719_468 - DAYS_OFFSET
+}
+
+fn days_in_month(year: i64, month: u16) -> u16 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use odbc_api::sys::Timestamp;
+
+    use super::{timestamp_micros, timestamp_millis, timestamp_nanos};
+
+    /// Compares the conversion of timestamps against chrono as a reference, for random timestamps
+    /// within the years 1600 to 9999. Also includes invalid dates, like the 30th of February.
+    #[test]
+    fn timestamp_conversion_matches_chrono() {
+        // SplitMix64, so we do not need another dependency for random numbers.
+        let mut state = 42u64;
+        let mut random = |range: u64| {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            (z ^ (z >> 31)) % range
+        };
+
+        for _ in 0..1_000_000 {
+            let ts = Timestamp {
+                year: 1600 + random(8400) as i16,
+                month: 1 + random(12) as u16,
+                day: 1 + random(31) as u16,
+                hour: random(24) as u16,
+                minute: random(60) as u16,
+                second: random(60) as u16,
+                // Every now and then a leap second.
+                fraction: random(1_100_000_000) as u32,
+            };
+            let reference = NaiveDate::from_ymd_opt(ts.year as i32, ts.month as u32, ts.day as u32)
+                .and_then(|date| {
+                    date.and_hms_nano_opt(
+                        ts.hour as u32,
+                        ts.minute as u32,
+                        ts.second as u32,
+                        ts.fraction,
+                    )
+                });
+            match reference {
+                Some(reference) => {
+                    assert_eq!(
+                        reference.timestamp_millis(),
+                        timestamp_millis(&ts).unwrap(),
+                        "{:?}",
+                        ts
+                    );
+                    assert_eq!(
+                        reference.timestamp() * 1_000_000
+                            + reference.timestamp_subsec_micros() as i64,
+                        timestamp_micros(&ts).unwrap(),
+                        "{:?}",
+                        ts
+                    );
+                    let nanos =
+                        reference
+                            .timestamp()
+                            .checked_mul(1_000_000_000)
+                            .and_then(|nanos| {
+                                nanos.checked_add(reference.timestamp_subsec_nanos() as i64)
+                            });
+                    assert_eq!(nanos, timestamp_nanos(&ts).ok(), "{:?}", ts);
+                }
+                None => assert!(timestamp_millis(&ts).is_err(), "{:?}", ts),
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use parquet::file::metadata::KeyValue;
+
+/// Key value pair given with `--metadata`, written into the footer of each file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserMetadata {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for UserMetadata {
+    type Err = Error;
+
+    /// Splits at the first `=`, so the value may contain `=`, too.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match s.find('=') {
+            Some(pos) => (&s[..pos], &s[(pos + 1)..]),
+            None => bail!(
+                "Metadata '{}' must be of the form `key=value`. E.g. `dataset_owner=sales`.",
+                s
+            ),
+        };
+        if key.is_empty() {
+            bail!("Metadata '{}' does not specify a key.", s)
+        }
+        Ok(UserMetadata {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+/// Fails if the same key is given more than once.
+pub fn check_unique(metadata: &[UserMetadata]) -> Result<(), Error> {
+    for (index, entry) in metadata.iter().enumerate() {
+        if metadata[..index].iter().any(|other| other.key == entry.key) {
+            bail!("Metadata key '{}' is given more than once.", entry.key)
+        }
+    }
+    Ok(())
+}
+
+/// Adds the user supplied entries to the generated ones. Generated entries with the same key are
+/// replaced.
+pub fn merge(generated: &mut Vec<KeyValue>, metadata: &[UserMetadata]) {
+    for UserMetadata { key, value } in metadata {
+        let entry = KeyValue::new(key.clone(), value.clone());
+        match generated.iter_mut().find(|generated| &generated.key == key) {
+            Some(generated) => *generated = entry,
+            None => generated.push(entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::metadata::KeyValue;
+
+    use super::{check_unique, merge, UserMetadata};
+
+    fn parse(text: &str) -> UserMetadata {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_metadata() {
+        assert_eq!(
+            UserMetadata {
+                key: "filter".to_owned(),
+                value: "year=1993".to_owned()
+            },
+            parse("filter=year=1993")
+        );
+        assert_eq!("", parse("empty=").value);
+        assert!("owner".parse::<UserMetadata>().is_err());
+        assert!("=sales".parse::<UserMetadata>().is_err());
+    }
+
+    #[test]
+    fn user_metadata_wins() {
+        let metadata = [parse("owner=sales"), parse("odbc2parquet.query=hidden")];
+        check_unique(&metadata).unwrap();
+        assert!(check_unique(&[parse("owner=a"), parse("owner=b")]).is_err());
+
+        let mut generated = vec![KeyValue::new(
+            "odbc2parquet.query".to_owned(),
+            "SELECT".to_owned(),
+        )];
+        merge(&mut generated, &metadata);
+        assert_eq!(
+            vec![
+                KeyValue::new("odbc2parquet.query".to_owned(), "hidden".to_owned()),
+                KeyValue::new("owner".to_owned(), "sales".to_owned()),
+            ],
+            generated
+        );
+    }
+}
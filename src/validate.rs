@@ -0,0 +1,198 @@
+//! Combinations of query options which contradict each other, or in which an option would be
+//! silently ignored. Checked after parsing the command line, before connecting to anything.
+
+use crate::{parquet_buffer::ConversionErrorPolicy, QueryOpt};
+
+/// A constraint on the query options, which can not be expressed with `conflicts_with` or
+/// `requires`, e.g. because it depends on the value of an option.
+struct Rule {
+    /// `true` if the options violate the rule.
+    violated: fn(&QueryOpt) -> bool,
+    /// One line explanation shown to the user.
+    message: &'static str,
+    /// Arguments violating the rule. Checked by the tests, so each rule is known to trigger.
+    #[cfg_attr(not(test), allow(dead_code))]
+    example: &'static [&'static str],
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        violated: |opt| {
+            opt.connect_opts.dsn.is_none() && opt.connect_opts.connection_string.is_none()
+        },
+        message: "Please specify a data source either using --dsn or --connection-string.",
+        example: &[],
+    },
+    Rule {
+        violated: |opt| {
+            opt.connect_opts.connection_string.is_some()
+                && (opt.connect_opts.user.is_some() || opt.connect_opts.password.is_some())
+        },
+        message: "--user and --password can not be combined with --connection-string. Specify \
+            them in the connection string instead, e.g. `UID=sa;PWD=secret;`.",
+        example: &["--connection-string", "DSN=db", "--user", "sa"],
+    },
+    Rule {
+        violated: |opt| opt.batch_size == Some(0),
+        message: "--batch-size must be at least 1.",
+        example: &["--batch-size", "0"],
+    },
+    Rule {
+        violated: |opt| opt.rows_per_row_group == Some(0),
+        message: "--rows-per-row-group must be at least 1.",
+        example: &["--rows-per-row-group", "0"],
+    },
+    Rule {
+        violated: |opt| opt.strict && opt.on_conversion_error == ConversionErrorPolicy::Null,
+        message: "--strict can not be combined with `--on-conversion-error null`.",
+        example: &["--strict", "--on-conversion-error", "null"],
+    },
+    Rule {
+        violated: |opt| {
+            opt.always_suffix && opt.batches_per_file == 0 && opt.file_size_threshold.is_none()
+        },
+        message: "--always-suffix has no effect without --batches-per-file or \
+            --file-size-threshold.",
+        example: &["--always-suffix"],
+    },
+    Rule {
+        violated: |opt| opt.force_statistics && opt.masks.is_empty(),
+        message: "--force-statistics has no effect without --mask.",
+        example: &["--force-statistics"],
+    },
+    Rule {
+        violated: |opt| {
+            opt.no_write
+                && (opt.batches_per_file != 0
+                    || opt.file_size_threshold.is_some()
+                    || opt.always_suffix
+                    || opt.no_empty_file
+                    || opt.append
+                    || opt.on_file_complete.is_some()
+                    || opt.upload_command.is_some())
+        },
+        message: "--no-write can not be combined with --batches-per-file, \
+            --file-size-threshold, --always-suffix, --no-empty-file, --append, \
+            --on-file-complete or --upload-command, since no file is written.",
+        example: &["--no-write", "--no-empty-file"],
+    },
+    Rule {
+        violated: |opt| {
+            opt.no_write
+                && (opt.row_group_memory_limit.is_some()
+                    || opt.rows_per_row_group.is_some()
+                    || opt.row_group_per_batch)
+        },
+        message: "--no-write can not be combined with --row-group-memory-limit, \
+            --rows-per-row-group or --row-group-per-batch, since no row group is written.",
+        example: &["--no-write", "--row-group-per-batch"],
+    },
+    Rule {
+        violated: |opt| {
+            opt.no_atomic
+                && (opt.append || opt.output_base64 || opt.no_write || opt.output.is_stdout())
+        },
+        message: "--no-atomic has no effect together with --append, --output-base64, --no-write \
+            or when writing to standard out (`-`).",
+        example: &["--no-atomic", "--no-write"],
+    },
+    Rule {
+        violated: |opt| opt.overwrite && (opt.output_base64 || opt.no_write),
+        message: "--overwrite has no effect together with --output-base64 or --no-write.",
+        example: &["--overwrite", "--no-write"],
+    },
+    // The positional arguments of the examples precede the ones appended by the tests, so `-`
+    // becomes the output.
+    Rule {
+        violated: |opt| {
+            opt.output.is_stdout()
+                && (opt.batches_per_file != 0
+                    || opt.file_size_threshold.is_some()
+                    || opt.always_suffix
+                    || opt.no_empty_file
+                    || opt.append
+                    || opt.on_file_complete.is_some()
+                    || opt.upload_command.is_some())
+        },
+        message: "Writing to standard out (`-`) can not be combined with --batches-per-file, \
+            --file-size-threshold, --always-suffix, --no-empty-file, --append, \
+            --on-file-complete or --upload-command, since no file is written.",
+        example: &["--batches-per-file", "2", "-", "SELECT"],
+    },
+    Rule {
+        violated: |opt| opt.output.is_stdout() && (opt.profile || opt.estimate),
+        message: "Writing to standard out (`-`) can not be combined with --profile or \
+            --estimate, since they print to standard out, too.",
+        example: &["--profile", "-", "SELECT"],
+    },
+    Rule {
+        violated: |opt| opt.reproducible && opt.sample_rate.is_some() && opt.sample_seed.is_none(),
+        message: "--reproducible requires --sample-seed, since --sample-rate draws a different \
+            sample each run otherwise.",
+        example: &["--reproducible", "--sample-rate", "0.5"],
+    },
+    Rule {
+        violated: |opt| opt.no_describe_param && opt.parameters.is_empty(),
+        message: "--no-describe-param has no effect on a query without parameters.",
+        example: &["--no-describe-param"],
+    },
+];
+
+/// Explanations for all rules violated by the options. Empty if the options are fine.
+pub fn violations(opt: &QueryOpt) -> Vec<&'static str> {
+    RULES
+        .iter()
+        .filter(|rule| (rule.violated)(opt))
+        .map(|rule| rule.message)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt;
+
+    use super::{violations, RULES};
+    use crate::QueryOpt;
+
+    fn violations_of(args: &[&str]) -> Vec<&'static str> {
+        let args = ["query"].iter().chain(args).chain(&["out.par", "SELECT"]);
+        violations(&QueryOpt::from_iter_safe(args).unwrap())
+    }
+
+    #[test]
+    fn every_rule_is_violated_by_its_example() {
+        for rule in RULES {
+            assert!(
+                violations_of(rule.example).contains(&rule.message),
+                "{:?} does not violate: {}",
+                rule.example,
+                rule.message
+            );
+        }
+    }
+
+    #[test]
+    fn all_violations_are_reported() {
+        let args = ["--batch-size", "0", "--always-suffix", "--force-statistics"];
+        // Lacks a data source, too.
+        assert_eq!(4, violations_of(&args).len());
+    }
+
+    #[test]
+    fn valid_options() {
+        assert!(violations_of(&["--connection-string", "DSN=db"]).is_empty());
+        let args = [
+            "--dsn",
+            "db",
+            "--user",
+            "sa",
+            "--batches-per-file",
+            "2",
+            "--always-suffix",
+            "--mask",
+            "a=null",
+            "--force-statistics",
+        ];
+        assert!(violations_of(&args).is_empty());
+    }
+}
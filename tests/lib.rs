@@ -1,5 +1,11 @@
+use std::path::Path;
+
 use assert_cmd::Command;
-use predicates::ord::eq;
+use predicates::{
+    boolean::PredicateBooleanExt,
+    ord::eq,
+    str::{contains, starts_with},
+};
 use tempfile::tempdir;
 
 const MSSQL: &str =
@@ -23,7 +29,7 @@ fn nullable_parquet_buffers() {
 
     Command::cargo_bin("odbc2parquet")
         .unwrap()
-        .args(&[
+        .args([
             "-vvvv",
             "query",
             out_str,
@@ -51,7 +57,7 @@ fn foobar_connection_string() {
     let out_str = out_path.to_str().expect("Tempfile path must be utf8");
 
     let mut cmd = Command::cargo_bin("odbc2parquet").unwrap();
-    cmd.args(&[
+    cmd.args([
         "-vvvv",
         "query",
         "-c",
@@ -80,7 +86,7 @@ fn parameters_in_query() {
 
     Command::cargo_bin("odbc2parquet")
         .unwrap()
-        .args(&[
+        .args([
             "-vvvv",
             "query",
             out_str,
@@ -98,13 +104,54 @@ fn parameters_in_query() {
     cmd.arg(out_str).assert().success().stdout(eq(expected));
 }
 
+#[test]
+fn describe_parameter_types() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let query = "SELECT title,year from Movies where year=?";
+
+    // The driver reports the type of the column the parameter is compared with.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            query,
+            "1968",
+        ])
+        .assert()
+        .success()
+        .stderr(contains("Binding parameter 1 as Integer."));
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--overwrite",
+            "--no-describe-param",
+            query,
+            "1968",
+        ])
+        .assert()
+        .success()
+        .stderr(contains("Binding parameter 1 as Varchar { length: 4 }."));
+}
+
 #[test]
 fn query_sales() {
     let expected_values = "\
-        {day: 2020-09-09 +00:00, time: \"00:05:34\", product: 54, price: 9.99}\n\
-        {day: 2020-09-10 +00:00, time: \"12:05:32\", product: 54, price: 9.99}\n\
-        {day: 2020-09-10 +00:00, time: \"14:05:32\", product: 34, price: 2.00}\n\
-        {day: 2020-09-11 +00:00, time: \"06:05:12\", product: 12, price: -1.50}\n\
+        {day: 2020-09-09 +00:00, time: 334000, product: 54, price: 9.99}\n\
+        {day: 2020-09-10 +00:00, time: 43532000, product: 54, price: 9.99}\n\
+        {day: 2020-09-10 +00:00, time: 50732000, product: 34, price: 2.00}\n\
+        {day: 2020-09-11 +00:00, time: 21912000, product: 12, price: -1.50}\n\
     ";
 
     // A temporary directory, to be removed at the end of the test.
@@ -117,7 +164,7 @@ fn query_sales() {
 
     Command::cargo_bin("odbc2parquet")
         .unwrap()
-        .args(&[
+        .args([
             "-vvvv",
             "query",
             out_str,
@@ -150,7 +197,7 @@ fn query_all_the_types() {
         my_double: 1.23, \
         my_varchar: \"Hello, World!\", \
         my_date: 2020-09-16 +00:00, \
-        my_time: \"03:54:12.0000000\", \
+        my_time: 14052000000, \
         my_timestamp: 2020-09-16 03:54:12 +00:00\
     }\n";
 
@@ -179,7 +226,7 @@ fn query_all_the_types() {
 
     Command::cargo_bin("odbc2parquet")
         .unwrap()
-        .args(&[
+        .args([
             "-vvvv",
             "query",
             out_str,
@@ -211,7 +258,7 @@ fn split_files() {
 
     Command::cargo_bin("odbc2parquet")
         .unwrap()
-        .args(&[
+        .args([
             "-vvvv",
             "query",
             out_str,
@@ -221,6 +268,8 @@ fn split_files() {
             "1",
             "--batches-per-file",
             "1",
+            "--suffix-length",
+            "1",
             "SELECT title FROM Movies ORDER BY year",
         ])
         .assert()
@@ -245,3 +294,1976 @@ fn split_files() {
         .assert()
         .success();
 }
+
+#[test]
+fn split_files_by_size() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    // Any file holding a row exceeds the threshold, so each row ends up in a file of its own.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--batch-size",
+            "1",
+            "--file-size-threshold",
+            "1",
+            "SELECT title FROM Movies ORDER BY year",
+        ])
+        .assert()
+        .success();
+
+    for name in ["out_01.par", "out_02.par", "out_03.par"] {
+        assert_eq!(1, read_parquet(&out_dir.path().join(name)).lines().count());
+    }
+    // No empty trailing file.
+    assert!(!out_dir.path().join("out_04.par").exists());
+    assert!(!out_path.exists());
+}
+
+#[test]
+fn failed_export_leaves_no_file() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    // Division by zero is only raised with the second batch, once the first one is written.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--batch-size",
+            "1",
+            "SELECT title, 1 / (1993 - year) AS x FROM Movies WHERE year IS NOT NULL \
+            ORDER BY year",
+        ])
+        .assert()
+        .failure();
+
+    assert!(!out_path.exists());
+    assert!(!out_dir.path().join("out.par.tmp").exists());
+}
+
+#[test]
+fn refuse_to_overwrite() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let query = |extra_args: &[&str]| {
+        let mut cmd = Command::cargo_bin("odbc2parquet").unwrap();
+        cmd.args(["query", out_str, "--connection-string", MSSQL])
+            .args(extra_args)
+            .arg("SELECT title FROM Movies ORDER BY year");
+        cmd
+    };
+
+    // Checked before connecting to the data source.
+    std::fs::write(&out_path, "previous export").unwrap();
+    query(&[]).assert().code(5).stderr(contains(
+        "out.par' already exists. Pass --overwrite to replace it.",
+    ));
+    // Only the suffixed names are written.
+    query(&["--batches-per-file", "1", "--always-suffix"])
+        .assert()
+        .stderr(contains("already exists").not());
+    std::fs::write(out_dir.path().join("out_01.par"), "previous export").unwrap();
+    query(&["--batches-per-file", "1", "--always-suffix"])
+        .assert()
+        .code(5)
+        .stderr(contains("out_01.par' already exists."));
+    query(&["--overwrite"])
+        .assert()
+        .stderr(contains("already exists").not());
+}
+
+#[test]
+fn refuse_to_overwrite_later_parts_before_connecting() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    // Left behind by a previous export, which has been split into more files.
+    std::fs::write(out_dir.path().join("out_03.par"), "previous export").unwrap();
+
+    // Connecting would fail, but the existing part is reported before trying.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            "foobar",
+            "--batches-per-file",
+            "1",
+            "SELECT title FROM Movies",
+        ])
+        .assert()
+        .code(5)
+        .stderr(contains("out_03.par' already exists."));
+}
+
+#[test]
+fn query_metadata() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let query = "SELECT title FROM Movies ORDER BY year";
+    let export = |extra_args: &[&str]| {
+        Command::cargo_bin("odbc2parquet")
+            .unwrap()
+            .args([
+                "query",
+                out_str,
+                "--connection-string",
+                MSSQL,
+                "--overwrite",
+            ])
+            .args(extra_args)
+            .arg(query)
+            .assert()
+            .success();
+    };
+
+    // Present in every part of a split output.
+    export(&["--batch-size", "1", "--batches-per-file", "1"]);
+    for name in ["out_01.par", "out_02.par", "out_03.par"] {
+        let metadata = key_value_metadata(&out_dir.path().join(name));
+        assert!(metadata.contains(&("odbc2parquet.query".to_owned(), query.to_owned())));
+        let (_, data_source) = metadata
+            .iter()
+            .find(|(key, _)| key == "odbc2parquet.data_source")
+            .unwrap();
+        assert!(data_source.contains("Server=localhost;"));
+        assert!(!data_source.contains("PWD"));
+        assert!(metadata
+            .iter()
+            .any(|(key, _)| key == "odbc2parquet.exported_at"));
+    }
+
+    export(&["--no-query-metadata"]);
+    assert!(key_value_metadata(&out_path).is_empty());
+
+    // User supplied keys replace generated ones.
+    export(&[
+        "--metadata",
+        "pipeline_run_id=run=42",
+        "--metadata",
+        "odbc2parquet.query=confidential",
+    ]);
+    let metadata = key_value_metadata(&out_path);
+    assert!(metadata.contains(&("pipeline_run_id".to_owned(), "run=42".to_owned())));
+    assert!(metadata.contains(&("odbc2parquet.query".to_owned(), "confidential".to_owned())));
+    assert!(!metadata.iter().any(|(_, value)| value == query));
+}
+
+#[test]
+fn duplicate_metadata_key() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--metadata",
+            "owner=a",
+            "--metadata",
+            "owner=b",
+            "SELECT title FROM Movies",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Metadata key 'owner' is given more than once."));
+}
+
+#[test]
+fn split_files_with_number_placeholder() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("movies_part{n}.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--batch-size",
+            "1",
+            "--batches-per-file",
+            "1",
+            "SELECT title FROM Movies ORDER BY year",
+        ])
+        .assert()
+        .success();
+
+    for name in [
+        "movies_part01.par",
+        "movies_part02.par",
+        "movies_part03.par",
+    ] {
+        Command::new("parquet-read")
+            .arg(out_dir.path().join(name).to_str().unwrap())
+            .assert()
+            .success();
+    }
+    assert!(!out_dir.path().join("movies_part.par").exists());
+}
+
+#[test]
+fn unknown_output_placeholder() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out_{part}.par",
+            "--connection-string",
+            MSSQL,
+            "SELECT title FROM Movies",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Unknown placeholder '{part}'"));
+}
+
+#[test]
+fn mask_columns() {
+    let expected = "\
+        {title: \"f1d900598a08c421496ab7dce06c5e728d1845907fda325bb52138ce9bbbd69b\", year: null}\n\
+        {title: \"b57dbf4a542adb44a101a0cbcf9c90c4c625b33fbfa42acee2d56914fa54dd9a\", year: \"2000\"}\n\
+        {title: \"5985ba76a23e8ec9befff90e372be6bf98e4a8a9473da6d1988de45b0593d0ee\", year: \"2000\"}\n\
+    ";
+
+    // A temporary directory, to be removed at the end of the test.
+    let out_dir = tempdir().unwrap();
+    // The name of the output parquet file we are going to write. Since it is in a temporary
+    // directory it will not outlive the end of the test.
+    let out_path = out_dir.path().join("out.par");
+    // We need to pass the output path as a string argument.
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--mask",
+            "title=sha256",
+            "--mask",
+            "year=fixed:2000",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .success();
+
+    // Use the parquet-read tool to verify the output. It can be installed with
+    // `cargo install parquet`.
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str).assert().success().stdout(eq(expected));
+}
+
+#[test]
+fn no_statistics_for_masked_columns() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    let export = |force_statistics: bool| {
+        let mut cmd = Command::cargo_bin("odbc2parquet").unwrap();
+        cmd.args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--mask",
+            "title=sha256",
+            "--overwrite",
+        ]);
+        if force_statistics {
+            cmd.arg("--force-statistics");
+        }
+        cmd.arg("SELECT title from Movies order by year")
+            .assert()
+            .success();
+    };
+
+    // Use the parquet-schema tool to inspect the statistics in the footer. It can be installed
+    // with `cargo install parquet`.
+    export(false);
+    Command::new("parquet-schema")
+        .args([out_str, "true"])
+        .assert()
+        .success()
+        .stdout(contains("min: N/A, max: N/A"));
+
+    export(true);
+    Command::new("parquet-schema")
+        .args([out_str, "true"])
+        .assert()
+        .success()
+        .stdout(contains("min: N/A, max: N/A").not());
+}
+
+#[test]
+fn mask_unknown_column() {
+    // A temporary directory, to be removed at the end of the test.
+    let out_dir = tempdir().unwrap();
+    // The name of the output parquet file we are going to write. Since it is in a temporary
+    // directory it will not outlive the end of the test.
+    let out_path = out_dir.path().join("out.par");
+    // We need to pass the output path as a string argument.
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--mask",
+            "titel=sha256",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .code(1);
+}
+
+#[test]
+fn sample_rate_of_one_keeps_all_rows() {
+    let expected = "\
+        {title: \"Interstellar\", year: null}\n\
+        {title: \"2001: A Space Odyssey\", year: 1968}\n\
+        {title: \"Jurassic Park\", year: 1993}\n\
+    ";
+
+    // A temporary directory, to be removed at the end of the test.
+    let out_dir = tempdir().unwrap();
+    // The name of the output parquet file we are going to write. Since it is in a temporary
+    // directory it will not outlive the end of the test.
+    let out_path = out_dir.path().join("out.par");
+    // We need to pass the output path as a string argument.
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--sample-rate",
+            "1",
+            "--sample-seed",
+            "42",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .success();
+
+    // Use the parquet-read tool to verify the output. It can be installed with
+    // `cargo install parquet`.
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str).assert().success().stdout(eq(expected));
+}
+
+#[test]
+fn sample_rate_out_of_range() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--sample-rate",
+            "1.5",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn sizes_with_suffixes() {
+    // Connecting fails, but only after all the sizes have been parsed successfully.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            "foobar",
+            "--batch-size",
+            "100_000",
+            "--batches-per-file",
+            "1k",
+            "--row-group-memory-limit",
+            "64MiB",
+            "--dedupe-max-keys",
+            "2Mi",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Invalid value").not());
+}
+
+#[test]
+fn estimate_conflicts_with_base64() {
+    // Both print to standard out, so the estimate would corrupt the base64 payload.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--estimate",
+            "--output-base64",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("--output-base64"));
+}
+
+#[test]
+fn size_with_invalid_suffix() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--batch-size",
+            "10KB",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("--batch-size").and(contains("'10KB' is not a valid number")));
+}
+
+#[test]
+fn size_out_of_range() {
+    // Batch size is a 32 Bit integer.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--batch-size",
+            "4Gi",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("'4Gi' exceeds the largest supported value"));
+}
+
+#[test]
+fn profile_without_writing() {
+    // A temporary directory, to be removed at the end of the test.
+    let out_dir = tempdir().unwrap();
+    // Since we specify `--no-write` this file is never created.
+    let out_path = out_dir.path().join("out.par");
+    // We need to pass the output path as a string argument.
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let summary_path = out_dir.path().join("summary.json");
+    let summary_str = summary_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--profile",
+            "--no-write",
+            "--summary-file",
+            summary_str,
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .success()
+        .stdout(contains("title  | 0     | -    | -    | 21         | 3\n"))
+        .stdout(contains("year   | 1     | 1968 | 1993 | -          | 2\n"));
+
+    assert!(!out_path.exists());
+    let summary = std::fs::read_to_string(summary_path).unwrap();
+    assert!(summary.contains("\"num_rows_fetched\": 3"));
+}
+
+#[test]
+fn reproducible() {
+    let out_dir = tempdir().unwrap();
+    let export = |name: &str, masks: &[&str]| {
+        let out_path = out_dir.path().join(name);
+        Command::cargo_bin("odbc2parquet")
+            .unwrap()
+            .args([
+                "-vvvv",
+                "query",
+                out_path.to_str().expect("Tempfile path must be utf8"),
+                "--connection-string",
+                MSSQL,
+                "--reproducible",
+            ])
+            .args(masks)
+            .arg("SELECT title, year FROM Movies ORDER BY year")
+            .assert()
+            .success();
+        std::fs::read(out_path).unwrap()
+    };
+
+    let first = export(
+        "first.par",
+        &["--mask", "title=null", "--mask", "year=null"],
+    );
+    let second = export(
+        "second.par",
+        &["--mask", "year=null", "--mask", "title=null"],
+    );
+    assert_eq!(first, second);
+}
+
+#[test]
+fn append() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let summary_path = out_dir.path().join("summary.json");
+    let summary_str = summary_path.to_str().expect("Tempfile path must be utf8");
+    let export = |query: &str| {
+        Command::cargo_bin("odbc2parquet")
+            .unwrap()
+            .args([
+                "-vvvv",
+                "query",
+                out_str,
+                "--connection-string",
+                MSSQL,
+                "--append",
+                "--summary-file",
+                summary_str,
+                query,
+            ])
+            .assert()
+    };
+
+    // Starts a new file, since there is none yet.
+    export("SELECT title, year FROM Movies WHERE year IS NULL OR year < 1990 ORDER BY year")
+        .success();
+    export("SELECT title, year FROM Movies WHERE year >= 1990 ORDER BY year").success();
+
+    let summary = std::fs::read_to_string(&summary_path).unwrap();
+    assert!(summary.contains("\"num_rows_written\": 1"));
+    assert!(summary.contains("\"num_rows_prior\": 2"));
+    let expected = "{title: \"Interstellar\", year: null}\n\
+        {title: \"2001: A Space Odyssey\", year: 1968}\n\
+        {title: \"Jurassic Park\", year: 1993}\n";
+    assert_eq!(expected, read_parquet(&out_path));
+
+    // Columns differ, so the file must stay untouched.
+    export("SELECT title FROM Movies")
+        .failure()
+        .stderr(contains("It has 2 columns, but the query yields 1."));
+    assert_eq!(expected, read_parquet(&out_path));
+    assert!(!out_dir.path().join("out.par.tmp").exists());
+}
+
+#[test]
+fn max_duration() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let summary_path = out_dir.path().join("summary.json");
+    let summary_str = summary_path.to_str().expect("Tempfile path must be utf8");
+
+    // The deadline has passed before the first batch is fetched.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--max-duration",
+            "0s",
+            "--summary-file",
+            summary_str,
+            "SELECT title, year FROM Movies ORDER BY year",
+        ])
+        .assert()
+        .code(2)
+        .stderr(contains("Export stopped early due to --max-duration"));
+
+    // The output is still a valid parquet file.
+    assert_eq!("", read_parquet(&out_path));
+    let summary = std::fs::read_to_string(summary_path).unwrap();
+    assert!(summary.contains("\"time_boxed\": true"));
+    assert!(summary.contains("\"num_rows_remaining\": null"));
+}
+
+#[test]
+fn invalid_max_duration() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--max-duration",
+            "30min",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("'30min' is not a valid duration"));
+}
+
+#[test]
+fn column_compression() {
+    let out_dir = tempdir().unwrap();
+    let export = |compression: &str| {
+        let out_path = out_dir.path().join(format!("{}.par", compression));
+        Command::cargo_bin("odbc2parquet")
+            .unwrap()
+            .args([
+                "-vvvv",
+                "query",
+                out_path.to_str().expect("Tempfile path must be utf8"),
+                "--connection-string",
+                MSSQL,
+                "--column-compression-default",
+                compression,
+                "SELECT REPLICATE('a', 1000) AS a FROM Movies",
+            ])
+            .assert()
+            .success();
+        out_path
+    };
+
+    let uncompressed = export("uncompressed");
+    let snappy = export("snappy");
+    let size = |path: &Path| std::fs::metadata(path).unwrap().len();
+    assert!(size(&snappy) < size(&uncompressed));
+    assert_eq!(read_parquet(&uncompressed), read_parquet(&snappy));
+}
+
+#[test]
+fn unknown_column_compression() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--column-compression-default",
+            "lzo",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Unknown compression 'lzo'"));
+}
+
+#[test]
+fn batch_memory() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            "--no-write",
+            "--connection-string",
+            MSSQL,
+            "--batch-memory",
+            "10KiB",
+            "out.par",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .success()
+        .stderr(contains("rows fit into --batch-memory of 10.0 KiB."));
+}
+
+#[test]
+fn statement_without_result_set() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "UPDATE Movies SET year = year WHERE title = 'Jurassic Park'",
+        ])
+        .assert()
+        .code(4)
+        .stderr(contains("Statement produced no result set."));
+
+    assert!(!out_path.exists());
+}
+
+#[test]
+fn metrics_file() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let metrics_path = out_dir.path().join("metrics.json");
+    let metrics_str = metrics_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--batch-size",
+            "1",
+            "--metrics-file",
+            metrics_str,
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .success();
+
+    let metrics = std::fs::read_to_string(metrics_path).unwrap();
+    for key in &[
+        "fetch", "convert", "write", "count", "p50", "p90", "p99", "max", "total",
+    ] {
+        assert!(metrics.contains(&format!("\"{}\"", key)), "{}", metrics);
+    }
+    assert!(metrics.contains("\"num_rows_fetched\": 3"));
+    assert!(metrics.contains("\"rows_per_second\""));
+}
+
+/// A failing `--on-file-complete` hook only fails the export, if asked to.
+#[test]
+fn failing_file_complete_hook() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    let query = |extra_args: &[&str]| {
+        let mut cmd = Command::cargo_bin("odbc2parquet").unwrap();
+        cmd.args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--on-file-complete",
+            // Understood by both `sh` and `cmd`.
+            "exit 1",
+            "--overwrite",
+        ])
+        .args(extra_args)
+        .arg("SELECT title FROM Movies ORDER BY year");
+        cmd
+    };
+
+    query(&[]).assert().success();
+    query(&["--hook-failure-aborts"]).assert().failure();
+}
+
+/// Each file is handed to the upload command as soon as it is complete.
+#[test]
+#[cfg(not(windows))]
+fn upload_command() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let upload_dir = tempdir().unwrap();
+    let summary_path = out_dir.path().join("summary.json");
+    let upload_command = format!("cp {{}} '{}'", upload_dir.path().display());
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_path.to_str().unwrap(),
+            "--connection-string",
+            MSSQL,
+            "--batch-size",
+            "1",
+            "--batches-per-file",
+            "1",
+            "--upload-command",
+            &upload_command,
+            "--remove-after-upload",
+            "--summary-file",
+            summary_path.to_str().unwrap(),
+            "SELECT title FROM Movies ORDER BY year",
+        ])
+        .assert()
+        .success();
+
+    for name in ["out_01.par", "out_02.par", "out_03.par"] {
+        assert!(upload_dir.path().join(name).exists());
+        assert!(!out_dir.path().join(name).exists());
+    }
+    let summary = std::fs::read_to_string(summary_path).unwrap();
+    assert_eq!(3, summary.matches("\"removed\": true").count());
+
+    // A failing upload fails the export with a dedicated exit code.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_path.to_str().unwrap(),
+            "--connection-string",
+            MSSQL,
+            "--upload-command",
+            "exit 1",
+            "SELECT title FROM Movies ORDER BY year",
+        ])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn output_base64() {
+    // Output path is ignored, so we do not need a temporary directory.
+    let query = |extra_args: &[&str]| {
+        let mut cmd = Command::cargo_bin("odbc2parquet").unwrap();
+        cmd.args([
+            "-vvvv",
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--output-base64",
+        ])
+        .args(extra_args)
+        .arg("SELECT title FROM Movies ORDER BY year");
+        cmd
+    };
+
+    // Base64 encoding of the parquet magic bytes `PAR1`.
+    query(&[]).assert().success().stdout(starts_with("UEFSMQ"));
+
+    query(&["--max-inline-size", "10"]).assert().failure();
+    assert!(!Path::new("out.par").exists());
+}
+
+#[test]
+fn output_to_stdout() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+
+    // Verbose, so log output would end up in the file if it is not written to stderr.
+    let output = Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            "-",
+            "--connection-string",
+            MSSQL,
+            "SELECT title FROM Movies ORDER BY year",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+    assert!(output.stdout.starts_with(b"PAR1"));
+    assert!(output.stdout.ends_with(b"PAR1"));
+    assert!(!output.stderr.is_empty());
+    assert!(!Path::new("-").exists());
+
+    std::fs::write(&out_path, &output.stdout).unwrap();
+    Command::new("parquet-read")
+        .arg(out_path.to_str().unwrap())
+        .assert()
+        .success()
+        .stdout(contains("Interstellar"));
+}
+
+#[test]
+fn split_stdout() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "-",
+            "--connection-string",
+            MSSQL,
+            "--batches-per-file",
+            "1",
+            "SELECT title FROM Movies",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Writing to standard out (`-`) can not be combined with --batches-per-file",
+        ));
+}
+
+#[test]
+fn split_timestamp() {
+    // Time of day is printed as microseconds since midnight
+    let expected = "{my_timestamp_date: 2020-09-16 +00:00, my_timestamp_time: 14052000000}\n";
+
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--split-timestamp",
+            "my_timestamp",
+            "SELECT my_timestamp FROM AllTheTypes",
+        ])
+        .assert()
+        .success();
+
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str).assert().success().stdout(eq(expected));
+}
+
+#[test]
+fn time_precisions() {
+    // Times are printed as milliseconds or microseconds since midnight. SQL Server rounds to the
+    // precision of the column, we truncate beyond microseconds.
+    let expected = "{t0: 50732000, t3: 50732123, t6: 50732123457, t7: 50732123456}\n";
+
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    let query = "SELECT \
+        CAST('14:05:32.1234567' AS TIME(0)) AS t0, \
+        CAST('14:05:32.1234567' AS TIME(3)) AS t3, \
+        CAST('14:05:32.1234567' AS TIME(6)) AS t6, \
+        CAST('14:05:32.1234567' AS TIME(7)) AS t7";
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            query,
+        ])
+        .assert()
+        .success();
+
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str).assert().success().stdout(eq(expected));
+
+    // Overriding the precision reported by the driver
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--explain-mapping",
+            "json",
+            "--time-precision",
+            "t7=3",
+            query,
+        ])
+        .assert()
+        .success()
+        .stdout(contains("\"time_precision\": 3"))
+        .stdout(contains("\"logical_type\": \"TIME_MILLIS\""));
+}
+
+#[test]
+fn binary_columns() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    // An MD5 hash has a fixed size of 16 bytes.
+    let query = "SELECT \
+        CAST(HASHBYTES('MD5', 'odbc2parquet') AS BINARY(16)) AS md5, \
+        CAST(0x6F006462 AS VARBINARY(256)) AS data";
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--explain-mapping",
+            "json",
+            query,
+        ])
+        .assert()
+        .success()
+        .stdout(contains("\"physical_type\": \"FIXED_LEN_BYTE_ARRAY\""))
+        .stdout(contains("\"length\": 16"))
+        .stdout(contains("\"physical_type\": \"BYTE_ARRAY\""));
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--overwrite",
+            query,
+        ])
+        .assert()
+        .success();
+}
+
+#[test]
+fn largest_date() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "SELECT CAST('9999-12-31' AS DATE) AS d",
+        ])
+        .assert()
+        .success();
+
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str)
+        .assert()
+        .success()
+        .stdout(eq("{d: 9999-12-31 +00:00}\n"));
+}
+
+#[test]
+fn null_default() {
+    let expected = "\
+        {title: \"Interstellar\", year: 0}\n\
+        {title: \"2001: A Space Odyssey\", year: 1968}\n\
+        {title: \"Jurassic Park\", year: 1993}\n\
+    ";
+
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let summary_path = out_dir.path().join("summary.json");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--null-default",
+            "year=0",
+            "--summary-file",
+            summary_path.to_str().unwrap(),
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .success();
+
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str).assert().success().stdout(eq(expected));
+
+    let summary = std::fs::read_to_string(&summary_path).unwrap();
+    assert!(summary.contains("\"year\": 1"));
+}
+
+#[test]
+fn null_default_of_wrong_type() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--null-default",
+            "year=unknown",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Invalid value 'unknown' specified in --null-default",
+        ));
+}
+
+#[test]
+fn dedupe_on() {
+    let expected = "\
+        {title: \"2001: A Space Odyssey\"}\n\
+        {title: \"Interstellar\"}\n\
+        {title: \"Jurassic Park\"}\n\
+    ";
+
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let summary_path = out_dir.path().join("summary.json");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--dedupe-on",
+            "title",
+            "--summary-file",
+            summary_path.to_str().unwrap(),
+            "SELECT title FROM Movies UNION ALL SELECT title FROM Movies ORDER BY title",
+        ])
+        .assert()
+        .success();
+
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str).assert().success().stdout(eq(expected));
+
+    let summary = std::fs::read_to_string(&summary_path).unwrap();
+    assert!(summary.contains("\"num_duplicates_dropped\": 3"));
+}
+
+#[test]
+fn single_file_without_suffix() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    // All rows fit into one batch, so no split happens.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--batches-per-file",
+            "1",
+            "SELECT title FROM Movies ORDER BY year",
+        ])
+        .assert()
+        .success();
+
+    assert!(out_path.exists());
+    assert!(!out_dir.path().join("out_01.par").exists());
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--batches-per-file",
+            "1",
+            "--always-suffix",
+            "SELECT title FROM Movies ORDER BY year",
+        ])
+        .assert()
+        .success();
+
+    assert!(out_dir.path().join("out_01.par").exists());
+}
+
+/// Without `--no-empty-file` an empty result set yields a file with the schema, but no rows.
+#[test]
+fn empty_file() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--batches-per-file",
+            "1",
+            "SELECT title FROM Movies WHERE year > 3000",
+        ])
+        .assert()
+        .success();
+
+    assert_eq!("", read_parquet(&out_path));
+    Command::new("parquet-schema")
+        .arg(out_str)
+        .assert()
+        .success()
+        .stdout(contains("BYTE_ARRAY title"));
+}
+
+#[test]
+fn no_empty_file() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--batches-per-file",
+            "1",
+            "--no-empty-file",
+            "SELECT title FROM Movies WHERE year > 3000",
+        ])
+        .assert()
+        .code(6);
+
+    assert!(!out_path.exists());
+    assert!(!out_dir.path().join("out_01.par").exists());
+}
+
+#[test]
+fn log_column_mapping() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "SELECT my_integer, my_decimal FROM AllTheTypes",
+        ])
+        .assert()
+        .success()
+        .stderr(contains(
+            "Column 1 'my_integer': SQL Integer (size 0, decimal digits 0, NoNulls) -> ODBC \
+            buffer I32 (12 bytes per row) -> parquet INT32 INT_32",
+        ))
+        .stderr(contains(
+            "Column 2 'my_decimal': SQL Decimal { precision: 3, scale: 2 } (size 3, decimal \
+            digits 2, NoNulls) -> ODBC buffer Text { max_str_len: 3 } (12 bytes per row) -> \
+            parquet FIXED_LEN_BYTE_ARRAY DECIMAL length 2 precision 3 scale 2",
+        ));
+}
+
+#[test]
+fn row_group_memory_limit() {
+    let expected = "\
+        {title: \"Interstellar\", year: null}\n\
+        {title: \"2001: A Space Odyssey\", year: 1968}\n\
+        {title: \"Jurassic Park\", year: 1993}\n\
+    ";
+
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let summary_path = out_dir.path().join("summary.json");
+
+    // Each row exceeds the limit on its own, so every row ends up in a row group of its own.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--row-group-memory-limit",
+            "1",
+            "--summary-file",
+            summary_path.to_str().unwrap(),
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .success();
+
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str).assert().success().stdout(eq(expected));
+
+    let summary = std::fs::read_to_string(&summary_path).unwrap();
+    assert_eq!(3, summary.matches("\"num_rows\": 1").count());
+}
+
+#[test]
+fn buffers_exceeding_memory_limit() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_path.to_str().unwrap(),
+            "--connection-string",
+            MSSQL,
+            "--buffer-memory-limit",
+            "1MiB",
+            "SELECT title,year from Movies order by year",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Largest columns: 'title'"));
+    assert!(!out_path.exists());
+}
+
+#[test]
+fn row_groups_of_batches() {
+    let out_dir = tempdir().unwrap();
+    let summary_path = out_dir.path().join("summary.json");
+
+    // Three rows fetched one at a time. Returns the number of rows in each row group.
+    let row_groups = |mode: &[&str]| {
+        let out_path = out_dir.path().join("out.par");
+        Command::cargo_bin("odbc2parquet")
+            .unwrap()
+            .args([
+                "-vvvv",
+                "query",
+                out_path.to_str().unwrap(),
+                "--connection-string",
+                MSSQL,
+                "--batch-size",
+                "1",
+                "--summary-file",
+                summary_path.to_str().unwrap(),
+            ])
+            .args(mode)
+            .arg("SELECT title,year from Movies order by year")
+            .assert()
+            .success();
+        let summary = std::fs::read_to_string(&summary_path).unwrap();
+        (
+            summary.matches("\"num_rows\": 1").count(),
+            summary.matches("\"num_rows\": 2").count(),
+        )
+    };
+
+    assert_eq!((3, 0), row_groups(&["--row-group-per-batch"]));
+    assert_eq!((1, 1), row_groups(&["--rows-per-row-group", "2"]));
+}
+
+#[test]
+fn nullable_bit() {
+    let expected = "\
+        {flag: null}\n\
+        {flag: true}\n\
+        {flag: false}\n\
+    ";
+
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "SELECT CAST(flag AS BIT) AS flag \
+            FROM (VALUES (1, NULL), (2, 1), (3, 0)) AS t(id, flag) ORDER BY id",
+        ])
+        .assert()
+        .success();
+
+    let mut cmd = Command::new("parquet-read");
+    cmd.arg(out_str).assert().success().stdout(eq(expected));
+}
+
+/// Not a correctness test, but a micro benchmark for wide tables consisting mostly of flags. Run
+/// with `cargo test --release -- --ignored --nocapture many_bit_columns`.
+#[test]
+#[ignore]
+fn many_bit_columns() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    // `ISNULL` tells the driver the columns can not be NULL, so the fast path is taken.
+    let columns: Vec<_> = (1..=50)
+        .map(|i| format!("ISNULL(CAST((n + {}) % 2 AS BIT), 0) AS flag_{}", i, i))
+        .collect();
+    let query = format!(
+        "SELECT {} FROM (SELECT TOP 1000000 ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) AS n \
+        FROM sys.all_objects a CROSS JOIN sys.all_objects b) AS t",
+        columns.join(", ")
+    );
+
+    let start = std::time::Instant::now();
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args(["query", out_str, "--connection-string", MSSQL, &query])
+        .assert()
+        .success();
+    println!("Exported 50 bit columns in {:?}.", start.elapsed());
+}
+
+#[test]
+fn explain_mapping() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--explain-mapping",
+            "json",
+            "--mask",
+            "my_varchar=sha256",
+            "SELECT my_integer, my_float, my_varchar FROM AllTheTypes",
+        ])
+        .assert()
+        .success()
+        .stdout(starts_with("{\n  \"version\": 1,\n  \"columns\": [\n"))
+        .stdout(contains("\"physical_type\": \"INT32\""))
+        .stdout(contains("SQL FLOAT may have double precision"))
+        .stdout(contains("\"mask\": \"sha256\""));
+
+    // Only the mapping is explained, no rows are fetched.
+    assert!(!out_path.exists());
+}
+
+/// Not a correctness test, but a micro benchmark for the conversion of timestamps. Run with
+/// `cargo test --release -- --ignored --nocapture many_timestamps`.
+#[test]
+#[ignore]
+fn many_timestamps() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    let query = "SELECT DATEADD(SECOND, n, CAST('1900-01-01' AS DATETIME2)) AS ts FROM \
+        (SELECT TOP 10000000 ROW_NUMBER() OVER (ORDER BY (SELECT NULL)) AS n \
+        FROM sys.all_objects a CROSS JOIN sys.all_objects b CROSS JOIN sys.all_objects c) AS t";
+
+    let start = std::time::Instant::now();
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args(["query", out_str, "--connection-string", MSSQL, query])
+        .assert()
+        .success();
+    println!("Exported 10M timestamps in {:?}.", start.elapsed());
+}
+
+#[test]
+fn field_ids() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--explain-mapping",
+            "json",
+            "--field-id",
+            "year=7",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .success()
+        .stdout(contains("\"field_id\": 7"));
+}
+
+#[test]
+fn duplicate_field_ids() {
+    // Validated before connecting to the data source.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--field-id",
+            "title=1",
+            "--field-id",
+            "year=1",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Field id 1 is assigned to both 'title' and 'year'.",
+        ));
+}
+
+#[test]
+fn field_id_must_be_positive() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--field-id",
+            "title=0",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Field id of column 'title' must be a positive integer.",
+        ));
+}
+
+#[test]
+fn skip_unsupported_columns_without_unsupported_columns() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+    let summary_path = out_dir.path().join("summary.json");
+
+    // Nothing to skip, so the export succeeds with the regular exit code.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vvvv",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--skip-unsupported-columns",
+            "--summary-file",
+            summary_path.to_str().unwrap(),
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .code(0);
+
+    let summary = std::fs::read_to_string(&summary_path).unwrap();
+    assert!(!summary.contains("skipped_columns"));
+}
+
+#[test]
+fn strict() {
+    // Query, additional arguments and the error expected with `--strict`.
+    let lossy = [
+        (
+            "SELECT CAST(1.5 AS FLOAT) AS a",
+            &[][..],
+            "Column 'a' violates rule 'float-precision'",
+        ),
+        (
+            "SELECT CAST('2021-03-04 12:34:56.1234567' AS DATETIME2(7)) AS a",
+            &[][..],
+            "Column 'a' violates rule 'timestamp-precision'",
+        ),
+        (
+            "SELECT CAST('12:34:56' AS TIME) AS a",
+            &[][..],
+            "Column 'a' violates rule 'timestamp-precision'",
+        ),
+        (
+            "SELECT CAST('2021-03-04 12:34:56 +01:00' AS DATETIMEOFFSET) AS a",
+            &[][..],
+            "Column 'a' violates rule 'fallback-to-text'",
+        ),
+        (
+            "SELECT title AS a FROM Movies",
+            &["--dedupe-on", "a", "--dedupe-max-keys", "1"][..],
+            "Key 'a' in batch 1 violates rule 'approximate-deduplication'",
+        ),
+    ];
+
+    for (query, args, expected) in lossy.iter() {
+        let out_dir = tempdir().unwrap();
+        let out_path = out_dir.path().join("out.par");
+        let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+        // Lossy exports succeed by default ...
+        Command::cargo_bin("odbc2parquet")
+            .unwrap()
+            .args(["query", out_str, "--connection-string", MSSQL])
+            .args(args.iter())
+            .arg(query)
+            .assert()
+            .success();
+
+        // ... but fail with `--strict`.
+        Command::cargo_bin("odbc2parquet")
+            .unwrap()
+            .args([
+                "query",
+                out_str,
+                "--connection-string",
+                MSSQL,
+                "--strict",
+                "--overwrite",
+            ])
+            .args(args.iter())
+            .arg(query)
+            .assert()
+            .failure()
+            .stderr(contains(*expected));
+    }
+}
+
+#[test]
+fn strict_lossless_export() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--strict",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .success();
+}
+
+#[test]
+fn strict_conflicts_with_null_on_conversion_error() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--strict",
+            "--on-conversion-error",
+            "null",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "--strict can not be combined with `--on-conversion-error null`.",
+        ));
+}
+
+#[test]
+fn report_all_invalid_option_combinations() {
+    // Connecting would fail, but the options are rejected before trying.
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            "foobar",
+            "--always-suffix",
+            "--no-write",
+            "--row-group-per-batch",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "--always-suffix has no effect without --batches-per-file or --file-size-threshold.",
+        ))
+        .stderr(contains("since no file is written"))
+        .stderr(contains("since no row group is written"))
+        .stderr(contains("Data source name not found").not());
+}
+
+#[test]
+fn repeated_diagnostics_are_logged_once() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    // Each PRINT causes an informational diagnostic record on execution.
+    let assert = Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-v",
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "PRINT 'Hello'; PRINT 'Hello'; PRINT 'Hello'; SELECT title FROM Movies",
+        ])
+        .assert()
+        .success()
+        .stderr(contains(
+            "Diagnostic record occurred 3 times in total: State: 01000",
+        ));
+
+    // Logged once as it occurs and once more with the count.
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert_eq!(2, stderr.matches("[SQL Server]Hello").count());
+}
+
+#[test]
+fn estimate() {
+    let out_dir = tempdir().unwrap();
+    let out_path = out_dir.path().join("out.par");
+    let out_str = out_path.to_str().expect("Tempfile path must be utf8");
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            out_str,
+            "--connection-string",
+            MSSQL,
+            "--estimate",
+            "--yes",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .success()
+        .stdout(contains("Estimated number of rows: 3\n"));
+
+    // The export runs after the estimate.
+    assert!(out_path.exists());
+}
+
+#[test]
+fn yes_requires_estimate() {
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "query",
+            "out.par",
+            "--connection-string",
+            MSSQL,
+            "--yes",
+            "SELECT title, year FROM Movies",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("--estimate"));
+}
+
+#[test]
+fn filter() {
+    let dir = tempdir().unwrap();
+    let in_path = dir.path().join("in.par");
+    let out_path = dir.path().join("out.par");
+    // Two row groups, so the first one can be skipped based on its statistics.
+    write_parquet(
+        &in_path,
+        &[
+            &[(1, Some("one")), (5, None), (9, Some("nine"))],
+            &[(10, Some("ten")), (15, None), (20, Some("twenty"))],
+        ],
+    );
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "-vv",
+            "filter",
+            in_path.to_str().unwrap(),
+            out_path.to_str().unwrap(),
+            "--where",
+            "a >= 10 AND a < 20 AND b IS NOT NULL",
+        ])
+        .assert()
+        .success()
+        .stderr(contains("Skipped 1 of 2 row groups"))
+        .stderr(contains("Wrote 1 of 6 rows."));
+
+    assert_eq!("{a: 10, b: \"ten\"}\n", read_parquet(&out_path));
+}
+
+#[test]
+fn filter_only_nulls() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::fs::File;
+
+    let dir = tempdir().unwrap();
+    let in_path = dir.path().join("in.par");
+    let out_path = dir.path().join("out.par");
+    write_parquet(&in_path, &[&[(1, Some("one")), (5, None), (15, None)]]);
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "filter",
+            in_path.to_str().unwrap(),
+            out_path.to_str().unwrap(),
+            "--where",
+            "b IS NULL",
+        ])
+        .assert()
+        .success();
+
+    // The column chunk holds nothing but NULLs, which must still be counted in its statistics.
+    let reader = SerializedFileReader::new(File::open(&out_path).unwrap()).unwrap();
+    let statistics = reader
+        .metadata()
+        .row_group(0)
+        .column(1)
+        .statistics()
+        .unwrap();
+    assert_eq!(2, statistics.null_count());
+}
+
+#[test]
+fn filter_unknown_column() {
+    let dir = tempdir().unwrap();
+    let in_path = dir.path().join("in.par");
+    write_parquet(&in_path, &[&[(1, Some("one"))]]);
+
+    Command::cargo_bin("odbc2parquet")
+        .unwrap()
+        .args([
+            "filter",
+            in_path.to_str().unwrap(),
+            dir.path().join("out.par").to_str().unwrap(),
+            "--where",
+            "c = 1",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("Unknown column 'c'."));
+}
+
+/// Writes a parquet file with a required integer column `a` and a nullable text column `b`. One
+/// row group per slice of rows.
+fn write_parquet(path: &Path, row_groups: &[&[(i32, Option<&str>)]]) {
+    use parquet::{
+        column::writer::ColumnWriter,
+        data_type::ByteArray,
+        file::{
+            properties::WriterProperties,
+            writer::{FileWriter, SerializedFileWriter},
+        },
+        schema::parser::parse_message_type,
+    };
+    use std::{fs::File, sync::Arc};
+
+    let schema =
+        parse_message_type("message schema { REQUIRED INT32 a; OPTIONAL BYTE_ARRAY b (UTF8); }")
+            .unwrap();
+    let properties = Arc::new(WriterProperties::builder().build());
+    let file = File::create(path).unwrap();
+    let mut writer = SerializedFileWriter::new(file, Arc::new(schema), properties).unwrap();
+    for rows in row_groups {
+        let mut row_group_writer = writer.next_row_group().unwrap();
+        let mut column_writer = row_group_writer.next_column().unwrap().unwrap();
+        if let ColumnWriter::Int32ColumnWriter(cw) = &mut column_writer {
+            let values: Vec<i32> = rows.iter().map(|(a, _)| *a).collect();
+            cw.write_batch(&values, None, None).unwrap();
+        }
+        row_group_writer.close_column(column_writer).unwrap();
+        let mut column_writer = row_group_writer.next_column().unwrap().unwrap();
+        if let ColumnWriter::ByteArrayColumnWriter(cw) = &mut column_writer {
+            let values: Vec<ByteArray> = rows
+                .iter()
+                .filter_map(|(_, b)| *b)
+                .map(ByteArray::from)
+                .collect();
+            let def_levels: Vec<i16> = rows.iter().map(|(_, b)| b.is_some() as i16).collect();
+            cw.write_batch(&values, Some(&def_levels), None).unwrap();
+        }
+        row_group_writer.close_column(column_writer).unwrap();
+        writer.close_row_group(row_group_writer).unwrap();
+    }
+    writer.close().unwrap();
+}
+
+/// Content of a parquet file, one row per line.
+/// Key value pairs in the footer of the parquet file at `path`.
+fn key_value_metadata(path: &Path) -> Vec<(String, String)> {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::fs::File;
+
+    let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+    reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .iter()
+        .flatten()
+        .map(|entry| (entry.key.clone(), entry.value.clone().unwrap_or_default()))
+        .collect()
+}
+
+fn read_parquet(path: &Path) -> String {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::fs::File;
+
+    let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+    reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|row| format!("{}\n", row))
+        .collect()
+}